@@ -0,0 +1,164 @@
+use wgpu::util::DeviceExt;
+use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
+
+use crate::camera::CameraModel;
+use crate::ground_grid::GridVertex;
+
+// Side of the gizmo's square in logical pixels
+const SIZE: f32 = 80.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoUniform {
+    // The camera's view matrix without the translation
+    view_rotation: [[f32; 4]; 4],
+}
+
+// X, Y and Z axes as red, green and blue lines from the origin, turned with the camera
+// and drawn over the finished frame in a small square at one of its corners
+pub struct AxisGizmo {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl AxisGizmo {
+    pub fn new(device: &Device, target_texture_format: TextureFormat) -> Self {
+        let vertices = [
+            ([1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 1.0]),
+            ([0.0, 1.0, 0.0], [0.0, 1.0, 0.0, 1.0]),
+            ([0.0, 0.0, 1.0], [0.0, 0.3, 1.0, 1.0]),
+        ]
+            .into_iter()
+            .flat_map(|(position, color)| [GridVertex { position: [0.0; 3], color }, GridVertex { position, color }])
+            .collect::<Vec<_>>();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Axis Gizmo Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Axis Gizmo Buffer"),
+            contents: bytemuck::cast_slice(&[GizmoUniform { view_rotation: [[0.0; 4]; 4] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("axis_gizmo_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("axis_gizmo_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Axis Gizmo Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/axis_gizmo.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Axis Gizmo Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Axis Gizmo Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[GridVertex::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_texture_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Always on top of the scene
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn update(&self, queue: &Queue, camera: &CameraModel) {
+        let uniform = GizmoUniform { view_rotation: camera.view_rotation().into() };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Draws in its own pass on top of what `view` already holds, `size` is the view's size in pixels
+    pub fn render(&self,
+                  view: &TextureView,
+                  encoder: &mut CommandEncoder,
+                  size: winit::dpi::PhysicalSize<u32>,
+                  scale_factor: f64,
+                  corner: Corner) {
+        let side = (SIZE * scale_factor as f32).min(size.width as f32).min(size.height as f32);
+        let x = match corner {
+            Corner::TopLeft | Corner::BottomLeft => 0.0,
+            Corner::TopRight | Corner::BottomRight => size.width as f32 - side,
+        };
+        let y = match corner {
+            Corner::TopLeft | Corner::TopRight => 0.0,
+            Corner::BottomLeft | Corner::BottomRight => size.height as f32 - side,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Axis Gizmo Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_viewport(x, y, side, side, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..6, 0..1);
+    }
+}
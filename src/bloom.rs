@@ -0,0 +1,279 @@
+use wgpu::util::DeviceExt;
+use wgpu::{CommandEncoder, Device, Queue, TextureView};
+
+use crate::tonemap::ToneMapper;
+
+// Horizontal plus vertical blur passes, more spread the glow wider
+const BLUR_PASSES: usize = 2;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    threshold: f32,
+    intensity: f32,
+    // Uniform buffers are at least 16 bytes on WebGL
+    _padding: [f32; 2],
+}
+
+struct Pipelines {
+    bright: wgpu::RenderPipeline,
+    blur_horizontal: wgpu::RenderPipeline,
+    blur_vertical: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+}
+
+// Half resolution textures the bright pass and the blur ping-pong between,
+// recreated with the scene's size
+struct Targets {
+    views: [TextureView; 2],
+    // Reading the scene, the first and the second texture
+    bind_groups: [wgpu::BindGroup; 3],
+}
+
+// Glow around the parts of the HDR scene above a threshold: they are extracted into a
+// half resolution texture, blurred and added back onto the scene before tone mapping
+pub struct Bloom {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    params_buffer: wgpu::Buffer,
+    pipelines: Pipelines,
+    targets: Targets,
+    threshold: f32,
+    intensity: f32,
+}
+
+impl Bloom {
+    pub const DEFAULT_THRESHOLD: f32 = 1.0;
+    pub const DEFAULT_INTENSITY: f32 = 0.5;
+
+    // `hdr_view` is the scene's HDR target, see ToneMapper::hdr_view
+    pub fn new(device: &Device, hdr_view: &TextureView, width: u32, height: u32) -> Self {
+        let threshold = Self::DEFAULT_THRESHOLD;
+        let intensity = Self::DEFAULT_INTENSITY;
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Params Buffer"),
+            contents: bytemuck::cast_slice(&[Self::params(threshold, intensity)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        // Linear filtering downsamples in the bright pass and halves the blur's samples
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let pipelines = Self::create_pipelines(device, &layout);
+        let targets = Self::create_targets(device, &layout, &sampler, &params_buffer, hdr_view, width, height);
+        Self {
+            layout,
+            sampler,
+            params_buffer,
+            pipelines,
+            targets,
+            threshold,
+            intensity,
+        }
+    }
+
+    fn params(threshold: f32, intensity: f32) -> ParamsUniform {
+        ParamsUniform {
+            threshold,
+            intensity,
+            _padding: [0.0; 2],
+        }
+    }
+
+    fn create_pipelines(device: &Device, layout: &wgpu::BindGroupLayout) -> Pipelines {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/bloom.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        let create = |label: &str, entry_point: &str, blend: wgpu::BlendState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: ToneMapper::HDR_FORMAT,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            // The scene's alpha is kept
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        Pipelines {
+            bright: create("Bloom Bright Pipeline", "fs_bright", wgpu::BlendState::REPLACE),
+            blur_horizontal: create("Bloom Horizontal Blur Pipeline", "fs_blur_horizontal", wgpu::BlendState::REPLACE),
+            blur_vertical: create("Bloom Vertical Blur Pipeline", "fs_blur_vertical", wgpu::BlendState::REPLACE),
+            composite: create("Bloom Composite Pipeline", "fs_composite", additive),
+        }
+    }
+
+    fn create_targets(device: &Device,
+                      layout: &wgpu::BindGroupLayout,
+                      sampler: &wgpu::Sampler,
+                      params_buffer: &wgpu::Buffer,
+                      hdr_view: &TextureView,
+                      width: u32,
+                      height: u32) -> Targets {
+        let create_view = || {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("bloom_texture"),
+                size: wgpu::Extent3d {
+                    width: width.div_ceil(2),
+                    height: height.div_ceil(2),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: ToneMapper::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let views = [create_view(), create_view()];
+        let create_bind_group = |view: &TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("bloom_bind_group"),
+            })
+        };
+        let bind_groups = [create_bind_group(hdr_view), create_bind_group(&views[0]), create_bind_group(&views[1])];
+        Targets { views, bind_groups }
+    }
+
+    // Call with the recreated HDR target after a resize
+    pub fn resize(&mut self, device: &Device, hdr_view: &TextureView, width: u32, height: u32) {
+        self.targets = Self::create_targets(device, &self.layout, &self.sampler, &self.params_buffer, hdr_view, width, height);
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn set_params(&mut self, queue: &Queue, threshold: f32, intensity: f32) {
+        self.threshold = threshold;
+        self.intensity = intensity;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[Self::params(threshold, intensity)]));
+    }
+
+    // Adds the bloom onto `hdr_view`, which has to be the view the targets were created with
+    pub fn render(&self, encoder: &mut CommandEncoder, hdr_view: &TextureView) {
+        let [first, second] = &self.targets.views;
+        let [scene_bind_group, first_bind_group, second_bind_group] = &self.targets.bind_groups;
+        Self::run(encoder, "Bloom Bright Pass", first, &self.pipelines.bright, scene_bind_group, wgpu::LoadOp::Clear(wgpu::Color::BLACK));
+        for _ in 0..BLUR_PASSES {
+            Self::run(encoder, "Bloom Blur Pass", second, &self.pipelines.blur_horizontal, first_bind_group, wgpu::LoadOp::Clear(wgpu::Color::BLACK));
+            Self::run(encoder, "Bloom Blur Pass", first, &self.pipelines.blur_vertical, second_bind_group, wgpu::LoadOp::Clear(wgpu::Color::BLACK));
+        }
+        Self::run(encoder, "Bloom Composite Pass", hdr_view, &self.pipelines.composite, first_bind_group, wgpu::LoadOp::Load);
+    }
+
+    fn run(encoder: &mut CommandEncoder,
+           label: &str,
+           target: &TextureView,
+           pipeline: &wgpu::RenderPipeline,
+           bind_group: &wgpu::BindGroup,
+           load: wgpu::LoadOp<wgpu::Color>) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,82 @@
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, Queue, TextureFormat};
+
+use crate::ground_grid::{GridVertex, GroundGrid};
+
+const COLOR: [f32; 4] = [1.0, 0.8, 0.0, 1.0];
+// 12 edges with 2 vertices each
+const NUM_VERTICES: u32 = 24;
+
+// Wireframe of an axis-aligned box, drawn like the ground grid
+pub struct BoundsBox {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    bounds: ([f32; 3], [f32; 3]),
+}
+
+impl BoundsBox {
+    pub fn new(device: &Device,
+               target_texture_format: TextureFormat,
+               sample_count: u32,
+               depth_format: TextureFormat,
+               depth_compare: wgpu::CompareFunction,
+               camera_layout: &BindGroupLayout,
+               bounds: ([f32; 3], [f32; 3])) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bounds Box Vertex Buffer"),
+            contents: bytemuck::cast_slice(&Self::vertices(bounds)),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            pipeline: GroundGrid::create_pipeline(device, target_texture_format, sample_count, depth_format, depth_compare, camera_layout),
+            vertex_buffer,
+            bounds,
+        }
+    }
+
+    fn vertices((min, max): ([f32; 3], [f32; 3])) -> Vec<GridVertex> {
+        let corner = |i: usize| [
+            if i & 1 == 0 { min[0] } else { max[0] },
+            if i & 2 == 0 { min[1] } else { max[1] },
+            if i & 4 == 0 { min[2] } else { max[2] },
+        ];
+        let mut vertices = Vec::with_capacity(NUM_VERTICES as usize);
+        // Corners whose indices differ in a single bit share an edge
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    vertices.push(GridVertex { position: corner(i), color: COLOR });
+                    vertices.push(GridVertex { position: corner(i | bit), color: COLOR });
+                }
+            }
+        }
+        vertices
+    }
+
+    // Only uploads when the box changed
+    pub fn set_bounds(&mut self, queue: &Queue, bounds: ([f32; 3], [f32; 3])) {
+        if bounds != self.bounds {
+            self.bounds = bounds;
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&Self::vertices(bounds)));
+        }
+    }
+
+    // Has to be called when the sample count, the depth format or the depth test of the scene pass changes
+    pub fn rebuild_pipeline(&mut self,
+                            device: &Device,
+                            target_texture_format: TextureFormat,
+                            sample_count: u32,
+                            depth_format: TextureFormat,
+                            depth_compare: wgpu::CompareFunction,
+                            camera_layout: &BindGroupLayout) {
+        self.pipeline = GroundGrid::create_pipeline(device, target_texture_format, sample_count, depth_format, depth_compare, camera_layout);
+    }
+
+    // Draws into a pass that already has the scene's color and depth attachments
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..NUM_VERTICES, 0..1);
+    }
+}
@@ -1,44 +1,243 @@
+use std::time::Duration;
+
+use cgmath::{Deg, Rad};
 use wgpu::util::DeviceExt;
-use winit::event::{WindowEvent, ElementState, KeyEvent};
+use winit::dpi::PhysicalPosition;
+use winit::event::{WindowEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
+// Default degrees of orbit per pixel of cursor movement
+const ORBIT_SENSITIVITY: f32 = 0.3;
+// Default units per second of keyboard movement, 0.2 per frame at 60 Hz
+const MOVE_SPEED: f32 = 12.0;
+// Movement speed while a `fast` key is held, relative to the normal speed
+const FAST_MULTIPLIER: f32 = 4.0;
+// Keeps the camera from flipping over the poles where `up` becomes parallel to the view direction
+const MAX_PITCH: Deg<f32> = Deg(89.0);
+// Touchpads report scrolling in pixels, treat this many of them as one wheel line
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+// Lower bound of `set_zoom_bounds`, the camera can't reach its target
+const MIN_ZOOM_DISTANCE: f32 = 0.01;
+// Range of the field of view a dolly zoom ends at
+const MIN_DOLLY_FOVY: Deg<f32> = Deg(5.0);
+const MAX_DOLLY_FOVY: Deg<f32> = Deg(150.0);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective { fovy: Deg<f32> },
+    // Height of the visible area in world units, the width follows from the aspect ratio
+    Orthographic { height: f32 },
+}
+
+// How the controller moves the camera, both modes move the same eye and target
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CameraMode {
+    // Dragging and the keys orbit around the target, the wheel zooms towards it
+    #[default]
+    Orbit,
+    // Mouse movement turns the view in place, the keys move eye and target together
+    Fly,
+}
+
+// Where the camera is and what it looks at, e.g. for saving and restoring views
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Viewpoint {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+}
+
+// Moves the eye linearly and slerps the view direction between two viewpoints
+struct CameraAnimation {
+    from: Viewpoint,
+    to: Viewpoint,
+    duration: Duration,
+    elapsed: Duration,
+}
+
+impl CameraAnimation {
+    // Viewpoint after advancing by dt, None once the animation is over
+    fn advance(&mut self, dt: Duration) -> Option<Viewpoint> {
+        use cgmath::{EuclideanSpace, InnerSpace, One, Quaternion, Rotation, VectorSpace};
+        if self.elapsed >= self.duration {
+            return None;
+        }
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+        let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+
+        let from_forward = self.from.target - self.from.eye;
+        let to_forward = self.to.target - self.to.eye;
+        let rotation = Quaternion::from_arc(from_forward.normalize(), to_forward.normalize(), None);
+        let direction = Quaternion::one().slerp(rotation, t).rotate_vector(from_forward.normalize());
+        let distance = from_forward.magnitude() + (to_forward.magnitude() - from_forward.magnitude()) * t;
+
+        let eye = cgmath::Point3::from_vec(self.from.eye.to_vec().lerp(self.to.eye.to_vec(), t));
+        Some(Viewpoint { eye, target: eye + direction * distance })
+    }
+}
+
+// Sweeps the field of view while moving the eye along the view direction, keeping
+// the height of the view at the target, so the subject there stays the same size
+struct DollyZoom {
+    from_fovy: Deg<f32>,
+    to_fovy: Deg<f32>,
+    // Height of the view at the target
+    frame_height: f32,
+    duration: Duration,
+    elapsed: Duration,
+}
+
+impl DollyZoom {
+    // Field of view and eye distance after advancing by dt, None once the animation is over
+    fn advance(&mut self, dt: Duration) -> Option<(Deg<f32>, f32)> {
+        use cgmath::Angle;
+        if self.elapsed >= self.duration {
+            return None;
+        }
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+        let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        let fovy = self.from_fovy + (self.to_fovy - self.from_fovy) * t;
+        Some((fovy, self.frame_height / (2.0 * (fovy / 2.0).tan())))
+    }
+}
+
+enum Animation {
+    Move(CameraAnimation),
+    DollyZoom(DollyZoom),
+}
+
 pub struct CameraModel {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
     pub up: cgmath::Vector3<f32>,
     pub aspect: f32,
-    pub fovy: f32,
+    pub projection: Projection,
     pub znear: f32,
     pub zfar: f32,
+    // Maps the near plane to depth 1.0 and the far plane to 0.0
+    pub reverse_z: bool,
 }
 
 impl CameraModel {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        // 1.
+    pub(crate) fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        // 2.
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        // 3.
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+        self.build_projection_matrix() * view
+    }
+
+    // From view space to wgpu's clip space, with the depth reversed when reverse_z is set
+    pub(crate) fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let proj = match self.projection {
+            Projection::Perspective { fovy } => OPENGL_TO_WGPU_MATRIX * cgmath::perspective(fovy, self.aspect, self.znear, self.zfar),
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                ORTHO_OPENGL_TO_WGPU_MATRIX * cgmath::ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            }
+        };
+        if self.reverse_z {
+            REVERSE_Z_MATRIX * proj
+        } else {
+            proj
+        }
+    }
+
+    // Maps (x, y, 1, 0) with x and y in NDC to the world space direction of the view
+    // ray through that point. The camera position is left out, so the sky only turns
+    // with the camera.
+    fn build_sky_rays_matrix(&self) -> cgmath::Matrix4<f32> {
+        use cgmath::{Angle, Matrix};
+        let tan_half_fovy = match self.projection {
+            Projection::Perspective { fovy } => (fovy / 2.0).tan(),
+            // Parallel rays would sample a single texel, pretend to have a usual field of view
+            Projection::Orthographic { .. } => Deg(22.5f32).tan(),
+        };
+        let view = self.view_rotation();
+        // A pure rotation, so the transpose is the inverse
+        view.transpose() * cgmath::Matrix4::from_nonuniform_scale(tan_half_fovy * self.aspect, tan_half_fovy, -1.0)
+    }
+
+    // View matrix of a camera at the origin looking the same way
+    pub(crate) fn view_rotation(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_to_rh(cgmath::Point3::new(0.0, 0.0, 0.0), self.target - self.eye, self.up)
+    }
+
+    // Direction of the eye as seen from the target, yaw is measured from +z towards +x
+    fn yaw_pitch(&self) -> (Rad<f32>, Rad<f32>) {
+        direction_yaw_pitch(self.eye - self.target)
+    }
+
+    // Moves the eye around the target keeping its distance, pitch is clamped to MAX_PITCH
+    fn set_yaw_pitch(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        use cgmath::InnerSpace;
+        let radius = (self.eye - self.target).magnitude();
+        self.eye = self.target + direction(yaw, pitch) * radius;
+    }
+
+    // Like `yaw_pitch` for the view direction, from the eye to the target
+    fn look_yaw_pitch(&self) -> (Rad<f32>, Rad<f32>) {
+        direction_yaw_pitch(self.target - self.eye)
+    }
+
+    // Turns the target around the eye keeping its distance, pitch is clamped to MAX_PITCH
+    fn set_look_yaw_pitch(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        use cgmath::InnerSpace;
+        let radius = (self.target - self.eye).magnitude();
+        self.target = self.eye + direction(yaw, pitch) * radius;
     }
 }
 
+fn direction_yaw_pitch(direction: cgmath::Vector3<f32>) -> (Rad<f32>, Rad<f32>) {
+    use cgmath::InnerSpace;
+    (Rad(direction.x.atan2(direction.z)), Rad((direction.y / direction.magnitude()).asin()))
+}
+
+// Unit vector for the angles of `direction_yaw_pitch`, with the pitch clamped to MAX_PITCH
+fn direction(yaw: Rad<f32>, pitch: Rad<f32>) -> cgmath::Vector3<f32> {
+    let max_pitch = Rad::from(MAX_PITCH).0;
+    let pitch = pitch.0.clamp(-max_pitch, max_pitch);
+    cgmath::Vector3::new(pitch.cos() * yaw.0.sin(), pitch.sin(), pitch.cos() * yaw.0.cos())
+}
+
 #[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+pub(crate) const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
     0.0, 0.0, 0.5, 0.5,
     0.0, 0.0, 0.0, 1.0,
 );
 
+// Maps the depth to 0..1 like OPENGL_TO_WGPU_MATRIX, which also adds half of it to w.
+// Perspective projections get away with that, orthographic ones would have the
+// near half of their depth range clipped.
+#[rustfmt::skip]
+pub(crate) const ORTHO_OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Replaces depth z/w with 1 - z/w
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
 // We need this for Rust to store our data correctly for the shaders
 #[repr(C)]
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct CameraUniform {
+pub(crate) struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // View ray directions for the skybox, see CameraModel::build_sky_rays_matrix
+    sky_rays: [[f32; 4]; 4],
+    // The eye in world space for specular highlights, w is 1
+    view_position: [f32; 4],
 }
 
 impl CameraUniform {
@@ -46,30 +245,96 @@ impl CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            sky_rays: cgmath::Matrix4::identity().into(),
+            view_position: [0.0, 0.0, 0.0, 1.0],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &CameraModel) {
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.sky_rays = camera.build_sky_rays_matrix().into();
+        self.view_position = camera.eye.to_homogeneous().into();
     }
 }
  
+// Keys for each camera movement, any of the listed keys triggers it. When orbiting, forward
+// and back move towards and away from the target and the others orbit around it. When
+// flying they all move along the view, `fast` speeds that up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    pub forward: Vec<KeyCode>,
+    pub back: Vec<KeyCode>,
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub up: Vec<KeyCode>,
+    pub down: Vec<KeyCode>,
+    pub fast: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    // WASD and the arrow keys, E and Q for up and down, Shift to fly faster
+    fn default() -> Self {
+        Self {
+            forward: vec![KeyCode::KeyW, KeyCode::ArrowUp],
+            back: vec![KeyCode::KeyS, KeyCode::ArrowDown],
+            left: vec![KeyCode::KeyA, KeyCode::ArrowLeft],
+            right: vec![KeyCode::KeyD, KeyCode::ArrowRight],
+            up: vec![KeyCode::KeyE],
+            down: vec![KeyCode::KeyQ],
+            fast: vec![KeyCode::ShiftLeft, KeyCode::ShiftRight],
+        }
+    }
+}
+
 pub struct CameraController {
+    // Units per second
     speed: f32,
+    // Degrees per pixel
+    look_sensitivity: f32,
+    bindings: KeyBindings,
+    mode: CameraMode,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_fast_pressed: bool,
+    is_dragging: bool,
+    last_cursor_position: Option<PhysicalPosition<f64>>,
+    // Accumulated since the last update, in degrees. Turns the view when flying.
+    orbit_yaw: f32,
+    orbit_pitch: f32,
+    // Accumulated since the last update, in wheel lines, positive is towards the target
+    zoom: f32,
+    zoom_sensitivity: f32,
+    min_distance: f32,
+    max_distance: f32,
 }
 
 impl CameraController {
+    // `speed` is in units per second
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
+            look_sensitivity: ORBIT_SENSITIVITY,
+            bindings: KeyBindings::default(),
+            mode: CameraMode::Orbit,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_fast_pressed: false,
+            is_dragging: false,
+            last_cursor_position: None,
+            orbit_yaw: 0.0,
+            orbit_pitch: 0.0,
+            zoom: 0.0,
+            zoom_sensitivity: 0.25,
+            min_distance: 0.5,
+            max_distance: 50.0,
         }
     }
 
@@ -84,43 +349,198 @@ impl CameraController {
                 ..
             } => {
                 let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    KeyCode::KeyW | KeyCode::ArrowUp => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyA | KeyCode::ArrowLeft => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyS | KeyCode::ArrowDown => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    KeyCode::KeyD | KeyCode::ArrowRight => {
-                        self.is_right_pressed = is_pressed;
-                        true
+                let bindings = &self.bindings;
+                let pressed = if bindings.forward.contains(keycode) {
+                    &mut self.is_forward_pressed
+                } else if bindings.back.contains(keycode) {
+                    &mut self.is_backward_pressed
+                } else if bindings.left.contains(keycode) {
+                    &mut self.is_left_pressed
+                } else if bindings.right.contains(keycode) {
+                    &mut self.is_right_pressed
+                } else if bindings.up.contains(keycode) {
+                    &mut self.is_up_pressed
+                } else if bindings.down.contains(keycode) {
+                    &mut self.is_down_pressed
+                } else if bindings.fast.contains(keycode) {
+                    &mut self.is_fast_pressed
+                } else {
+                    return false;
+                };
+                *pressed = is_pressed;
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.is_dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(last) = self.last_cursor_position {
+                    // Flying looks around with `process_mouse_motion`, the cursor is locked in place
+                    if self.is_dragging && self.mode == CameraMode::Orbit {
+                        self.orbit_yaw -= (position.x - last.x) as f32 * self.look_sensitivity;
+                        self.orbit_pitch += (position.y - last.y) as f32 * self.look_sensitivity;
                     }
-                    _ => false,
                 }
+                self.last_cursor_position = Some(*position);
+                self.is_dragging
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.zoom += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_SCROLL_LINE) as f32,
+                };
+                true
             }
             _ => false,
         }
     }
 
-    pub fn update_camera(&self, camera: &mut CameraModel) {
+    // Raw mouse movement, e.g. from DeviceEvent::MouseMotion, turns the view when flying
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) -> bool {
+        if self.mode != CameraMode::Fly {
+            return false;
+        }
+        self.orbit_yaw -= dx as f32 * self.look_sensitivity;
+        self.orbit_pitch += dy as f32 * self.look_sensitivity;
+        true
+    }
+
+    // Drops the turning and zooming accumulated since the last update
+    fn discard_input(&mut self) {
+        self.orbit_yaw = 0.0;
+        self.orbit_pitch = 0.0;
+        self.zoom = 0.0;
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    // Whether the next update moves the camera, it keeps moving while keys are held
+    pub fn is_moving(&self) -> bool {
+        self.is_forward_pressed
+            || self.is_backward_pressed
+            || self.is_left_pressed
+            || self.is_right_pressed
+            || self.is_up_pressed
+            || self.is_down_pressed
+            || self.orbit_yaw != 0.0
+            || self.orbit_pitch != 0.0
+            || self.zoom != 0.0
+    }
+
+    // Drops the input accumulated for the previous mode
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = mode;
+        self.orbit_yaw = 0.0;
+        self.orbit_pitch = 0.0;
+        self.zoom = 0.0;
+    }
+
+    fn orbit(&mut self, camera: &mut CameraModel) {
+        if self.orbit_yaw == 0.0 && self.orbit_pitch == 0.0 {
+            return;
+        }
+        let (yaw, pitch) = camera.yaw_pitch();
+        camera.set_yaw_pitch(yaw + Rad::from(Deg(self.orbit_yaw)), pitch + Rad::from(Deg(self.orbit_pitch)));
+        self.orbit_yaw = 0.0;
+        self.orbit_pitch = 0.0;
+    }
+
+    fn apply_zoom(&mut self, camera: &mut CameraModel) {
+        use cgmath::InnerSpace;
+        if self.zoom == 0.0 {
+            return;
+        }
+        let offset = camera.eye - camera.target;
+        let distance = (offset.magnitude() - self.zoom * self.zoom_sensitivity)
+            .clamp(self.min_distance, self.max_distance);
+        camera.eye = camera.target + offset.normalize() * distance;
+        self.zoom = 0.0;
+    }
+
+    pub fn set_zoom_sensitivity(&mut self, units_per_line: f32) {
+        self.zoom_sensitivity = units_per_line;
+    }
+
+    pub fn set_move_speed(&mut self, units_per_sec: f32) {
+        self.speed = units_per_sec;
+    }
+
+    pub fn set_look_sensitivity(&mut self, deg_per_pixel: f32) {
+        self.look_sensitivity = deg_per_pixel;
+    }
+
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.bindings
+    }
+
+    // Releases the held keys, their release events might not match the new bindings
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.bindings = bindings;
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+        self.is_up_pressed = false;
+        self.is_down_pressed = false;
+        self.is_fast_pressed = false;
+    }
+
+    // Keyboard movement is scaled by `dt`, cursor and wheel input is applied as is
+    pub fn update_camera(&mut self, camera: &mut CameraModel, dt: Duration) {
+        match self.mode {
+            CameraMode::Orbit => self.update_orbit(camera, dt),
+            CameraMode::Fly => self.update_fly(camera, dt),
+        }
+    }
+
+    fn update_fly(&mut self, camera: &mut CameraModel, dt: Duration) {
         use cgmath::InnerSpace;
+        if self.orbit_yaw != 0.0 || self.orbit_pitch != 0.0 {
+            let (yaw, pitch) = camera.look_yaw_pitch();
+            camera.set_look_yaw_pitch(yaw + Rad::from(Deg(self.orbit_yaw)), pitch - Rad::from(Deg(self.orbit_pitch)));
+            self.orbit_yaw = 0.0;
+            self.orbit_pitch = 0.0;
+        }
+
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = forward * axis(self.is_forward_pressed, self.is_backward_pressed)
+            + right * axis(self.is_right_pressed, self.is_left_pressed)
+            + up * axis(self.is_up_pressed, self.is_down_pressed);
+        let speed = if self.is_fast_pressed { self.speed * FAST_MULTIPLIER } else { self.speed };
+        // The wheel moves along the view like it zooms when orbiting
+        let offset = direction * speed * dt.as_secs_f32() + forward * self.zoom * self.zoom_sensitivity;
+        self.zoom = 0.0;
+        camera.eye += offset;
+        camera.target += offset;
+    }
+
+    fn update_orbit(&mut self, camera: &mut CameraModel, dt: Duration) {
+        self.orbit(camera);
+        self.apply_zoom(camera);
+
+        use cgmath::InnerSpace;
+        let step = self.speed * dt.as_secs_f32();
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
         // Prevents glitching when camera gets too close to the
         // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+        if self.is_forward_pressed && forward_mag > step {
+            camera.eye += forward_norm * step;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+            camera.eye -= forward_norm * step;
         }
 
         let right = forward_norm.cross(camera.up);
@@ -133,10 +553,16 @@ impl CameraController {
             // Rescale the distance between the target and eye so
             // that it doesn't change. The eye therefore still
             // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward + right * step).normalize() * forward_mag;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            camera.eye = camera.target - (forward - right * step).normalize() * forward_mag;
+        }
+        if self.is_up_pressed != self.is_down_pressed {
+            // Same angle as a step to the side, clamped at the poles
+            let angle = Rad((step / forward_mag).atan());
+            let (yaw, pitch) = camera.yaw_pitch();
+            camera.set_yaw_pitch(yaw, if self.is_up_pressed { pitch + angle } else { pitch - angle });
         }
     }
 }
@@ -144,13 +570,15 @@ impl CameraController {
 pub struct CameraState {
     pub model: CameraModel,
     pub controller: CameraController,
-    pub uniform: CameraUniform,
-    pub buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    // Controller input is ignored while animating
+    animation: Option<Animation>,
 }
 
 impl CameraState {
-    pub fn new(device: &wgpu::Device,
+    pub(crate) fn new(device: &wgpu::Device,
                width: u32,
                height: u32,
                layout: &wgpu::BindGroupLayout) -> Self {
@@ -163,9 +591,10 @@ impl CameraState {
             // which way is "up"
             up: cgmath::Vector3::unit_y(),
             aspect: width as f32 / height as f32,
-            fovy: 45.0,
+            projection: Projection::Perspective { fovy: Deg(45.0) },
             znear: 0.1,
             zfar: 100.0,
+            reverse_z: false,
         };
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
@@ -179,7 +608,7 @@ impl CameraState {
         );
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                 binding: 0,
@@ -189,23 +618,25 @@ impl CameraState {
             label: Some("camera_bind_group"),
         });
 
-        let controller = CameraController::new(0.2);
+        let controller = CameraController::new(MOVE_SPEED);
 
-        return Self {
+        Self {
             model: camera,
             uniform: camera_uniform,
-            controller: controller,
+            controller,
             buffer: camera_buffer,
-            bind_group: camera_bind_group
+            bind_group: camera_bind_group,
+            animation: None,
         }
     }
 
-    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    pub(crate) fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // The fragment stage needs the view position
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -218,8 +649,194 @@ impl CameraState {
         })
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        self.controller.update_camera(&mut self.model);
+    // Keeps the projection's aspect ratio in sync with the render target, the
+    // uniform is uploaded right away so the next frame isn't stretched
+    pub fn resize(&mut self, width: u32, height: u32, queue: &wgpu::Queue) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.model.aspect = width as f32 / height as f32;
+        self.uniform.update_view_proj(&self.model);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn set_projection(&mut self, mode: Projection) {
+        self.model.projection = mode;
+    }
+
+    pub fn set_bindings(&mut self, bindings: KeyBindings) {
+        self.controller.set_bindings(bindings);
+    }
+
+    pub fn set_move_speed(&mut self, units_per_sec: f32) {
+        self.controller.set_move_speed(units_per_sec);
+    }
+
+    pub fn set_look_sensitivity(&mut self, deg_per_pixel: f32) {
+        self.controller.set_look_sensitivity(deg_per_pixel);
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.controller.mode()
+    }
+
+    // The view stays where it is, flying starts looking towards the orbit target
+    // and orbiting continues around the point the flight last looked at
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.controller.set_mode(mode);
+    }
+
+    // Range of the distance to the orbit target. Swapped bounds are put in order and min is
+    // kept at least MIN_ZOOM_DISTANCE, with a warning.
+    pub fn set_zoom_bounds(&mut self, min: f32, max: f32) {
+        let (mut min, mut max) = (min, max);
+        if min > max {
+            log::warn!("Zoom bounds {}..{} are swapped", min, max);
+            (min, max) = (max, min);
+        }
+        if min.is_nan() || min < MIN_ZOOM_DISTANCE {
+            log::warn!("Zoom bounds have to start at {} at least, got {}", MIN_ZOOM_DISTANCE, min);
+            min = MIN_ZOOM_DISTANCE;
+        }
+        // Also replaces NaN
+        max = max.max(min);
+        self.controller.min_distance = min;
+        self.controller.max_distance = max;
+    }
+
+    pub fn set_orbit_target(&mut self, target: cgmath::Point3<f32>) {
+        self.model.target = target;
+    }
+
+    // The uniform is rewritten on every update, so the setters below
+    // take effect on the next one
+
+    pub fn position(&self) -> cgmath::Point3<f32> {
+        self.model.eye
+    }
+
+    // The camera keeps looking at the orbit target
+    pub fn set_position(&mut self, position: cgmath::Point3<f32>) {
+        self.model.eye = position;
+    }
+
+    // Yaw is measured around +y from +z towards +x, positive pitch looks down from above the target
+    pub fn yaw_pitch(&self) -> (Deg<f32>, Deg<f32>) {
+        let (yaw, pitch) = self.model.yaw_pitch();
+        (yaw.into(), pitch.into())
+    }
+
+    // Orbits to the given angles keeping the distance to the target
+    pub fn set_yaw_pitch(&mut self, yaw: Deg<f32>, pitch: Deg<f32>) {
+        self.model.set_yaw_pitch(yaw.into(), pitch.into());
+    }
+
+    pub fn viewpoint(&self) -> Viewpoint {
+        Viewpoint { eye: self.model.eye, target: self.model.target }
+    }
+
+    pub fn set_viewpoint(&mut self, viewpoint: Viewpoint) {
+        self.animation = None;
+        self.model.eye = viewpoint.eye;
+        self.model.target = viewpoint.target;
+    }
+
+    // Replaces any animation in progress, starting from the current viewpoint
+    pub fn animate_to(&mut self, target: Viewpoint, duration: Duration) {
+        if duration.is_zero() {
+            self.set_viewpoint(target);
+            return;
+        }
+        self.animation = Some(Animation::Move(CameraAnimation {
+            from: self.viewpoint(),
+            to: target,
+            duration,
+            elapsed: Duration::ZERO,
+        }));
+    }
+
+    // Hitchcock zoom: the subject at the target keeps its size on screen while the
+    // background warps. `target_subject_size` is what the height of the view at the
+    // current distance ends up as, smaller values narrow the field of view and
+    // dolly out, larger ones widen it and dolly in. Only for perspective projections.
+    pub fn dolly_zoom(&mut self, target_subject_size: f32, duration: Duration) {
+        use cgmath::{Angle, InnerSpace};
+        let Projection::Perspective { fovy } = self.model.projection else {
+            log::warn!("Dolly zoom needs a perspective projection");
+            return;
+        };
+        if target_subject_size.is_nan() || target_subject_size <= 0.0 {
+            log::warn!("Dolly zoom needs a positive subject size, got {}", target_subject_size);
+            return;
+        }
+        let distance = (self.model.target - self.model.eye).magnitude();
+        let to_fovy = Deg::from(Rad((target_subject_size / (2.0 * distance)).atan() * 2.0));
+        let to_fovy = Deg(to_fovy.0.clamp(MIN_DOLLY_FOVY.0, MAX_DOLLY_FOVY.0));
+        let frame_height = 2.0 * distance * (fovy / 2.0).tan();
+        if duration.is_zero() {
+            self.animation = None;
+            self.apply_dolly_zoom(to_fovy, frame_height / (2.0 * (to_fovy / 2.0).tan()));
+            return;
+        }
+        self.animation = Some(Animation::DollyZoom(DollyZoom {
+            from_fovy: fovy,
+            to_fovy,
+            frame_height,
+            duration,
+            elapsed: Duration::ZERO,
+        }));
+    }
+
+    fn apply_dolly_zoom(&mut self, fovy: Deg<f32>, distance: f32) {
+        use cgmath::InnerSpace;
+        self.model.projection = Projection::Perspective { fovy };
+        self.model.eye = self.model.target - (self.model.target - self.model.eye).normalize() * distance;
+    }
+
+    // Animates towards looking at `point` as the new orbit target, from the direction the
+    // eye currently sees it in and at the current orbit distance within the zoom bounds
+    pub fn focus_on(&mut self, point: cgmath::Point3<f32>, duration: Duration) {
+        use cgmath::InnerSpace;
+        let direction = point - self.model.eye;
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+        let distance = (self.model.target - self.model.eye).magnitude()
+            .clamp(self.controller.min_distance, self.controller.max_distance);
+        let eye = point - direction.normalize() * distance;
+        self.animate_to(Viewpoint { eye, target: point }, duration);
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        let animating = match self.animation.as_mut() {
+            Some(Animation::Move(animation)) => match animation.advance(dt) {
+                Some(viewpoint) => {
+                    self.model.eye = viewpoint.eye;
+                    self.model.target = viewpoint.target;
+                    true
+                }
+                None => false,
+            },
+            Some(Animation::DollyZoom(animation)) => match animation.advance(dt) {
+                Some((fovy, distance)) => {
+                    self.apply_dolly_zoom(fovy, distance);
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        };
+        if animating {
+            // Applied all at once afterwards, the camera would jump
+            self.controller.discard_input();
+        } else {
+            self.animation = None;
+            self.controller.update_camera(&mut self.model, dt);
+        }
         self.uniform.update_view_proj(&self.model);
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
     }
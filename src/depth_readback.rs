@@ -0,0 +1,211 @@
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, Queue};
+
+use crate::depth_view::with_depth_texture_alias;
+use crate::texture::Texture;
+
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TexelUniform {
+    coords: [u32; 2],
+    // Uniform buffers are at least 16 bytes on WebGL
+    _padding: [u32; 2],
+}
+
+// Reads single depth values back to the CPU. Depth textures can't be copied to buffers
+// everywhere (not on GL) nor when multisampled, so a pass loads the texel in a shader
+// and writes it into a 1x1 color target that is copied instead.
+pub struct DepthReadback {
+    layout: BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    texel_buffer: wgpu::Buffer,
+    target: wgpu::Texture,
+    buffer: wgpu::Buffer,
+}
+
+impl DepthReadback {
+    pub fn new(device: &Device, sample_count: u32) -> Self {
+        let layout = Self::create_layout(device, sample_count);
+        let texel_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth Readback Texel Buffer"),
+            contents: bytemuck::cast_slice(&[TexelUniform { coords: [0; 2], _padding: [0; 2] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("depth_readback_texture"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        // Rows of buffer copies are 256 bytes apart, even a single one
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Readback Buffer"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            pipeline: Self::create_pipeline(device, sample_count, &layout),
+            layout,
+            texel_buffer,
+            target,
+            buffer,
+        }
+    }
+
+    fn create_layout(device: &Device, sample_count: u32) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("depth_readback_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        // Not Depth, GLSL can't textureLoad from depth textures
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: sample_count > 1,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_pipeline(device: &Device, sample_count: u32, layout: &BindGroupLayout) -> wgpu::RenderPipeline {
+        let source = with_depth_texture_alias(sample_count, include_str!("shaders/depth_readback.wgsl"));
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Readback Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Readback Pipeline Layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Readback Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TARGET_FORMAT,
+                    // Integer formats can't be blended
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // The depth texture has to match the sample count
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.layout = Self::create_layout(device, sample_count);
+        self.pipeline = Self::create_pipeline(device, sample_count, &self.layout);
+    }
+
+    // Depth stored at texel (x, y), the first sample of multisampled textures.
+    // Waits for the GPU, which never finishes on wasm, see State::world_position_at.
+    pub fn read(&self, device: &Device, queue: &Queue, depth_texture: &Texture, x: u32, y: u32) -> f32 {
+        queue.write_buffer(&self.texel_buffer, 0, bytemuck::cast_slice(&[TexelUniform { coords: [x, y], _padding: [0; 2] }]));
+        // Only the depth aspect of combined depth-stencil formats can be sampled
+        let depth_only_view = depth_texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth_readback_bind_group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_only_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.texel_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let target_view = self.target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Depth Readback Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Readback Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_buffer(
+            self.target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            self.target.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.buffer.slice(..4);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().expect("Failed to map depth readback buffer");
+        let depth = f32::from_le_bytes(slice.get_mapped_range()[..4].try_into().unwrap());
+        self.buffer.unmap();
+        depth
+    }
+}
@@ -1,74 +1,233 @@
-use wgpu::{BindGroup, BindGroupLayout, ColorTargetState, CommandEncoder, Device, Face, FragmentState, StoreOp, SurfaceConfiguration, TextureFormat, TextureView, VertexState};
-use wgpu::TextureSampleType::Depth;
+use std::collections::HashMap;
+
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, FragmentState, Queue, StoreOp, TextureFormat, TextureView, VertexState};
+use wgpu::util::DeviceExt;
 use crate::texture::Texture;
 
+// How the linearized depth is turned into a color
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Colormap {
+    // Black at the near plane, white at the far one, fading out towards the near plane
+    #[default]
+    Grayscale,
+    // Rainbow ramp from dark blue to dark red, small depth differences change the hue
+    Turbo,
+    // Opaque, white at the near plane and black at the far one
+    Inverted,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClipPlanesUniform {
+    near: f32,
+    far: f32,
+    // 1 when the depth buffer stores 1.0 at the near plane and 0.0 at the far one
+    reverse_z: u32,
+    // See Colormap::index
+    colormap: u32,
+}
+
+// Prepends the declaration of the DepthTexture type to a shader reading the scene's depth buffer.
+// Both texture types are read with textureLoad, so only the declaration differs.
+pub(crate) fn with_depth_texture_alias(sample_count: u32, shader_source: &str) -> String {
+    let depth_texture_type = if sample_count > 1 {
+        "texture_multisampled_2d<f32>"
+    } else {
+        "texture_2d<f32>"
+    };
+    format!("alias DepthTexture = {};\n{}", depth_texture_type, shader_source)
+}
+
+impl Colormap {
+    // Selects the ramp in depth_render.wgsl
+    fn index(self) -> u32 {
+        match self {
+            Colormap::Grayscale => 0,
+            Colormap::Turbo => 1,
+            Colormap::Inverted => 2,
+        }
+    }
+}
+
 pub struct DepthView {
     pipeline: wgpu::RenderPipeline,
     depth_texture_bind_group_layout: wgpu::BindGroupLayout,
     depth_texture_bind_group: wgpu::BindGroup,
+    clip_planes: ClipPlanesUniform,
+    clip_planes_buffer: wgpu::Buffer,
+    // Set when the clip planes changed and have to be uploaded on the next update
+    clip_planes_dirty: bool,
+    target_texture_format: TextureFormat,
+    sample_count: u32,
+    // Without the DepthTexture alias, which depends on the sample count
+    shader_source: String,
+    // x, y, width and height in pixels of the target, None covers all of it
+    viewport: Option<[f32; 4]>,
 }
 
 impl DepthView {
     pub(crate) fn new(device: &Device,
                       target_texture_format: TextureFormat,
-                      depth_texture: &Texture) -> DepthView {
-        let depth_texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                      depth_texture: &Texture,
+                      sample_count: u32) -> DepthView {
+        let depth_texture_bind_group_layout = Self::create_bind_group_layout(device, sample_count);
+        // Matches the default camera, State keeps it in sync
+        let clip_planes = ClipPlanesUniform { near: 0.1, far: 100.0, reverse_z: 0, colormap: Colormap::default().index() };
+        let clip_planes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth View Clip Planes Buffer"),
+            contents: bytemuck::cast_slice(&[clip_planes]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let shader_source = include_str!("shaders/depth_render.wgsl").to_string();
+        let pipeline = Self::create_depth_render_pipeline(device, target_texture_format, sample_count, &shader_source, &[&depth_texture_bind_group_layout]);
+        let depth_texture_bind_group = Self::create_bind_group(device, &depth_texture_bind_group_layout, depth_texture, &clip_planes_buffer);
+        DepthView {
+            pipeline,
+            depth_texture_bind_group_layout,
+            depth_texture_bind_group,
+            clip_planes,
+            clip_planes_buffer,
+            clip_planes_dirty: false,
+            target_texture_format,
+            sample_count,
+            shader_source,
+            viewport: None,
+        }
+    }
+
+    fn create_bind_group_layout(device: &Device, sample_count: u32) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("depth_texture_bind_group_layout"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
-                        sample_type: Depth,
+                        // Not Depth, GLSL can't textureLoad from depth textures
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
                         view_dimension: Default::default(),
-                        multisampled: false,
+                        multisampled: sample_count > 1,
                     },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: 2,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
                     count: None,
                 }
             ]
-        });
-        let pipeline = Self::create_depth_render_pipeline(device, target_texture_format, &[&depth_texture_bind_group_layout]);
-        let depth_texture_bind_group = Self::create_bind_group(device, &depth_texture_bind_group_layout, depth_texture);
-        DepthView { pipeline, depth_texture_bind_group_layout, depth_texture_bind_group }
+        })
     }
 
     fn create_bind_group(device: &Device,
                          depth_texture_bind_group_layout: &BindGroupLayout,
-                         depth_texture: &Texture) -> BindGroup {
-        return device.create_bind_group(
+                         depth_texture: &Texture,
+                         clip_planes_buffer: &Buffer) -> BindGroup {
+        // Only the depth aspect of combined depth-stencil formats can be sampled
+        let depth_only_view = depth_texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        device.create_bind_group(
             &wgpu::BindGroupDescriptor {
                 label: Some("depth_texture_bind_group"),
                 layout: depth_texture_bind_group_layout,
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                        resource: wgpu::BindingResource::TextureView(&depth_only_view),
                     },
                     wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&depth_texture.sampler),
+                        binding: 2,
+                        resource: clip_planes_buffer.as_entire_binding(),
                     }
                 ],
             }
-        );
+        )
     }
 
     pub fn set_depth_texture(&mut self, device: &Device, depth_texture: &Texture) {
-        self.depth_texture_bind_group = Self::create_bind_group(device, &self.depth_texture_bind_group_layout, depth_texture);
+        self.depth_texture_bind_group = Self::create_bind_group(device, &self.depth_texture_bind_group_layout, depth_texture, &self.clip_planes_buffer);
+    }
+
+    // The layout and pipeline depend on whether the depth texture is multisampled,
+    // call set_depth_texture with the matching texture afterwards
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        self.depth_texture_bind_group_layout = Self::create_bind_group_layout(device, sample_count);
+        self.pipeline = self.create_pipeline(device, &self.shader_source);
+    }
+
+    pub(crate) fn create_pipeline(&self, device: &Device, shader_source: &str) -> wgpu::RenderPipeline {
+        Self::create_depth_render_pipeline(device,
+                                           self.target_texture_format,
+                                           self.sample_count,
+                                           shader_source,
+                                           &[&self.depth_texture_bind_group_layout])
+    }
+
+    // Swaps in a pipeline created by `create_pipeline` from `shader_source`
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub(crate) fn set_pipeline(&mut self, pipeline: wgpu::RenderPipeline, shader_source: String) {
+        self.pipeline = pipeline;
+        self.shader_source = shader_source;
+    }
+
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        self.clip_planes.near = near;
+        self.clip_planes.far = far;
+        self.clip_planes_dirty = true;
+    }
+
+    pub fn colormap(&self) -> Colormap {
+        match self.clip_planes.colormap {
+            1 => Colormap::Turbo,
+            2 => Colormap::Inverted,
+            _ => Colormap::Grayscale,
+        }
+    }
+
+    pub fn set_colormap(&mut self, map: Colormap) {
+        self.clip_planes.colormap = map.index();
+        self.clip_planes_dirty = true;
+    }
+
+    pub fn set_reverse_z(&mut self, on: bool) {
+        self.clip_planes.reverse_z = on as u32;
+        self.clip_planes_dirty = true;
+    }
+
+    // Draws into the rectangle from (x, y) in pixels from the top left instead of over the
+    // whole target, e.g. as a picture-in-picture in a corner
+    pub fn set_viewport(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.viewport = Some([x, y, width, height]);
+    }
+
+    pub fn clear_viewport(&mut self) {
+        self.viewport = None;
+    }
+
+    pub fn update(&mut self, queue: &Queue) {
+        if self.clip_planes_dirty {
+            queue.write_buffer(&self.clip_planes_buffer, 0, bytemuck::cast_slice(&[self.clip_planes]));
+            self.clip_planes_dirty = false;
+        }
     }
 
     pub fn create_depth_render_pipeline(device: &Device,
                                         target_texture_format: TextureFormat,
+                                        sample_count: u32,
+                                        shader_source: &str,
                                         bind_group_layouts: &[&BindGroupLayout]) -> wgpu::RenderPipeline {
+        let source = with_depth_texture_alias(sample_count, shader_source);
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Depth view shaders"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/depth_render.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
         });
         let depth_view_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -76,6 +235,8 @@ impl DepthView {
                 bind_group_layouts,
                 push_constant_ranges: &[],
             });
+        // The ramps are sRGB encoded
+        let constants = HashMap::from([("srgb_target".to_string(), if target_texture_format.is_srgb() { 1.0 } else { 0.0 })]);
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Depth View Pipeline"),
             layout: Some(&depth_view_pipeline_layout),
@@ -88,7 +249,10 @@ impl DepthView {
             fragment: Some(FragmentState {
                 module: &shader,
                 entry_point: "depth_view_fs",
-                compilation_options: Default::default(),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
                 targets: &[Some(wgpu::ColorTargetState {
                     format: target_texture_format,
                     blend: Some(wgpu::BlendState::ALPHA_BLENDING),
@@ -111,11 +275,16 @@ impl DepthView {
         })
     }
 
-    pub fn render(&self, view: &TextureView, encoder: &mut CommandEncoder) {
+    // `size` is the view's size in pixels, the viewport is clamped to it
+    pub fn render(&self,
+                  view: &TextureView,
+                  encoder: &mut CommandEncoder,
+                  size: winit::dpi::PhysicalSize<u32>,
+                  timestamp_writes: Option<wgpu::RenderPassTimestampWrites>) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Depth View Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Load,
@@ -123,10 +292,22 @@ impl DepthView {
                 },
             })],
             depth_stencil_attachment: None,
-            timestamp_writes: None,
+            timestamp_writes,
             occlusion_query_set: None,
         });
 
+        if let Some([x, y, width, height]) = self.viewport {
+            let (target_width, target_height) = (size.width as f32, size.height as f32);
+            let x = x.clamp(0.0, target_width);
+            let y = y.clamp(0.0, target_height);
+            let width = width.clamp(0.0, target_width - x);
+            let height = height.clamp(0.0, target_height - y);
+            // An empty viewport is invalid
+            if width < 1.0 || height < 1.0 {
+                return;
+            }
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
         render_pass.set_pipeline(&self.pipeline);
         render_pass.set_bind_group(0, &self.depth_texture_bind_group, &[]);
         render_pass.draw(0..3, 0..1);
@@ -0,0 +1,243 @@
+use egui::{pos2, vec2, Pos2};
+use web_time::Instant;
+use wgpu::{Device, Queue, TextureFormat};
+use winit::{
+    dpi::PhysicalSize,
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+// egui drawn on top of the final image. Winit events are translated here because
+// egui-winit requires a newer winit than the one this crate uses.
+pub struct EguiOverlay {
+    context: egui::Context,
+    renderer: egui_wgpu::Renderer,
+    max_texture_side: usize,
+    start: Instant,
+    // Collected from `on_event` until the next pass begins
+    events: Vec<egui::Event>,
+    modifiers: egui::Modifiers,
+    // In points, PointerButton events need it
+    pointer_position: Option<Pos2>,
+    focused: bool,
+    // Between `begin_pass` and `render`
+    pass_running: bool,
+    // Texture changes of passes that were never rendered, e.g. the font atlas
+    pending_textures: egui::TexturesDelta,
+}
+
+impl EguiOverlay {
+    pub fn new(device: &Device, output_format: TextureFormat) -> Self {
+        Self {
+            context: egui::Context::default(),
+            renderer: egui_wgpu::Renderer::new(device, output_format, None, 1, false),
+            max_texture_side: device.limits().max_texture_dimension_2d as usize,
+            start: Instant::now(),
+            events: Vec::new(),
+            modifiers: egui::Modifiers::default(),
+            pointer_position: None,
+            focused: true,
+            pass_running: false,
+            pending_textures: egui::TexturesDelta::default(),
+        }
+    }
+
+    pub fn context(&self) -> &egui::Context {
+        &self.context
+    }
+
+    // Returns true when egui uses the event and nothing else should handle it.
+    // Mouse events are only taken while the pointer is over a widget.
+    pub fn on_event(&mut self, event: &WindowEvent, scale_factor: f64) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = position.to_logical::<f32>(scale_factor);
+                let position = pos2(position.x, position.y);
+                self.pointer_position = Some(position);
+                self.events.push(egui::Event::PointerMoved(position));
+                // The camera has to keep tracking the cursor
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer_position = None;
+                self.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let (Some(pos), Some(button)) = (self.pointer_position, pointer_button(*button)) else {
+                    return false;
+                };
+                self.events.push(egui::Event::PointerButton {
+                    pos,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+                self.wants_pointer()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (egui::MouseWheelUnit::Line, vec2(*x, *y)),
+                    MouseScrollDelta::PixelDelta(delta) => {
+                        let delta = delta.to_logical::<f32>(scale_factor);
+                        (egui::MouseWheelUnit::Point, vec2(delta.x, delta.y))
+                    }
+                };
+                self.events.push(egui::Event::MouseWheel { unit, delta, modifiers: self.modifiers });
+                self.wants_pointer()
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let state = modifiers.state();
+                self.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: cfg!(target_os = "macos") && state.super_key(),
+                    command: if cfg!(target_os = "macos") { state.super_key() } else { state.control_key() },
+                };
+                false
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    if let Some(key) = key(code) {
+                        self.events.push(egui::Event::Key {
+                            key,
+                            physical_key: Some(key),
+                            pressed,
+                            repeat: event.repeat,
+                            modifiers: self.modifiers,
+                        });
+                    }
+                }
+                if let Some(text) = event.text.as_ref().filter(|_| pressed) {
+                    if !text.chars().any(char::is_control) && !self.modifiers.command {
+                        self.events.push(egui::Event::Text(text.to_string()));
+                    }
+                }
+                self.context.wants_keyboard_input()
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn wants_pointer(&self) -> bool {
+        self.context.wants_pointer_input() || self.context.is_pointer_over_area()
+    }
+
+    // Starts collecting the UI of the next frame, panels are added between this and `render`
+    pub fn begin_pass(&mut self, size: PhysicalSize<u32>, scale_factor: f64) {
+        if self.pass_running {
+            // The previous pass was never rendered, keep its texture changes
+            let output = self.context.end_pass();
+            self.pending_textures.append(output.textures_delta);
+        }
+        let mut input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                Pos2::ZERO,
+                vec2(size.width as f32, size.height as f32) / scale_factor as f32,
+            )),
+            max_texture_side: Some(self.max_texture_side),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            focused: self.focused,
+            ..Default::default()
+        };
+        input.viewports.entry(egui::ViewportId::ROOT).or_default().native_pixels_per_point = Some(scale_factor as f32);
+        self.context.begin_pass(input);
+        self.pass_running = true;
+    }
+
+    // Draws the UI of the current pass over `view`, nothing when no pass was begun
+    pub fn render(&mut self,
+                  device: &Device,
+                  queue: &Queue,
+                  encoder: &mut wgpu::CommandEncoder,
+                  view: &wgpu::TextureView,
+                  size: PhysicalSize<u32>) {
+        if !self.pass_running {
+            return;
+        }
+        self.pass_running = false;
+        let output = self.context.end_pass();
+        let mut textures = std::mem::take(&mut self.pending_textures);
+        textures.append(output.textures_delta);
+
+        let paint_jobs = self.context.tessellate(output.shapes, output.pixels_per_point);
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+        for (id, delta) in &textures.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        // Only paint callbacks produce command buffers, they have to run before the pass
+        let callback_buffers = self.renderer.update_buffers(device, queue, encoder, &paint_jobs, &screen);
+        if !callback_buffers.is_empty() {
+            queue.submit(callback_buffers);
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Egui Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            }).forget_lifetime();
+            self.renderer.render(&mut render_pass, &paint_jobs, &screen);
+        }
+        for id in &textures.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+fn pointer_button(button: MouseButton) -> Option<egui::PointerButton> {
+    match button {
+        MouseButton::Left => Some(egui::PointerButton::Primary),
+        MouseButton::Right => Some(egui::PointerButton::Secondary),
+        MouseButton::Middle => Some(egui::PointerButton::Middle),
+        MouseButton::Back => Some(egui::PointerButton::Extra1),
+        MouseButton::Forward => Some(egui::PointerButton::Extra2),
+        MouseButton::Other(_) => None,
+    }
+}
+
+// Keys used for navigating and editing widgets, text arrives separately
+fn key(code: KeyCode) -> Option<egui::Key> {
+    use egui::Key;
+    Some(match code {
+        KeyCode::ArrowDown => Key::ArrowDown,
+        KeyCode::ArrowLeft => Key::ArrowLeft,
+        KeyCode::ArrowRight => Key::ArrowRight,
+        KeyCode::ArrowUp => Key::ArrowUp,
+        KeyCode::Escape => Key::Escape,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Enter | KeyCode::NumpadEnter => Key::Enter,
+        KeyCode::Space => Key::Space,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        // Select all and undo/redo shortcuts
+        KeyCode::KeyA => Key::A,
+        KeyCode::KeyY => Key::Y,
+        KeyCode::KeyZ => Key::Z,
+        _ => return None,
+    })
+}
@@ -0,0 +1,52 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use web_time::Instant;
+
+// Number of frames the averages are taken over
+const WINDOW: usize = 60;
+
+// Rolling average of the wall clock time between frames
+pub struct FrameTimer {
+    last_frame: Option<Instant>,
+    frame_times: VecDeque<Duration>,
+    total: Duration,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self {
+            last_frame: None,
+            frame_times: VecDeque::with_capacity(WINDOW),
+            total: Duration::ZERO,
+        }
+    }
+
+    pub fn record_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            if self.frame_times.len() == WINDOW {
+                self.total -= self.frame_times.pop_front().unwrap();
+            }
+            let frame_time = now - last_frame;
+            self.frame_times.push_back(frame_time);
+            self.total += frame_time;
+        }
+        self.last_frame = Some(now);
+    }
+
+    pub fn frame_time_ms(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.total.as_secs_f32() * 1000.0 / self.frame_times.len() as f32
+    }
+
+    pub fn fps(&self) -> f32 {
+        let frame_time_ms = self.frame_time_ms();
+        if frame_time_ms == 0.0 {
+            return 0.0;
+        }
+        1000.0 / frame_time_ms
+    }
+}
@@ -0,0 +1,160 @@
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, TextureFormat};
+
+// Lines are drawn every unit from -HALF_EXTENT to HALF_EXTENT on both axes
+const HALF_EXTENT: i32 = 10;
+// Every MAJOR_STEP-th line is a major one
+const MAJOR_STEP: i32 = 5;
+const MINOR_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 0.35];
+const MAJOR_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 0.8];
+
+// Also drawn by BoundsBox
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct GridVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl GridVertex {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GridVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// Reference grid of lines on the XZ plane, drawn with the scene's camera and depth buffer
+pub struct GroundGrid {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+}
+
+impl GroundGrid {
+    pub fn new(device: &Device,
+               target_texture_format: TextureFormat,
+               sample_count: u32,
+               depth_format: TextureFormat,
+               depth_compare: wgpu::CompareFunction,
+               camera_layout: &BindGroupLayout) -> Self {
+        let vertices = Self::vertices();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ground Grid Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self {
+            pipeline: Self::create_pipeline(device, target_texture_format, sample_count, depth_format, depth_compare, camera_layout),
+            vertex_buffer,
+            num_vertices: vertices.len() as u32,
+        }
+    }
+
+    fn vertices() -> Vec<GridVertex> {
+        let extent = HALF_EXTENT as f32;
+        let mut vertices = Vec::new();
+        for i in -HALF_EXTENT..=HALF_EXTENT {
+            let color = if i % MAJOR_STEP == 0 { MAJOR_COLOR } else { MINOR_COLOR };
+            let offset = i as f32;
+            // One line along Z and one along X
+            for (start, end) in [
+                ([offset, 0.0, -extent], [offset, 0.0, extent]),
+                ([-extent, 0.0, offset], [extent, 0.0, offset]),
+            ] {
+                vertices.push(GridVertex { position: start, color });
+                vertices.push(GridVertex { position: end, color });
+            }
+        }
+        vertices
+    }
+
+    // Has to be called when the sample count, the depth format or the depth test of the scene pass changes
+    pub fn rebuild_pipeline(&mut self,
+                            device: &Device,
+                            target_texture_format: TextureFormat,
+                            sample_count: u32,
+                            depth_format: TextureFormat,
+                            depth_compare: wgpu::CompareFunction,
+                            camera_layout: &BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, target_texture_format, sample_count, depth_format, depth_compare, camera_layout);
+    }
+
+    // Line list pipeline for GridVertex lines, tested against the scene's depth buffer
+    pub(crate) fn create_pipeline(device: &Device,
+                                  target_texture_format: TextureFormat,
+                                  sample_count: u32,
+                                  depth_format: TextureFormat,
+                                  depth_compare: wgpu::CompareFunction,
+                                  camera_layout: &BindGroupLayout) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ground Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/grid.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ground Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ground Grid Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[GridVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_texture_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Tested against the cubes but not written, the lines are see-through
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Draws into a pass that already has the scene's color and depth attachments
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
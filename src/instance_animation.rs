@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, Queue};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    dt: f32,
+    // Uniform buffers are at least 16 bytes on WebGL
+    _padding: [f32; 3],
+}
+
+// Compute pass advancing the instance spins directly in the instances storage buffer
+pub struct InstanceAnimation {
+    pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+}
+
+impl InstanceAnimation {
+    // None when the device can't run compute shaders, e.g. on WebGL
+    pub fn new(device: &Device, adapter: &wgpu::Adapter, instances_layout: &BindGroupLayout) -> Option<Self> {
+        if !adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            return None;
+        }
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Animation Params Buffer"),
+            contents: bytemuck::cast_slice(&[ParamsUniform { dt: 0.0, _padding: [0.0; 3] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("instance_animation_params_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &params_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("instance_animation_params_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Instance Animation Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/animate_instances.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Instance Animation Pipeline Layout"),
+            bind_group_layouts: &[instances_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Instance Animation Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Some(Self {
+            pipeline,
+            params_buffer,
+            params_bind_group,
+        })
+    }
+
+    // Advances `count` spins by `dt` and submits the pass right away
+    pub fn run(&self, device: &Device, queue: &Queue, instances_bind_group: &wgpu::BindGroup, count: u32, dt: Duration) {
+        let params = ParamsUniform { dt: dt.as_secs_f32(), _padding: [0.0; 3] };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Instance Animation Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Instance Animation Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, instances_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.params_bind_group, &[]);
+            compute_pass.dispatch_workgroups(count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
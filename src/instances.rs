@@ -1,54 +1,85 @@
-use cgmath::{prelude::*, Deg, Matrix4, Vector3};
+use std::ops::Range;
+use std::time::Duration;
+
+use cgmath::{prelude::*, Deg, Matrix4, Rad, Vector3, Vector4};
 use wgpu::util::DeviceExt;
 use wgpu::BindGroupLayout;
 
+use crate::mesh::{Mesh, Vertex};
+
+// Rotation shared by all instances, advanced by the elapsed time every update
 pub struct Rotation {
-    pub step: cgmath::Matrix4<f32>,
-    pub rotation: cgmath::Matrix4<f32>,
-    pub rotation_uniform: PodMatrix,
-    pub buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
+    // Degrees per second around x and y
+    speed: (f32, f32),
+    // Turned so far around x and y, the matrix is rebuilt from them so it can't drift
+    // away from a rotation like repeated multiplication does
+    angles: (Deg<f32>, Deg<f32>),
+    rotation_uniform: PodMatrix,
+    buffer: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    paused: bool,
 }
 
 impl Rotation {
-    pub fn new(device: &wgpu::Device, layout: &BindGroupLayout) -> Self {
-        let x_step = cgmath::Matrix4::from_angle_x(Deg(1f32));
-        let y_step = cgmath::Matrix4::from_angle_y(Deg(0.8f32));
-        let step = x_step * y_step;
-        let rotation = cgmath::Matrix4::identity();
-        let rotation_uniform = PodMatrix {
-            m: rotation.into(),
-        };
+    pub(crate) fn new(device: &wgpu::Device, layout: &BindGroupLayout) -> Self {
+        let speed = (60.0, 48.0);
+        let angles = (Deg(0.0), Deg(0.0));
+        let rotation_uniform = PodMatrix::from(Self::matrix(angles));
 
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Rotator Buffer"),
+            label: Some("Rotation Buffer"),
             contents: bytemuck::cast_slice(&[rotation_uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
                     resource: buffer.as_entire_binding(),
                 }
             ],
-            label: Some("rotator_bind_group"),
+            label: Some("rotation_bind_group"),
         });
 
         Self {
-            step,
-            rotation,
+            speed,
+            angles,
             rotation_uniform,
             buffer,
-            bind_group
+            bind_group,
+            paused: false,
         }
     }
 
-    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    pub fn speed(&self) -> (f32, f32) {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, x_deg_per_second: f32, y_deg_per_second: f32) {
+        self.speed = (x_deg_per_second, y_deg_per_second);
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub(crate) fn is_turning(&self) -> bool {
+        !self.paused && self.speed != (0.0, 0.0)
+    }
+
+    pub(crate) fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("rotator_bind_group_layout"),
+            label: Some("rotation_bind_group_layout"),
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
                 visibility: wgpu::ShaderStages::VERTEX,
@@ -62,98 +93,756 @@ impl Rotation {
         })
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        self.rotation = self.rotation * self.step;
-        let rotation_uniform: PodMatrix = self.rotation.into();
-        self.rotation_uniform = rotation_uniform;
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        if self.paused || dt.is_zero() {
+            return;
+        }
+        let (x, y) = self.angles;
+        self.set_angles(queue, (x + angle_at(self.speed.0, dt), y + angle_at(self.speed.1, dt)));
+    }
+
+    fn matrix((x, y): (Deg<f32>, Deg<f32>)) -> Matrix4<f32> {
+        Matrix4::from_angle_x(x) * Matrix4::from_angle_y(y)
+    }
+
+    fn set_angles(&mut self, queue: &wgpu::Queue, (x, y): (Deg<f32>, Deg<f32>)) {
+        self.angles = (x.normalize(), y.normalize());
+        self.rotation_uniform = Self::matrix(self.angles).into();
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.rotation_uniform]))
     }
+
+    // Sets the rotation to where it is `time` after the start, see State::set_deterministic. A paused rotation stays where it is.
+    pub(crate) fn set_time(&mut self, queue: &wgpu::Queue, time: Duration) {
+        if self.paused {
+            return;
+        }
+        self.set_angles(queue, (angle_at(self.speed.0, time), angle_at(self.speed.1, time)));
+    }
+}
+
+// Of something turning at `degrees_per_second` for `time`, in double precision before
+// wrapping it to a turn so late times don't lose the fraction
+fn angle_at(degrees_per_second: f32, time: Duration) -> Deg<f32> {
+    Deg((degrees_per_second as f64 * time.as_secs_f64()).rem_euclid(360.0) as f32)
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct PodMatrix {
+pub(crate) struct PodMatrix {
     m: [[f32; 4]; 4],
 }
 
 impl From<Matrix4<f32>> for PodMatrix {
     fn from(value: Matrix4<f32>) -> Self {
-        return PodMatrix {
+        PodMatrix {
             m: value.into(),
         }
     }
 }
 
+// Element of the instances buffer, 96 bytes so every element stays 16 byte aligned
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct InstanceRaw {
+    transform: [[f32; 4]; 4],
+    color: [f32; 4],
+    layer: u32,
+    _padding: [u32; 3],
+}
+
+// Element of the spins storage buffer used by the GPU animation
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpinRaw {
+    base: [[f32; 4]; 4],
+    axis: [f32; 3],
+    radians_per_second: f32,
+    angle: f32,
+    // Array elements are 16 byte aligned
+    _padding: [f32; 3],
+}
+
+// Spins advanced on the GPU, see InstanceAnimation
+struct GpuSpins {
+    buffer: wgpu::Buffer,
+    // Instances and spins, bound together for the compute pass
+    bind_group: wgpu::BindGroup,
+}
+
+const DEFAULT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Of the sphere around the unit cube, the mesh until `set_mesh`
+const BOUNDING_RADIUS: f32 = 0.866_025_4;
+
+// Rotation of a single instance around its own center
+#[derive(Debug, Copy, Clone)]
+pub struct Spin {
+    pub axis: Vector3<f32>,
+    pub degrees_per_second: f32,
+    pub angle: Deg<f32>,
+}
+
+impl Spin {
+    pub const NONE: Spin = Spin { axis: Vector3::new(0.0, 1.0, 0.0), degrees_per_second: 0.0, angle: Deg(0.0) };
+
+    fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_axis_angle(self.axis, self.angle)
+    }
+}
+
+// Grid of mesh copies, each with its own transformation, tint, spin and texture layer
 pub struct Instances {
-    pub transformations: Vec<cgmath::Matrix4<f32>>,
-    pub layout: wgpu::BindGroupLayout,
-    pub buffer: wgpu::Buffer,
-    pub bind_group: wgpu::BindGroup,
+    transformations: Vec<cgmath::Matrix4<f32>>,
+    // RGBA tint multiplied into the texture color, one per transformation
+    colors: Vec<[f32; 4]>,
+    // Applied on top of the transformation, one per transformation
+    spins: Vec<Spin>,
+    // Layer of the texture array sampled, one per transformation
+    layers: Vec<u32>,
+    // Empty when the vertex stage can't read storage buffers, the shaders then
+    // take the instances as vertex attributes, see `as_vertex_buffer_layout`
+    pub(crate) layout: wgpu::BindGroupLayout,
+    vertex_storage: bool,
+    buffer: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    pub(crate) animation_layout: wgpu::BindGroupLayout,
+    // Some while the spins are animated on the GPU
+    gpu_spins: Option<GpuSpins>,
+    // Indices into the vectors above in the order the instances are uploaded and drawn
+    order: Vec<usize>,
+    // Set by `cull` for instances outside the view, they are ordered after the others
+    culled: Vec<bool>,
+    // Leading instances of `order` that aren't culled
+    visible_count: u32,
+    // DrawIndexedIndirectArgs for drawing all instances of the selected mesh
+    indirect_buffer: wgpu::Buffer,
+    index_count: u32,
+    // Bounding radius of the mesh drawn for every instance
+    mesh_radius: f32,
+    rows: u32,
+    cols: u32,
+    spacing: f32,
+    // Min and max corner of the box enclosing all instances, recomputed by set_grid
+    bounds: ([f32; 3], [f32; 3]),
+    // Set when instance data changed and the buffer has to be uploaded again
+    dirty: bool,
+    // Indices of the transforms handed out by `transform_mut` since the last commit
+    dirty_range: Option<Range<usize>>,
 }
 
 impl Instances {
-    fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    fn layout(device: &wgpu::Device, vertex_storage: bool) -> wgpu::BindGroupLayout {
+        let entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("instances_bind_group_layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+            entries: if vertex_storage { std::slice::from_ref(&entry) } else { &[] },
+        })
+    }
+
+    // Vertex entry point and buffers of a pipeline drawing instances of a mesh. `vs_main` reads
+    // the instances from storage, `vs_main_instanced` from the instances buffer in slot 1.
+    pub(crate) fn vertex_input(vertex_buffer: bool) -> (&'static str, Vec<wgpu::VertexBufferLayout<'static>>) {
+        if vertex_buffer {
+            ("vs_main_instanced", vec![Vertex::desc(), Self::as_vertex_buffer_layout()])
+        } else {
+            ("vs_main", vec![Vertex::desc()])
+        }
+    }
+
+    // The instances buffer bound as a second vertex buffer, the transform's
+    // columns go to locations 5 to 8, the color to location 9 and the layer to 10
+    pub fn as_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+            10 => Uint32,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+
+    fn animation_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("instances_animation_bind_group_layout"),
+            entries: &[storage_entry(0), storage_entry(1)],
         })
     }
 
     pub fn count(&self) -> u32 {
-        return self.transformations.len() as u32;
-    }
-
-    pub fn new(device: &wgpu::Device) -> Self {
-        let per_row = 4i32;
-        let per_col = 4i32;
-        let count = (per_col * per_row) as usize;
-        let dx = 2.0f32;
-        let dy = 2.0f32;
-        let mut transformations = Vec::with_capacity(count);
-        for i in 0..=per_row {
-            for j in 0..per_col {
-                let x = (j - per_row / 2) as f32 * dx;
-                let y = (i - per_col / 2) as f32 * dy;
-                let m = Matrix4::from_translation(Vector3::new(x, y, 0f32));
-                transformations.push(m);
+        self.transformations.len() as u32
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.spacing
+    }
+
+    // `vertex_storage` tells whether the vertex stage can read storage buffers
+    pub(crate) fn new(device: &wgpu::Device, vertex_storage: bool) -> Self {
+        let (rows, cols, spacing) = (4, 4, 2.0);
+        Self::with_transforms(device, vertex_storage, Self::grid(rows, cols, spacing), (rows, cols, spacing))
+    }
+
+    // White instances without spins at the given transforms, an empty list draws nothing.
+    // Hand them to a State with `State::set_instances`.
+    pub fn from_transforms(device: &wgpu::Device, transforms: &[Matrix4<f32>]) -> Self {
+        let vertex_storage = device.limits().max_storage_buffers_per_shader_stage > 0;
+        Self::with_transforms(device, vertex_storage, transforms.to_vec(), (1, transforms.len() as u32, 0.0))
+    }
+
+    // `grid` is the rows, columns and spacing reported until the next `set_grid`
+    fn with_transforms(device: &wgpu::Device,
+                       vertex_storage: bool,
+                       transformations: Vec<Matrix4<f32>>,
+                       (rows, cols, spacing): (u32, u32, f32)) -> Self {
+        let colors = vec![DEFAULT_COLOR; transformations.len()];
+        let spins = vec![Spin::NONE; transformations.len()];
+        let layers = vec![0; transformations.len()];
+        let order = (0..transformations.len()).collect::<Vec<_>>();
+        let culled = vec![false; transformations.len()];
+        let visible_count = transformations.len() as u32;
+        let layout = Self::layout(device, vertex_storage);
+        let (buffer, bind_group) = Self::create_buffer(device, &layout, vertex_storage, &Self::raw(&transformations, &colors, &spins, &layers, &order));
+        let indirect_buffer = Self::create_indirect_buffer(device, 0, transformations.len() as u32);
+        let animation_layout = Self::animation_layout(device);
+        let bounds = Self::bounds_of(&transformations, BOUNDING_RADIUS);
+
+        Self {
+            transformations,
+            colors,
+            spins,
+            layers,
+            layout,
+            vertex_storage,
+            buffer,
+            bind_group,
+            animation_layout,
+            gpu_spins: None,
+            order,
+            culled,
+            visible_count,
+            indirect_buffer,
+            index_count: 0,
+            mesh_radius: BOUNDING_RADIUS,
+            rows,
+            cols,
+            spacing,
+            bounds,
+            dirty: false,
+            dirty_range: None,
+        }
+    }
+
+    // Transforms in the order of the instance indices, without the spins
+    pub fn transforms(&self) -> &[Matrix4<f32>] {
+        &self.transformations
+    }
+
+    // The change is uploaded by the next `commit`, None if `index` is out of range
+    pub fn transform_mut(&mut self, index: usize) -> Option<&mut Matrix4<f32>> {
+        let transformation = self.transformations.get_mut(index)?;
+        self.dirty_range = Some(match self.dirty_range.take() {
+            Some(range) => range.start.min(index)..range.end.max(index + 1),
+            None => index..index + 1,
+        });
+        Some(transformation)
+    }
+
+    pub fn color(&self, index: usize) -> [f32; 4] {
+        self.colors[index]
+    }
+
+    pub fn set_color(&mut self, index: usize, color: [f32; 4]) {
+        self.colors[index] = color;
+        self.dirty = true;
+    }
+
+    pub fn layer(&self, index: usize) -> u32 {
+        self.layers[index]
+    }
+
+    // Layer of the texture array the instance samples, see State::load_texture_array.
+    // Layers past the last one sample the last one.
+    pub fn set_layer(&mut self, index: usize, layer: u32) {
+        self.layers[index] = layer;
+        self.dirty = true;
+    }
+
+    pub(crate) fn is_spinning(&self) -> bool {
+        self.spins.iter().any(|spin| spin.degrees_per_second != 0.0)
+    }
+
+    pub fn spin(&self, index: usize) -> Spin {
+        self.spins[index]
+    }
+
+    pub fn set_spin(&mut self, index: usize, spin: Spin) {
+        self.spins[index] = spin;
+        self.dirty = true;
+    }
+
+    // Gives every instance a spin around a random axis, the same seed always produces the same spins
+    pub fn randomize_spins(&mut self, seed: u64) {
+        let mut random = SplitMix64(seed);
+        for spin in self.spins.iter_mut() {
+            let axis = Vector3::new(random.next_signed(), random.next_signed(), random.next_signed());
+            // Degenerate axes are practically impossible but would produce NaNs
+            spin.axis = if axis.magnitude2() > 1e-6 { axis.normalize() } else { Vector3::unit_y() };
+            spin.degrees_per_second = 30.0 + random.next_f32() * 150.0;
+        }
+        self.dirty = true;
+    }
+
+    // Advances the spins by `dt` and uploads instance data changed since the last call.
+    // With GPU animation the spins are still advanced here to keep them in sync, but
+    // the transforms are only rebuilt by the compute pass.
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        let gpu_animated = self.gpu_spins.is_some();
+        if gpu_animated {
+            // The compute pass advances the uploaded angles by `dt` itself
+            self.commit(queue);
+        }
+        for spin in self.spins.iter_mut().filter(|s| s.degrees_per_second != 0.0 && !dt.is_zero()) {
+            spin.angle = (spin.angle + Deg(spin.degrees_per_second * dt.as_secs_f32())).normalize();
+            self.dirty |= !gpu_animated;
+        }
+        if !gpu_animated {
+            self.commit(queue);
+        }
+    }
+
+    // Sets every spin's angle to where it is `time` after starting from 0 and uploads the
+    // instances, see State::set_deterministic
+    pub(crate) fn set_time(&mut self, queue: &wgpu::Queue, time: Duration) {
+        for spin in self.spins.iter_mut() {
+            let angle = angle_at(spin.degrees_per_second, time);
+            if angle != spin.angle {
+                spin.angle = angle;
+                self.dirty = true;
             }
         }
-        let layout = Self::layout(device);
-        let pod_transformations: Vec<PodMatrix> = transformations.iter().map(|t| {
-            (*t).into()
-        }).collect();
+        self.commit(queue);
+    }
+
+    // Uploads instance data changed since the last call. State::update commits its
+    // instances every frame, call it directly for instances that aren't handed to a State.
+    pub fn commit(&mut self, queue: &wgpu::Queue) {
+        if let Some(range) = self.dirty_range.take() {
+            self.bounds = Self::bounds_of(&self.transformations, self.mesh_radius);
+            // Sorted instances are scattered over the buffer, upload them all
+            let in_order = self.order.iter().enumerate().all(|(position, i)| position == *i);
+            if in_order && !self.dirty {
+                let order = &self.order[range.clone()];
+                let raw = Self::raw(&self.transformations, &self.colors, &self.spins, &self.layers, order);
+                let offset = (range.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+                queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&raw));
+                if let Some(gpu_spins) = &self.gpu_spins {
+                    let spins_raw = Self::spins_raw(&self.transformations, &self.spins, order);
+                    let offset = (range.start * std::mem::size_of::<SpinRaw>()) as wgpu::BufferAddress;
+                    queue.write_buffer(&gpu_spins.buffer, offset, bytemuck::cast_slice(&spins_raw));
+                }
+            } else {
+                self.dirty = true;
+            }
+        }
+        if self.dirty {
+            let raw = Self::raw(&self.transformations, &self.colors, &self.spins, &self.layers, &self.order);
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw));
+            if let Some(gpu_spins) = &self.gpu_spins {
+                let spins_raw = Self::spins_raw(&self.transformations, &self.spins, &self.order);
+                queue.write_buffer(&gpu_spins.buffer, 0, bytemuck::cast_slice(&spins_raw));
+            }
+            self.dirty = false;
+        }
+    }
+
+    pub(crate) fn is_gpu_animated(&self) -> bool {
+        self.gpu_spins.is_some()
+    }
+
+    // Bind group for InstanceAnimation, None unless animated on the GPU
+    pub(crate) fn animation_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.gpu_spins.as_ref().map(|gpu_spins| &gpu_spins.bind_group)
+    }
+
+    pub(crate) fn set_gpu_animated(&mut self, device: &wgpu::Device, on: bool) {
+        if on == self.is_gpu_animated() {
+            return;
+        }
+        self.gpu_spins = on.then(|| self.create_gpu_spins(device));
+        // Rebuild the transforms from the current CPU spins
+        self.dirty = true;
+    }
 
+    fn create_gpu_spins(&self, device: &wgpu::Device) -> GpuSpins {
+        let mut spins_raw = Self::spins_raw(&self.transformations, &self.spins, &self.order);
+        // Like the instances buffer it can't be empty
+        if spins_raw.is_empty() {
+            spins_raw.push(bytemuck::Zeroable::zeroed());
+        }
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instances Buffer"),
-            contents: bytemuck::cast_slice(pod_transformations.as_slice()),
+            label: Some("Instance Spins Buffer"),
+            contents: bytemuck::cast_slice(&spins_raw),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
-
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &layout,
+            layout: &self.animation_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
+                    resource: self.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
                     resource: buffer.as_entire_binding(),
-                }
+                },
             ],
-            label: Some("instances_bind_group"),
+            label: Some("instances_animation_bind_group"),
         });
+        GpuSpins { buffer, bind_group }
+    }
 
-        Self {
-            transformations,
-            layout,
-            buffer,
-            bind_group
+    fn spins_raw(transformations: &[Matrix4<f32>], spins: &[Spin], order: &[usize]) -> Vec<SpinRaw> {
+        order.iter().map(|&i| SpinRaw {
+            base: transformations[i].into(),
+            axis: spins[i].axis.into(),
+            radians_per_second: spins[i].degrees_per_second.to_radians(),
+            angle: Rad::from(spins[i].angle).0,
+            _padding: [0.0; 3],
+        }).collect()
+    }
+
+    fn raw(transformations: &[Matrix4<f32>], colors: &[[f32; 4]], spins: &[Spin], layers: &[u32], order: &[usize]) -> Vec<InstanceRaw> {
+        order.iter().map(|&i| InstanceRaw {
+            transform: (transformations[i] * spins[i].matrix()).into(),
+            color: colors[i],
+            layer: layers[i],
+            _padding: [0; 3],
+        }).collect()
+    }
+
+    // Orders the instances by decreasing distance of their centers to `eye`,
+    // so blended instances are drawn over the ones behind them
+    pub(crate) fn sort_back_to_front(&mut self, eye: cgmath::Point3<f32>) {
+        let distances: Vec<f32> = self.transformations.iter()
+            .map(|t| (t.w.truncate() - eye.to_vec()).magnitude2())
+            .collect();
+        let mut order = self.order.clone();
+        order.sort_by(|a, b| distances[*b].total_cmp(&distances[*a]));
+        self.set_order(order);
+    }
+
+    // Back to the grid order
+    pub(crate) fn reset_order(&mut self) {
+        self.set_order((0..self.transformations.len()).collect());
+    }
+
+    // Keeps the culled instances last, in the order they have in `order`
+    fn set_order(&mut self, mut order: Vec<usize>) {
+        order.sort_by_key(|&i| self.culled[i]);
+        if order != self.order {
+            self.order = order;
+            self.dirty = true;
+        }
+    }
+
+    // Culls the instances whose bounding sphere is outside of all the frusta, they are
+    // left out of the draws of visible_count instances. The shadow map still draws them
+    // all, instances outside the view can cast shadows into it.
+    pub(crate) fn cull(&mut self, queue: &wgpu::Queue, view_projections: &[Matrix4<f32>]) {
+        let frusta: Vec<[Vector4<f32>; 6]> = view_projections.iter().map(|m| frustum_planes(*m)).collect();
+        self.culled = self.transformations.iter()
+            .map(|t| {
+                let center = t.w.truncate().extend(1.0);
+                let radius = max_scale(t) * self.mesh_radius;
+                !frusta.iter().any(|planes| {
+                    planes.iter().all(|plane| plane.dot(center) >= -radius * plane.truncate().magnitude())
+                })
+            })
+            .collect();
+        self.set_order(self.order.clone());
+        self.set_visible_count(queue);
+    }
+
+    // Draws all instances again
+    pub(crate) fn uncull(&mut self, queue: &wgpu::Queue) {
+        self.culled.fill(false);
+        self.set_visible_count(queue);
+    }
+
+    fn set_visible_count(&mut self, queue: &wgpu::Queue) {
+        let visible_count = self.culled.iter().filter(|culled| !**culled).count() as u32;
+        if visible_count != self.visible_count {
+            self.visible_count = visible_count;
+            queue.write_buffer(&self.indirect_buffer, 0, Self::indirect_args(self.index_count, visible_count).as_bytes());
+        }
+    }
+
+    // Instances drawn for the camera, the first ones in the buffer
+    pub(crate) fn visible_count(&self) -> u32 {
+        self.visible_count
+    }
+
+    // Replaces the instances with white ones without spins on layer 0 at the given transforms,
+    // the grid is then reported as a single row
+    pub fn set_transforms(&mut self, device: &wgpu::Device, transforms: &[Matrix4<f32>]) {
+        self.rows = 1;
+        self.cols = transforms.len() as u32;
+        self.spacing = 0.0;
+        self.colors = vec![DEFAULT_COLOR; transforms.len()];
+        self.spins = vec![Spin::NONE; transforms.len()];
+        self.layers = vec![0; transforms.len()];
+        self.replace_transformations(device, transforms.to_vec());
+    }
+
+    // Transforms of all instances as a JSON array of 4x4 arrays, one inner array per column
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let transforms: Vec<[[f32; 4]; 4]> = self.transformations.iter().map(|t| (*t).into()).collect();
+        serde_json::to_string(&transforms).expect("arrays of floats always serialize")
+    }
+
+    // Instances at the transforms written by `to_json`
+    #[cfg(feature = "serde")]
+    pub fn from_json(device: &wgpu::Device, json: &str) -> Result<Self, serde_json::Error> {
+        let transforms: Vec<[[f32; 4]; 4]> = serde_json::from_str(json)?;
+        let transforms: Vec<Matrix4<f32>> = transforms.into_iter().map(Matrix4::from).collect();
+        Ok(Self::from_transforms(device, &transforms))
+    }
+
+    // Regenerates the instances, rows and cols are clamped to at least 1
+    pub fn set_grid(&mut self, device: &wgpu::Device, rows: u32, cols: u32, spacing: f32) {
+        self.rows = rows.max(1);
+        self.cols = cols.max(1);
+        self.spacing = spacing;
+        let transformations = Self::grid(self.rows, self.cols, spacing);
+        self.colors.resize(transformations.len(), DEFAULT_COLOR);
+        self.spins.resize(transformations.len(), Spin::NONE);
+        self.layers.resize(transformations.len(), 0);
+        self.replace_transformations(device, transformations);
+    }
+
+    // Recreates the layout and the buffers when switching between reading the instances
+    // from storage in the vertex stage and not
+    pub(crate) fn set_vertex_storage(&mut self, device: &wgpu::Device, vertex_storage: bool) {
+        if vertex_storage == self.vertex_storage {
+            return;
+        }
+        self.vertex_storage = vertex_storage;
+        self.layout = Self::layout(device, vertex_storage);
+        let transformations = std::mem::take(&mut self.transformations);
+        self.replace_transformations(device, transformations);
+    }
+
+    // Recreates the buffers for new transformations, colors, spins and layers have to match them already
+    fn replace_transformations(&mut self, device: &wgpu::Device, transformations: Vec<Matrix4<f32>>) {
+        self.transformations = transformations;
+        self.bounds = Self::bounds_of(&self.transformations, self.mesh_radius);
+        self.order = (0..self.transformations.len()).collect();
+        self.culled = vec![false; self.transformations.len()];
+        self.visible_count = self.count();
+        (self.buffer, self.bind_group) = Self::create_buffer(device, &self.layout, self.vertex_storage, &Self::raw(&self.transformations, &self.colors, &self.spins, &self.layers, &self.order));
+        self.indirect_buffer = Self::create_indirect_buffer(device, self.index_count, self.count());
+        if self.gpu_spins.is_some() {
+            self.gpu_spins = Some(self.create_gpu_spins(device));
+        }
+        self.dirty = false;
+        self.dirty_range = None;
+    }
+
+    // Arguments for draw_indexed_indirect, kept in sync with the instance count
+    pub fn indirect_buffer(&self) -> &wgpu::Buffer {
+        &self.indirect_buffer
+    }
+
+    // The mesh drawn for every instance, its indices and its bounds
+    pub(crate) fn set_mesh(&mut self, queue: &wgpu::Queue, mesh: &Mesh) {
+        if self.mesh_radius != mesh.bounding_radius() {
+            self.mesh_radius = mesh.bounding_radius();
+            self.bounds = Self::bounds_of(&self.transformations, self.mesh_radius);
+        }
+        let index_count = mesh.num_indices();
+        if self.index_count != index_count {
+            self.index_count = index_count;
+            queue.write_buffer(&self.indirect_buffer, 0, Self::indirect_args(index_count, self.visible_count).as_bytes());
         }
     }
-}
\ No newline at end of file
+
+    fn indirect_args(index_count: u32, instance_count: u32) -> wgpu::util::DrawIndexedIndirectArgs {
+        wgpu::util::DrawIndexedIndirectArgs {
+            index_count,
+            instance_count,
+            first_index: 0,
+            base_vertex: 0,
+            // Non zero values need Features::INDIRECT_FIRST_INSTANCE
+            first_instance: 0,
+        }
+    }
+
+    fn create_indirect_buffer(device: &wgpu::Device, index_count: u32, instance_count: u32) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instances Indirect Buffer"),
+            contents: Self::indirect_args(index_count, instance_count).as_bytes(),
+            usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    // Drawn from as a vertex buffer by the pipelines that take the instances as vertex attributes
+    pub(crate) fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    // Buffer and bind group with a single instance, for objects that aren't part of the grid
+    pub(crate) fn single_instance(&self, device: &wgpu::Device, transform: Matrix4<f32>, color: [f32; 4]) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let raw = InstanceRaw { transform: transform.into(), color, layer: 0, _padding: [0; 3] };
+        Self::create_buffer(device, &self.layout, self.vertex_storage, &[raw])
+    }
+
+    pub(crate) fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        self.bounds
+    }
+
+    // Every instance is treated as the mesh's bounding sphere scaled by the transform, so
+    // the box holds however the rotation and the spins turn them
+    fn bounds_of(transformations: &[Matrix4<f32>], mesh_radius: f32) -> ([f32; 3], [f32; 3]) {
+        if transformations.is_empty() {
+            return ([0.0; 3], [0.0; 3]);
+        }
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for transformation in transformations {
+            let center = transformation.w.truncate();
+            let radius = max_scale(transformation) * mesh_radius;
+            for axis in 0..3 {
+                min[axis] = min[axis].min(center[axis] - radius);
+                max[axis] = max[axis].max(center[axis] + radius);
+            }
+        }
+        (min, max)
+    }
+
+    fn grid(rows: u32, cols: u32, spacing: f32) -> Vec<Matrix4<f32>> {
+        let mut transformations = Vec::with_capacity((rows * cols) as usize);
+        // Offsets of the first row/column so the grid is centered around the origin
+        let x0 = (cols - 1) as f32 / 2.0;
+        let y0 = (rows - 1) as f32 / 2.0;
+        for i in 0..rows {
+            for j in 0..cols {
+                let x = (j as f32 - x0) * spacing;
+                let y = (i as f32 - y0) * spacing;
+                let m = Matrix4::from_translation(Vector3::new(x, y, 0f32));
+                transformations.push(m);
+            }
+        }
+        transformations
+    }
+
+    fn create_buffer(device: &wgpu::Device,
+                     layout: &BindGroupLayout,
+                     vertex_storage: bool,
+                     raw: &[InstanceRaw]) -> (wgpu::Buffer, wgpu::BindGroup) {
+        // The GPU animation writes the buffer as storage even where the vertex stage can't read it
+        let storage = if device.limits().max_storage_buffers_per_shader_stage > 0 {
+            wgpu::BufferUsages::STORAGE
+        } else {
+            wgpu::BufferUsages::empty()
+        };
+        // Empty buffers can't be bound, without instances there is a placeholder that isn't drawn
+        let placeholder: [InstanceRaw; 1] = [bytemuck::Zeroable::zeroed()];
+        let raw = if raw.is_empty() { &placeholder } else { raw };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instances Buffer"),
+            contents: bytemuck::cast_slice(raw),
+            usage: storage | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let entry = wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: if vertex_storage { std::slice::from_ref(&entry) } else { &[] },
+            label: Some("instances_bind_group"),
+        });
+
+        (buffer, bind_group)
+    }
+}
+
+// Left, right, bottom, top, near and far planes of the clip space volume of wgpu, with
+// depth from 0 to 1, in world space. Points inside have a non negative dot product with
+// each of them.
+fn frustum_planes(view_projection: Matrix4<f32>) -> [Vector4<f32>; 6] {
+    let row = |i: usize| view_projection.row(i);
+    [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(2),
+        row(3) - row(2),
+    ]
+}
+
+// Largest factor the transform scales a length by, of any of its axes
+fn max_scale(transform: &Matrix4<f32>) -> f32 {
+    transform.x.truncate().magnitude()
+        .max(transform.y.truncate().magnitude())
+        .max(transform.z.truncate().magnitude())
+}
+
+// Small deterministic generator, good enough to scatter spins without pulling in a dependency
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in 0..1
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // Uniform in -1..1
+    fn next_signed(&mut self) -> f32 {
+        self.next_f32() * 2.0 - 1.0
+    }
+}
@@ -4,11 +4,40 @@ mod camera;
 mod instances;
 mod mesh;
 mod depth_view;
+mod depth_readback;
+mod light;
+mod frame_timer;
+mod profiler;
+mod ground_grid;
+mod bounds_box;
+mod axis_gizmo;
+mod bloom;
+mod fxaa;
+mod ssao;
+mod outline;
+mod instance_animation;
+mod particles;
+mod shadow_map;
+mod skybox;
+mod tonemap;
+mod recorder;
+mod render_graph;
+#[cfg(feature = "egui")]
+mod egui_overlay;
 
-use state::State;
-use winit::{event::*, event_loop::{ControlFlow, EventLoop}, keyboard, window::WindowBuilder};
-use winit::event::WindowEvent::KeyboardInput;
-use winit::keyboard::{KeyCode, PhysicalKey};
+pub use state::{AlphaModeError, BackgroundMode, DepthFormatError, DepthSettings, MeshIndexError, OverlayRenderer, PresentModeError, ShaderConstantError, RenderMode, ShadingMode, Settings, State, StateConfig, StateInitError};
+pub use axis_gizmo::Corner;
+pub use depth_view::Colormap;
+pub use camera::{CameraController, CameraMode, CameraModel, CameraState, KeyBindings, Projection, Viewpoint};
+pub use mesh::{Mesh, MeshError};
+pub use texture::{Cubemap, SamplerConfig, Texture, TextureError};
+pub use profiler::PassTimings;
+pub use tonemap::ToneMapOp;
+pub use instances::{Instances, Rotation, Spin};
+pub use render_graph::{PassId, RenderGraph, RenderGraphError, Resource};
+pub use light::{Light, LightKind, MAX_UNIFORM_LIGHTS};
+use winit::{event::*, event_loop::EventLoop, keyboard, window::WindowBuilder};
+use winit::keyboard::KeyCode;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
@@ -46,47 +75,69 @@ pub async fn run() {
         log::warn!("Setup canvas");
     }
 
-    let mut state = State::new(&window).await;
+    let mut state = match State::new(&window, Settings::default()).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Failed to initialize the renderer: {}", e);
+            return;
+        }
+    };
 
     event_loop.run(move |event, control_flow| {
         match event {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == state.window().id() => {
-                if !state.input(event) { // UPDATED!
-                    match event {
-                        WindowEvent::RedrawRequested => {
-                            state.window().request_redraw();
-                            state.update();
-                            match state.render() {
-                                Ok(_) => {}
-                                // Reconfigure the surface if lost
-                                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                                // The system is out of memory, we should probably quit
-                                Err(wgpu::SurfaceError::OutOfMemory) => control_flow.exit(),
-                                // All other errors (Outdated, Timeout) should be resolved by the next frame
-                                Err(e) => eprintln!("{:?}", e),
-                            }
+            } if Some(window_id) == state.window().map(|w| w.id()) => {
+                // Input may change the scene, which only keeps redrawing while it animates
+                if !matches!(event, WindowEvent::RedrawRequested) {
+                    state.window().unwrap().request_redraw();
+                }
+                if state.input(event) {
+                    return;
+                }
+                match event {
+                    WindowEvent::RedrawRequested => {
+                        state.update();
+                        match state.render() {
+                            Ok(_) => {}
+                            // The system is out of memory, we should probably quit
+                            Err(wgpu::SurfaceError::OutOfMemory) => control_flow.exit(),
+                            // Timeout should be resolved by the next frame
+                            Err(e) => eprintln!("{:?}", e),
                         }
-
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            event:
-                                KeyEvent {
-                                    state: ElementState::Pressed,
-                                    physical_key: keyboard::PhysicalKey::Code(KeyCode::Escape),
-                                    ..
-                                },
-                            ..
-                        } => control_flow.exit(),
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
+                        // Nothing renders while hidden, Occluded(false) and Resized request the next frame
+                        if state.render_mode() == RenderMode::Continuous && state.is_visible() {
+                            state.window().unwrap().request_redraw();
                         }
-                        _ => {}
                     }
+
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                state: ElementState::Pressed,
+                                physical_key: keyboard::PhysicalKey::Code(KeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => control_flow.exit(),
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    }
+                    WindowEvent::Occluded(occluded) => {
+                        state.set_visible(!occluded);
+                    }
+                    _ => {}
                 }
             }
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } if state.process_mouse_motion(delta) => {
+                state.window().unwrap().request_redraw();
+            }
+            Event::LoopExiting => state.shutdown(),
             _ => {}
         }
     }).unwrap();
@@ -0,0 +1,353 @@
+use std::borrow::Cow;
+
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt;
+use wgpu::BindGroupLayout;
+
+use crate::camera::ORTHO_OPENGL_TO_WGPU_MATRIX;
+use crate::shadow_map::ShadowMap;
+
+// Half size of the box covered by the shadow map, large enough for the ground plane
+const SHADOW_EXTENT: f32 = 15.0;
+// Distance of the light's eye from the origin
+const SHADOW_DISTANCE: f32 = 30.0;
+// Lights of `set_lights` the uniform buffer holds where storage buffers aren't supported, e.g. on WebGL
+pub const MAX_UNIFORM_LIGHTS: usize = 16;
+const DEFAULT_SHININESS: f32 = 32.0;
+const DEFAULT_SPECULAR_STRENGTH: f32 = 0.5;
+// The declaration of shaders.wgsl, replaced with a fixed size uniform array without storage buffers
+const STORAGE_LIGHTS_DECLARATION: &str = "var<storage, read> lights: array<Light>;";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    // Fades with the squared distance
+    Point { position: Point3<f32> },
+    // Travels along the direction, like the main light
+    Directional { direction: Vector3<f32> },
+}
+
+// Additional light on top of the main directional light, see State::set_lights
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn point(position: Point3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self { kind: LightKind::Point { position }, color, intensity }
+    }
+
+    pub fn directional(direction: Vector3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self { kind: LightKind::Directional { direction: direction.normalize() }, color, intensity }
+    }
+
+    fn to_raw(self) -> LightRaw {
+        let (position, kind) = match self.kind {
+            LightKind::Point { position } => (position.into(), 0),
+            LightKind::Directional { direction } => (direction.into(), 1),
+        };
+        LightRaw { position, kind, color: self.color, intensity: self.intensity }
+    }
+}
+
+// Element of the lights buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    // The direction for directional lights
+    position: [f32; 3],
+    // 0 for point, 1 for directional lights
+    kind: u32,
+    color: [f32; 3],
+    intensity: f32,
+}
+
+// Storage buffers in the fragment stage hold any number of lights
+pub(crate) fn lights_storage_supported(device: &wgpu::Device) -> bool {
+    device.limits().max_storage_buffers_per_shader_stage > 0
+}
+
+// Declares the lights as a uniform array when they can't be in a storage buffer
+pub(crate) fn scene_shader_source<'a>(device: &wgpu::Device, source: &'a str) -> Cow<'a, str> {
+    if lights_storage_supported(device) {
+        Cow::Borrowed(source)
+    } else {
+        let declaration = format!("var<uniform> lights: array<Light, {}>;", MAX_UNIFORM_LIGHTS);
+        Cow::Owned(source.replace(STORAGE_LIGHTS_DECLARATION, &declaration))
+    }
+}
+
+// We need this for Rust to store our data correctly for the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    // Light-space view projection of the shadow map
+    view_proj: [[f32; 4]; 4],
+    // Direction the light travels in, vec3 is 16 byte aligned in WGSL
+    direction: [f32; 3],
+    shadows: u32,
+    color: [f32; 3],
+    ambient: f32,
+    // Number of lights of `set_lights`
+    light_count: u32,
+    // Blinn-Phong exponent, higher values give smaller highlights
+    shininess: f32,
+    specular_strength: f32,
+    // 1 when the ambient term is multiplied by the ambient occlusion texture
+    ambient_occlusion: u32,
+}
+
+// The main directional light casting the shadows, and the additional lights
+pub struct LightState {
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub ambient: f32,
+    pub shadows: bool,
+    pub lights: Vec<Light>,
+    pub shininess: f32,
+    pub specular_strength: f32,
+    pub ambient_occlusion: bool,
+    pub uniform: LightUniform,
+    pub buffer: wgpu::Buffer,
+    // Holds at least one light, bindings can't be empty
+    lights_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    // Bound in the shadow pass, where the shadow map itself can't be bound
+    pub shadow_bind_group: wgpu::BindGroup,
+}
+
+impl LightState {
+    // `ambient_occlusion` is the texture of Ssao::view
+    pub fn new(device: &wgpu::Device,
+               queue: &wgpu::Queue,
+               layout: &BindGroupLayout,
+               shadow_map: &ShadowMap,
+               ambient_occlusion: &wgpu::TextureView) -> Self {
+        // Written below once all the fields are set
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: std::mem::size_of::<LightUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let lights_buffer = Self::create_lights_buffer(device, &[]);
+        let bind_group = Self::create_bind_group(device, layout, &buffer, &lights_buffer, shadow_map, ambient_occlusion);
+        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &shadow_map.light_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("light_shadow_bind_group"),
+        });
+
+        let mut light = Self {
+            direction: Vector3::new(-0.5f32, -1.0, -0.75).normalize(),
+            color: [1.0, 1.0, 1.0],
+            ambient: 0.15,
+            shadows: false,
+            lights: Vec::new(),
+            shininess: DEFAULT_SHININESS,
+            specular_strength: DEFAULT_SPECULAR_STRENGTH,
+            ambient_occlusion: false,
+            uniform: bytemuck::Zeroable::zeroed(),
+            buffer,
+            lights_buffer,
+            bind_group,
+            shadow_bind_group,
+        };
+        light.write(queue);
+        light
+    }
+
+    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("light_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    // The vertex stage needs the light-space view projection
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // Read with textureLoad at the fragment's position
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: if lights_storage_supported(device) {
+                            wgpu::BufferBindingType::Storage { read_only: true }
+                        } else {
+                            wgpu::BufferBindingType::Uniform
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_lights_buffer(device: &wgpu::Device, lights: &[Light]) -> wgpu::Buffer {
+        let (len, usage) = if lights_storage_supported(device) {
+            (lights.len().max(1), wgpu::BufferUsages::STORAGE)
+        } else {
+            (MAX_UNIFORM_LIGHTS, wgpu::BufferUsages::UNIFORM)
+        };
+        let mut raw = vec![<LightRaw as bytemuck::Zeroable>::zeroed(); len];
+        for (raw, light) in raw.iter_mut().zip(lights) {
+            *raw = light.to_raw();
+        }
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lights Buffer"),
+            contents: bytemuck::cast_slice(&raw),
+            usage,
+        })
+    }
+
+    fn create_bind_group(device: &wgpu::Device,
+                         layout: &BindGroupLayout,
+                         buffer: &wgpu::Buffer,
+                         lights_buffer: &wgpu::Buffer,
+                         shadow_map: &ShadowMap,
+                         ambient_occlusion: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(shadow_map.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(shadow_map.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: lights_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(ambient_occlusion),
+                },
+            ],
+            label: Some("light_bind_group"),
+        })
+    }
+
+    // Call after the shadow map or the ambient occlusion texture were recreated
+    pub fn recreate_bind_group(&mut self,
+                               device: &wgpu::Device,
+                               layout: &BindGroupLayout,
+                               shadow_map: &ShadowMap,
+                               ambient_occlusion: &wgpu::TextureView) {
+        self.bind_group = Self::create_bind_group(device, layout, &self.buffer, &self.lights_buffer, shadow_map, ambient_occlusion);
+    }
+
+    // Recreates the lights buffer and the bind group, only the first MAX_UNIFORM_LIGHTS
+    // are kept without storage buffers
+    pub fn set_lights(&mut self,
+                      device: &wgpu::Device,
+                      queue: &wgpu::Queue,
+                      layout: &BindGroupLayout,
+                      shadow_map: &ShadowMap,
+                      ambient_occlusion: &wgpu::TextureView,
+                      lights: &[Light]) {
+        let mut lights = lights.to_vec();
+        if !lights_storage_supported(device) && lights.len() > MAX_UNIFORM_LIGHTS {
+            log::warn!("Only {} of {} lights are used without storage buffers", MAX_UNIFORM_LIGHTS, lights.len());
+            lights.truncate(MAX_UNIFORM_LIGHTS);
+        }
+        self.lights_buffer = Self::create_lights_buffer(device, &lights);
+        self.lights = lights;
+        self.recreate_bind_group(device, layout, shadow_map, ambient_occlusion);
+        self.write(queue);
+    }
+
+    pub fn set_direction(&mut self, queue: &wgpu::Queue, direction: Vector3<f32>) {
+        self.direction = direction.normalize();
+        self.write(queue);
+    }
+
+    pub fn set_material(&mut self, queue: &wgpu::Queue, shininess: f32, specular_strength: f32) {
+        self.shininess = shininess;
+        self.specular_strength = specular_strength;
+        self.write(queue);
+    }
+
+    pub fn set_shadows(&mut self, queue: &wgpu::Queue, on: bool) {
+        self.shadows = on;
+        self.write(queue);
+    }
+
+    pub fn set_ambient_occlusion(&mut self, queue: &wgpu::Queue, on: bool) {
+        self.ambient_occlusion = on;
+        self.write(queue);
+    }
+
+    // Orthographic projection looking along the light direction at the origin
+    fn view_proj(direction: Vector3<f32>) -> Matrix4<f32> {
+        let eye = Point3::new(0.0, 0.0, 0.0) - direction * SHADOW_DISTANCE;
+        // look_at_rh degenerates when looking along the up vector
+        let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+        let view = Matrix4::look_at_rh(eye, Point3::new(0.0, 0.0, 0.0), up);
+        let proj = cgmath::ortho(-SHADOW_EXTENT, SHADOW_EXTENT, -SHADOW_EXTENT, SHADOW_EXTENT, 0.1, 2.0 * SHADOW_DISTANCE);
+        ORTHO_OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    fn write(&mut self, queue: &wgpu::Queue) {
+        self.uniform = LightUniform {
+            view_proj: Self::view_proj(self.direction).into(),
+            direction: self.direction.into(),
+            shadows: self.shadows as u32,
+            color: self.color,
+            ambient: self.ambient,
+            light_count: self.lights.len() as u32,
+            shininess: self.shininess,
+            specular_strength: self.specular_strength,
+            ambient_occlusion: self.ambient_occlusion as u32,
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
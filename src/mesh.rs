@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector3};
 use wgpu::Device;
 use wgpu::util::DeviceExt;
 
@@ -6,8 +11,13 @@ use wgpu::util::DeviceExt;
 pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    normal: [f32; 3],
+    // Only used by ShadingMode::VertexColor
+    color: [f32; 4],
 }
 
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
 impl Vertex {
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -24,20 +34,39 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
+// Indexed triangles, lines or points in the Vertex layout, uploaded to the GPU
 pub struct Mesh {
-    pub num_vertices: u32,
-    pub vertex_buffer: wgpu::Buffer,
-    pub num_indices: u32,
-    pub index_buffer: wgpu::Buffer
+    num_vertices: u32,
+    pub(crate) vertex_buffer: wgpu::Buffer,
+    num_indices: u32,
+    pub(crate) index_buffer: wgpu::Buffer,
+    index_format: wgpu::IndexFormat,
+    // Kept to rebuild the index buffer, see `flip_winding`
+    indices: Vec<u32>,
+    // One of the list topologies, strips aren't supported
+    topology: wgpu::PrimitiveTopology,
+    // Of the sphere around the origin holding all vertices
+    bounding_radius: f32,
 }
 
 impl Mesh {
-    pub(crate) fn new(device: &Device) -> Self {
+    // Unit cube around the origin with the whole texture on every face
+    pub fn cube(device: &Device) -> Self {
         let num_vertices = VERTICES.len() as u32;
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -56,38 +85,432 @@ impl Mesh {
             vertex_buffer,
             num_indices,
             index_buffer,
+            index_format: wgpu::IndexFormat::Uint16,
+            indices: INDICES.iter().map(|i| *i as u32).collect(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            bounding_radius: Self::bounding_radius_of(VERTICES),
+        }
+    }
+
+    pub fn num_vertices(&self) -> u32 {
+        self.num_vertices
+    }
+
+    pub fn num_indices(&self) -> u32 {
+        self.num_indices
+    }
+
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        self.index_format
+    }
+
+    pub fn topology(&self) -> wgpu::PrimitiveTopology {
+        self.topology
+    }
+
+    // Radius of the sphere around the origin holding all vertices, however the mesh is rotated
+    pub fn bounding_radius(&self) -> f32 {
+        self.bounding_radius
+    }
+
+    fn bounding_radius_of(vertices: &[Vertex]) -> f32 {
+        vertices.iter()
+            .map(|vertex| Vector3::from(vertex.position).magnitude())
+            .fold(0.0, f32::max)
+    }
+
+    // Point cloud with a color per point, drawn unlit
+    pub fn points(device: &Device, positions: &[[f32; 3]], colors: &[[f32; 4]]) -> Result<Mesh, MeshError> {
+        let indices: Vec<u32> = (0..positions.len() as u32).collect();
+        Ok(Self::from_vertices_with_topology(device, &Self::unlit_vertices(positions, colors)?, &indices, wgpu::PrimitiveTopology::PointList))
+    }
+
+    // Line segments between pairs of `indices`, with a color per position, drawn unlit
+    pub fn lines(device: &Device, positions: &[[f32; 3]], colors: &[[f32; 4]], indices: &[u32]) -> Result<Mesh, MeshError> {
+        if !indices.len().is_multiple_of(2) {
+            return Err(MeshError::OddLineIndices(indices.len()));
+        }
+        if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+            return Err(MeshError::IndexOutOfRange { index, count: positions.len() });
         }
+        Ok(Self::from_vertices_with_topology(device, &Self::unlit_vertices(positions, colors)?, indices, wgpu::PrimitiveTopology::LineList))
+    }
+
+    fn unlit_vertices(positions: &[[f32; 3]], colors: &[[f32; 4]]) -> Result<Vec<Vertex>, MeshError> {
+        if positions.len() != colors.len() {
+            return Err(MeshError::ColorCount { positions: positions.len(), colors: colors.len() });
+        }
+        Ok(positions.iter().zip(colors)
+            .map(|(position, color)| Vertex {
+                position: *position,
+                tex_coords: [0.0, 0.0],
+                normal: [0.0, 0.0, 0.0],
+                color: *color,
+            })
+            .collect())
+    }
+
+    // The pipelines treat counter-clockwise triangles as front faces, files written with
+    // `FrontFace::Cw` get their triangles flipped while loading
+    pub fn from_obj(device: &Device, path: &Path, winding: wgpu::FrontFace) -> Result<Mesh, MeshError> {
+        let source = std::fs::read_to_string(path)?;
+        let (vertices, indices) = parse_obj(&source, winding)?;
+        Ok(Self::from_vertices(device, &vertices, &indices))
+    }
+
+    // Swaps front and back faces by reversing the order of every triangle's indices.
+    // Does nothing for lines and points.
+    pub fn flip_winding(&mut self, device: &Device) {
+        if self.topology != wgpu::PrimitiveTopology::TriangleList {
+            return;
+        }
+        for triangle in self.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+        (self.index_buffer, self.index_format) = Self::create_index_buffer(device, &self.indices, self.num_vertices);
+    }
+
+    // Sphere of radius 0.5 around the origin, u goes around the equator and v from the north to the south pole.
+    // rings and sectors are clamped to at least 2 and 3.
+    pub fn uv_sphere(device: &Device, rings: u32, sectors: u32) -> Mesh {
+        use std::f32::consts::PI;
+        let rings = rings.max(2);
+        let sectors = sectors.max(3);
+        let mut vertices = Vec::with_capacity(((rings + 1) * (sectors + 1)) as usize);
+        // The first and last sector overlap, so the texture seam gets its own vertices
+        for i in 0..=rings {
+            let v = i as f32 / rings as f32;
+            let theta = v * PI;
+            for j in 0..=sectors {
+                let u = j as f32 / sectors as f32;
+                let phi = u * 2.0 * PI;
+                let normal = [theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos()];
+                vertices.push(Vertex {
+                    position: normal.map(|c| c * 0.5),
+                    tex_coords: [u, v],
+                    normal,
+                    color: WHITE,
+                });
+            }
+        }
+
+        let mut indices = Vec::with_capacity((rings * sectors * 6) as usize);
+        for i in 0..rings {
+            for j in 0..sectors {
+                let top_left = i * (sectors + 1) + j;
+                let bottom_left = top_left + sectors + 1;
+                // Counter-clockwise seen from outside, the triangles touching a pole are degenerate
+                if i != 0 {
+                    indices.extend_from_slice(&[top_left, bottom_left, top_left + 1]);
+                }
+                if i != rings - 1 {
+                    indices.extend_from_slice(&[top_left + 1, bottom_left, bottom_left + 1]);
+                }
+            }
+        }
+        Self::from_vertices(device, &vertices, &indices)
+    }
+
+    // size x size square on the XZ plane facing +y, split into subdivisions x subdivisions quads.
+    // The texture is stretched over the whole plane, see State::set_sampler_config to tile it.
+    pub fn plane(device: &Device, size: f32, subdivisions: u32) -> Mesh {
+        let subdivisions = subdivisions.max(1);
+        let mut vertices = Vec::with_capacity(((subdivisions + 1) * (subdivisions + 1)) as usize);
+        for i in 0..=subdivisions {
+            let v = i as f32 / subdivisions as f32;
+            for j in 0..=subdivisions {
+                let u = j as f32 / subdivisions as f32;
+                vertices.push(Vertex {
+                    position: [(u - 0.5) * size, 0.0, (v - 0.5) * size],
+                    tex_coords: [u, v],
+                    normal: [0.0, 1.0, 0.0],
+                    color: WHITE,
+                });
+            }
+        }
+
+        let mut indices = Vec::with_capacity((subdivisions * subdivisions * 6) as usize);
+        for i in 0..subdivisions {
+            for j in 0..subdivisions {
+                let top_left = i * (subdivisions + 1) + j;
+                let bottom_left = top_left + subdivisions + 1;
+                // Counter-clockwise seen from above
+                indices.extend_from_slice(&[top_left, bottom_left, top_left + 1]);
+                indices.extend_from_slice(&[top_left + 1, bottom_left, bottom_left + 1]);
+            }
+        }
+        Self::from_vertices(device, &vertices, &indices)
+    }
+
+    fn from_vertices(device: &Device, vertices: &[Vertex], indices: &[u32]) -> Mesh {
+        Self::from_vertices_with_topology(device, vertices, indices, wgpu::PrimitiveTopology::TriangleList)
+    }
+
+    fn from_vertices_with_topology(device: &Device, vertices: &[Vertex], indices: &[u32], topology: wgpu::PrimitiveTopology) -> Mesh {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let (index_buffer, index_format) = Self::create_index_buffer(device, indices, vertices.len() as u32);
+        Mesh {
+            num_vertices: vertices.len() as u32,
+            vertex_buffer,
+            num_indices: indices.len() as u32,
+            index_buffer,
+            index_format,
+            indices: indices.to_vec(),
+            topology,
+            bounding_radius: Self::bounding_radius_of(vertices),
+        }
+    }
+
+    fn create_index_buffer(device: &Device, indices: &[u32], num_vertices: u32) -> (wgpu::Buffer, wgpu::IndexFormat) {
+        // 16 bit indices take half the memory, use them whenever they can address every vertex.
+        // 0xFFFF is left out, GL always treats it as the primitive restart index.
+        let (index_bytes, index_format) = if num_vertices as usize <= u16::MAX as usize {
+            let indices: Vec<u16> = indices.iter().map(|i| *i as u16).collect();
+            (bytemuck::cast_slice(&indices).to_vec(), wgpu::IndexFormat::Uint16)
+        } else {
+            (bytemuck::cast_slice(indices).to_vec(), wgpu::IndexFormat::Uint32)
+        };
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: &index_bytes,
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        (index_buffer, index_format)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum MeshError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    TooManyVertices(usize),
+    // Line lists take pairs of indices
+    OddLineIndices(usize),
+    IndexOutOfRange { index: u32, count: usize },
+    // Unlit meshes need a color per position
+    ColorCount { positions: usize, colors: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Io(e) => write!(f, "failed to read OBJ file: {}", e),
+            MeshError::Parse { line, message } => write!(f, "OBJ parse error at line {}: {}", line, message),
+            MeshError::TooManyVertices(count) => write!(f, "OBJ mesh has {} vertices, at most {} are supported", count, u32::MAX as u64 + 1),
+            MeshError::OddLineIndices(count) => write!(f, "line list needs an even number of indices, got {}", count),
+            MeshError::IndexOutOfRange { index, count } => write!(f, "index {} is out of range, there are {} vertices", index, count),
+            MeshError::ColorCount { positions, colors } => write!(f, "{} positions but {} colors, every position needs one", positions, colors),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MeshError {
+    fn from(value: std::io::Error) -> Self {
+        MeshError::Io(value)
     }
 }
 
+// Parses `v`, `vt`, `vn` and `f` records, everything else is ignored. `v` records may
+// carry an RGB vertex color after the position, vertices without one are white.
+// Polygons are triangulated as a fan around their first vertex, counter-clockwise whatever the `winding` of the file.
+// Vertices without an explicit normal get the average of the adjacent face normals.
+fn parse_obj(source: &str, winding: wgpu::FrontFace) -> Result<(Vec<Vertex>, Vec<u32>), MeshError> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut colors: Vec<[f32; 4]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut computed_normals: Vec<bool> = Vec::new();
+    // Each distinct (position, tex_coords, normal) triple becomes one vertex
+    let mut known: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| MeshError::Parse { line: line_number, message };
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let v = parse_floats::<3>(&mut tokens).map_err(parse_error)?;
+                positions.push(v);
+                // A single value after the position is the optional w component
+                let rest: Vec<&str> = tokens.collect();
+                let color = if rest.len() >= 3 {
+                    let [r, g, b] = parse_floats::<3>(&mut rest.into_iter()).map_err(parse_error)?;
+                    [r, g, b, 1.0]
+                } else {
+                    WHITE
+                };
+                colors.push(color);
+            }
+            Some("vt") => {
+                let [u, v] = parse_floats::<2>(&mut tokens).map_err(parse_error)?;
+                // OBJ puts the origin of texture space in the bottom left corner, wgpu in the top left
+                tex_coords.push([u, 1.0 - v]);
+            }
+            Some("vn") => {
+                let n = parse_floats::<3>(&mut tokens).map_err(parse_error)?;
+                normals.push(n);
+            }
+            Some("f") => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let position = parts.next()
+                        .ok_or_else(|| parse_error(format!("empty face vertex `{}`", token)))
+                        .and_then(|p| resolve_index(p, positions.len()).map_err(parse_error))?;
+                    let tex_coord = match parts.next() {
+                        Some(t) if !t.is_empty() => Some(resolve_index(t, tex_coords.len()).map_err(parse_error)?),
+                        _ => None,
+                    };
+                    let normal = match parts.next() {
+                        Some(n) if !n.is_empty() => Some(resolve_index(n, normals.len()).map_err(parse_error)?),
+                        _ => None,
+                    };
+                    let index = match known.get(&(position, tex_coord, normal)) {
+                        Some(index) => *index,
+                        None => {
+                            let index = u32::try_from(vertices.len())
+                                .map_err(|_| MeshError::TooManyVertices(vertices.len() + 1))?;
+                            vertices.push(Vertex {
+                                position: positions[position],
+                                tex_coords: tex_coord.map_or([0.0, 0.0], |t| tex_coords[t]),
+                                normal: normal.map_or([0.0, 0.0, 0.0], |n| normals[n]),
+                                color: colors[position],
+                            });
+                            computed_normals.push(normal.is_none());
+                            known.insert((position, tex_coord, normal), index);
+                            index
+                        }
+                    };
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    return Err(parse_error(format!("face has {} vertices, at least 3 are required", face.len())));
+                }
+                for i in 1..face.len() - 1 {
+                    match winding {
+                        wgpu::FrontFace::Ccw => indices.extend_from_slice(&[face[0], face[i], face[i + 1]]),
+                        wgpu::FrontFace::Cw => indices.extend_from_slice(&[face[0], face[i + 1], face[i]]),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+        // Not normalized, so bigger faces contribute more
+        let face_normal = (b - a).cross(c - a);
+        for &index in triangle {
+            if computed_normals[index as usize] {
+                let normal = Vector3::from(vertices[index as usize].normal) + face_normal;
+                vertices[index as usize].normal = normal.into();
+            }
+        }
+    }
+    for (vertex, computed) in vertices.iter_mut().zip(computed_normals) {
+        let normal = Vector3::from(vertex.normal);
+        if computed && normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+
+    Ok((vertices, indices))
+}
+
+fn parse_floats<'a, const N: usize>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<[f32; N], String> {
+    let mut values = [0f32; N];
+    for value in values.iter_mut() {
+        let token = tokens.next().ok_or_else(|| format!("expected {} components", N))?;
+        *value = token.parse().map_err(|_| format!("invalid number `{}`", token))?;
+    }
+    Ok(values)
+}
+
+// OBJ indices are 1-based, negative ones are relative to the end of the list
+fn resolve_index(token: &str, len: usize) -> Result<usize, String> {
+    let index: i64 = token.parse().map_err(|_| format!("invalid index `{}`", token))?;
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i if i < 0 => len as i64 + i,
+        _ => return Err("index 0 is not valid".to_string()),
+    };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("index {} is out of range", index));
+    }
+    Ok(resolved as usize)
+}
+
 const VERTICES: &[Vertex] = &[
-    Vertex { position: [-0.5, -0.5, -0.5], tex_coords: [0.0, 0.0], },
-    Vertex { position: [0.5, -0.5, -0.5], tex_coords: [1.0, 0.0], },
-    Vertex { position: [0.5, 0.5, -0.5], tex_coords: [1.0, 1.0], },
-    Vertex { position: [-0.5, 0.5, -0.5], tex_coords: [0.0, 1.0], },
-
-    Vertex { position: [-0.5, -0.5, 0.5], tex_coords: [0.0, 0.0], },
-    Vertex { position: [0.5, -0.5, 0.5], tex_coords: [1.0, 0.0], },
-    Vertex { position: [0.5, 0.5, 0.5], tex_coords: [1.0, 1.0], },
-    Vertex { position: [-0.5, 0.5, 0.5], tex_coords: [0.0, 1.0], },
+    // +Z
+    Vertex { position: [-0.5, -0.5, 0.5], tex_coords: [0.0, 1.0], normal: [0.0, 0.0, 1.0], color: WHITE, },
+    Vertex { position: [0.5, -0.5, 0.5], tex_coords: [1.0, 1.0], normal: [0.0, 0.0, 1.0], color: WHITE, },
+    Vertex { position: [0.5, 0.5, 0.5], tex_coords: [1.0, 0.0], normal: [0.0, 0.0, 1.0], color: WHITE, },
+    Vertex { position: [-0.5, 0.5, 0.5], tex_coords: [0.0, 0.0], normal: [0.0, 0.0, 1.0], color: WHITE, },
+
+    // -Z
+    Vertex { position: [0.5, -0.5, -0.5], tex_coords: [0.0, 1.0], normal: [0.0, 0.0, -1.0], color: WHITE, },
+    Vertex { position: [-0.5, -0.5, -0.5], tex_coords: [1.0, 1.0], normal: [0.0, 0.0, -1.0], color: WHITE, },
+    Vertex { position: [-0.5, 0.5, -0.5], tex_coords: [1.0, 0.0], normal: [0.0, 0.0, -1.0], color: WHITE, },
+    Vertex { position: [0.5, 0.5, -0.5], tex_coords: [0.0, 0.0], normal: [0.0, 0.0, -1.0], color: WHITE, },
+
+    // +X
+    Vertex { position: [0.5, -0.5, 0.5], tex_coords: [0.0, 1.0], normal: [1.0, 0.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, -0.5, -0.5], tex_coords: [1.0, 1.0], normal: [1.0, 0.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, 0.5, -0.5], tex_coords: [1.0, 0.0], normal: [1.0, 0.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, 0.5, 0.5], tex_coords: [0.0, 0.0], normal: [1.0, 0.0, 0.0], color: WHITE, },
+
+    // -X
+    Vertex { position: [-0.5, -0.5, -0.5], tex_coords: [0.0, 1.0], normal: [-1.0, 0.0, 0.0], color: WHITE, },
+    Vertex { position: [-0.5, -0.5, 0.5], tex_coords: [1.0, 1.0], normal: [-1.0, 0.0, 0.0], color: WHITE, },
+    Vertex { position: [-0.5, 0.5, 0.5], tex_coords: [1.0, 0.0], normal: [-1.0, 0.0, 0.0], color: WHITE, },
+    Vertex { position: [-0.5, 0.5, -0.5], tex_coords: [0.0, 0.0], normal: [-1.0, 0.0, 0.0], color: WHITE, },
+
+    // +Y
+    Vertex { position: [-0.5, 0.5, 0.5], tex_coords: [0.0, 1.0], normal: [0.0, 1.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, 0.5, 0.5], tex_coords: [1.0, 1.0], normal: [0.0, 1.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, 0.5, -0.5], tex_coords: [1.0, 0.0], normal: [0.0, 1.0, 0.0], color: WHITE, },
+    Vertex { position: [-0.5, 0.5, -0.5], tex_coords: [0.0, 0.0], normal: [0.0, 1.0, 0.0], color: WHITE, },
+
+    // -Y
+    Vertex { position: [-0.5, -0.5, -0.5], tex_coords: [0.0, 1.0], normal: [0.0, -1.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, -0.5, -0.5], tex_coords: [1.0, 1.0], normal: [0.0, -1.0, 0.0], color: WHITE, },
+    Vertex { position: [0.5, -0.5, 0.5], tex_coords: [1.0, 0.0], normal: [0.0, -1.0, 0.0], color: WHITE, },
+    Vertex { position: [-0.5, -0.5, 0.5], tex_coords: [0.0, 0.0], normal: [0.0, -1.0, 0.0], color: WHITE, },
 ];
 
 const INDICES: &[u16] = &[
-    0, 2, 1,
-    0, 3, 2,
-
-    1, 2, 6,
-    6, 5, 1,
+    0, 1, 2,
+    0, 2, 3,
 
     4, 5, 6,
-    6, 7, 4,
+    4, 6, 7,
 
-    2, 3, 6,
-    6, 3, 7,
+    8, 9, 10,
+    8, 10, 11,
 
-    0, 7, 3,
-    0, 4, 7,
+    12, 13, 14,
+    12, 14, 15,
 
-    0, 1, 5,
-    0, 5, 4
-];
+    16, 17, 18,
+    16, 18, 19,
+
+    20, 21, 22,
+    20, 22, 23
+];
\ No newline at end of file
@@ -0,0 +1,217 @@
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, Queue, TextureFormat};
+
+use crate::instances::Instances;
+use crate::mesh::Mesh;
+
+// Stencil value the scene pipelines write for the instances, everything else
+// drawn with them writes 0
+pub const INSTANCE_STENCIL_REFERENCE: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+}
+
+impl OutlineUniform {
+    fn new(color: wgpu::Color) -> Self {
+        Self {
+            color: [color.r as f32, color.g as f32, color.b as f32, color.a as f32],
+        }
+    }
+}
+
+// Stencil state of the scene pipelines, marks the drawn pixels with the pass's stencil reference.
+// Formats without a stencil aspect must not have stencil state.
+pub fn scene_stencil_state(depth_format: TextureFormat) -> wgpu::StencilState {
+    if !depth_format.has_stencil_aspect() {
+        return wgpu::StencilState::default();
+    }
+    let face = wgpu::StencilFaceState {
+        compare: wgpu::CompareFunction::Always,
+        fail_op: wgpu::StencilOperation::Keep,
+        depth_fail_op: wgpu::StencilOperation::Keep,
+        pass_op: wgpu::StencilOperation::Replace,
+    };
+    wgpu::StencilState {
+        front: face,
+        back: face,
+        read_mask: 0xff,
+        write_mask: 0xff,
+    }
+}
+
+// Silhouette of the instances, drawn in the scene pass after them where the stencil
+// buffer doesn't hold INSTANCE_STENCIL_REFERENCE. Needs a depth format with stencil.
+pub struct Outline {
+    pipeline: wgpu::RenderPipeline,
+    color: wgpu::Color,
+    color_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    outline_layout: BindGroupLayout,
+}
+
+impl Outline {
+    pub fn new(device: &Device,
+               target_texture_format: TextureFormat,
+               sample_count: u32,
+               depth_format: TextureFormat,
+               instance_vertex_buffer: bool,
+               layouts: [&BindGroupLayout; 3],
+               color: wgpu::Color) -> Self {
+        let outline_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("outline_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let color_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Buffer"),
+            contents: bytemuck::cast_slice(&[OutlineUniform::new(color)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &outline_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: color_buffer.as_entire_binding(),
+            }],
+            label: Some("outline_bind_group"),
+        });
+        Self {
+            pipeline: Self::create_pipeline(device, target_texture_format, sample_count, depth_format, instance_vertex_buffer, layouts, &outline_layout),
+            color,
+            color_buffer,
+            bind_group,
+            outline_layout,
+        }
+    }
+
+    // `layouts` are the camera, rotation and instances layouts.
+    // Has to be called when the sample count or the depth format of the scene pass changes
+    // and when the instances switch between storage and vertex attributes.
+    pub fn rebuild_pipeline(&mut self,
+                            device: &Device,
+                            target_texture_format: TextureFormat,
+                            sample_count: u32,
+                            depth_format: TextureFormat,
+                            instance_vertex_buffer: bool,
+                            layouts: [&BindGroupLayout; 3]) {
+        self.pipeline = Self::create_pipeline(device, target_texture_format, sample_count, depth_format, instance_vertex_buffer, layouts, &self.outline_layout);
+    }
+
+    fn create_pipeline(device: &Device,
+                       target_texture_format: TextureFormat,
+                       sample_count: u32,
+                       depth_format: TextureFormat,
+                       instance_vertex_buffer: bool,
+                       [camera_layout, rotation_layout, instances_layout]: [&BindGroupLayout; 3],
+                       outline_layout: &BindGroupLayout) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/outline.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, rotation_layout, instances_layout, outline_layout],
+            push_constant_ranges: &[],
+        });
+        // Passes outside the instances, writes nothing
+        let stencil = if depth_format.has_stencil_aspect() {
+            let face = wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::NotEqual,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Keep,
+            };
+            wgpu::StencilState {
+                front: face,
+                back: face,
+                read_mask: 0xff,
+                write_mask: 0,
+            }
+        } else {
+            wgpu::StencilState::default()
+        };
+        let (entry_point, buffers) = Instances::vertex_input(instance_vertex_buffer);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point,
+                buffers: &buffers,
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_texture_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            // The outline stays visible in front of the background and the ground
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil,
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn color(&self) -> wgpu::Color {
+        self.color
+    }
+
+    pub fn set_color(&mut self, queue: &Queue, color: wgpu::Color) {
+        if color != self.color {
+            self.color = color;
+            queue.write_buffer(&self.color_buffer, 0, bytemuck::cast_slice(&[OutlineUniform::new(color)]));
+        }
+    }
+
+    // Draws into the scene pass after the instances, with the same bind groups as them
+    pub fn render<'a>(&'a self,
+                      render_pass: &mut wgpu::RenderPass<'a>,
+                      camera_bind_group: &'a wgpu::BindGroup,
+                      rotation_bind_group: &'a wgpu::BindGroup,
+                      instances: &'a Instances,
+                      mesh: &'a Mesh) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_stencil_reference(INSTANCE_STENCIL_REFERENCE);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, rotation_bind_group, &[]);
+        render_pass.set_bind_group(2, &instances.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instances.buffer().slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format());
+        render_pass.draw_indexed(0..mesh.num_indices(), 0, 0..instances.visible_count());
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, Queue, TextureFormat};
+
+const WORKGROUP_SIZE: u32 = 64;
+// Seconds from a particle's spawn to its respawn
+const LIFETIME: f32 = 3.0;
+
+// Element of the particles storage buffer, also read as a per-instance vertex buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleRaw {
+    position: [f32; 3],
+    // Negative until the first spawn
+    age: f32,
+    velocity: [f32; 3],
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    dt: f32,
+    lifetime: f32,
+    seed: u32,
+    // Uniform buffers are at least 16 bytes on WebGL
+    _padding: u32,
+}
+
+// Fountain of particles advanced by a compute pass, see animate_particles.wgsl
+pub struct Particles {
+    count: u32,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    params_bind_group: wgpu::BindGroup,
+    // Number of steps run, seeds the respawn velocities
+    steps: u64,
+}
+
+impl Particles {
+    // The device has to support compute shaders
+    pub fn new(device: &Device, count: u32) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particles Buffer"),
+            contents: bytemuck::cast_slice(&Self::initial(count)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let storage_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("particles_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &storage_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("particles_bind_group"),
+        });
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particles Params Buffer"),
+            contents: bytemuck::cast_slice(&[Self::params(Duration::ZERO, 0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("particles_params_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+            label: Some("particles_params_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Animation Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/animate_particles.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Animation Pipeline Layout"),
+            bind_group_layouts: &[&storage_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Animation Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Self {
+            count,
+            buffer,
+            bind_group,
+            pipeline,
+            params_buffer,
+            params_bind_group,
+            steps: 0,
+        }
+    }
+
+    // Spawned one after another over the first lifetime instead of all at once
+    fn initial(count: u32) -> Vec<ParticleRaw> {
+        (0..count)
+            .map(|i| ParticleRaw {
+                position: [0.0; 3],
+                age: -LIFETIME * i as f32 / count as f32,
+                velocity: [0.0; 3],
+                _padding: 0.0,
+            })
+            .collect()
+    }
+
+    fn params(dt: Duration, seed: u32) -> ParamsUniform {
+        ParamsUniform {
+            dt: dt.as_secs_f32(),
+            lifetime: LIFETIME,
+            seed,
+            _padding: 0,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    // Advances the particles by `dt` and submits the pass right away
+    pub fn run(&mut self, device: &Device, queue: &Queue, dt: Duration) {
+        self.steps += 1;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[Self::params(dt, self.steps as u32)]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Particle Animation Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Particle Animation Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.params_bind_group, &[]);
+            compute_pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // Runs steps of `dt` until `steps` were run since the start, restarting first when more
+    // were already. The same number of steps always gives the same particles. Submits every
+    // step on its own and isn't bounded, a large `steps` blocks for as long as it takes.
+    pub fn run_until(&mut self, device: &Device, queue: &Queue, steps: u64, dt: Duration) {
+        if steps < self.steps {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&Self::initial(self.count)));
+            self.steps = 0;
+        }
+        while self.steps < steps {
+            self.run(device, queue, dt);
+        }
+    }
+}
+
+// Draws Particles into the scene pass, additively blended and tested against the scene's depth
+pub struct ParticleRenderer {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ParticleRenderer {
+    pub fn new(device: &Device,
+               target_texture_format: TextureFormat,
+               sample_count: u32,
+               depth_format: TextureFormat,
+               depth_compare: wgpu::CompareFunction,
+               camera_layout: &BindGroupLayout) -> Self {
+        Self {
+            pipeline: Self::create_pipeline(device, target_texture_format, sample_count, depth_format, depth_compare, camera_layout),
+        }
+    }
+
+    // Has to be called when the sample count, the depth format or the depth test of the scene pass changes
+    pub fn rebuild_pipeline(&mut self,
+                            device: &Device,
+                            target_texture_format: TextureFormat,
+                            sample_count: u32,
+                            depth_format: TextureFormat,
+                            depth_compare: wgpu::CompareFunction,
+                            camera_layout: &BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, target_texture_format, sample_count, depth_format, depth_compare, camera_layout);
+    }
+
+    fn create_pipeline(device: &Device,
+                       target_texture_format: TextureFormat,
+                       sample_count: u32,
+                       depth_format: TextureFormat,
+                       depth_compare: wgpu::CompareFunction,
+                       camera_layout: &BindGroupLayout) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particles Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/particles.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particles Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let constants = HashMap::from([("lifetime".to_string(), LIFETIME as f64)]);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particles Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                // One quad per particle, only the position and the age are read
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<ParticleRaw>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x4],
+                }],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_texture_format,
+                    // Premultiplied by the fragment shader, overlapping particles add up
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            // Hidden behind the cubes, but don't hide each other
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Draws into a pass that already has the scene's color and depth attachments
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup, particles: &'a Particles) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, particles.buffer.slice(..));
+        render_pass.draw(0..6, 0..particles.count);
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use wgpu::{CommandEncoder, Device, Queue};
+
+// Begin and end timestamps for the scene pass and the depth view pass
+const QUERY_COUNT: u32 = 4;
+const SCENE_BEGIN: u32 = 0;
+const DEPTH_VIEW_BEGIN: u32 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PassTimings {
+    pub scene_ms: f32,
+    // None when the depth view wasn't rendered
+    pub depth_view_ms: Option<f32>,
+}
+
+// Measures render passes with timestamp queries. Results are read back
+// asynchronously, so they lag a few frames behind and frames rendered
+// while a readback is in flight are not measured.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    // Set when the current frame is being measured
+    measuring: bool,
+    depth_view_measured: bool,
+    // Result of the requested readback, None when no readback is in flight
+    readback: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    // Whether the in flight readback includes the depth view pass
+    pending_depth_view: bool,
+    last_timings: Option<PassTimings>,
+}
+
+impl GpuProfiler {
+    // None when the device doesn't support timestamp queries, e.g. on WebGL
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Pass Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let size = (QUERY_COUNT as usize * std::mem::size_of::<u64>()) as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            measuring: false,
+            depth_view_measured: false,
+            readback: None,
+            pending_depth_view: false,
+            last_timings: None,
+        })
+    }
+
+    pub fn last_timings(&self) -> Option<PassTimings> {
+        self.last_timings
+    }
+
+    // Collects finished results and decides whether the next frame is measured
+    pub fn begin_frame(&mut self, device: &Device) {
+        if let Some(readback) = &self.readback {
+            device.poll(wgpu::Maintain::Poll);
+            match readback.try_recv() {
+                Ok(Ok(())) => {
+                    self.read_results();
+                    self.readback = None;
+                }
+                Ok(Err(_)) | Err(TryRecvError::Disconnected) => self.readback = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        self.measuring = self.readback.is_none();
+        self.depth_view_measured = false;
+    }
+
+    pub fn scene_pass_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.pass_writes(SCENE_BEGIN)
+    }
+
+    pub fn depth_view_pass_writes(&mut self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.depth_view_measured = self.measuring;
+        self.pass_writes(DEPTH_VIEW_BEGIN)
+    }
+
+    fn pass_writes(&self, begin: u32) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.measuring.then_some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(begin),
+            end_of_pass_write_index: Some(begin + 1),
+        })
+    }
+
+    // Records the copy of this frame's timestamps, call after the measured passes
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        if !self.measuring {
+            return;
+        }
+        // Only resolve queries that were actually written this frame
+        let count = if self.depth_view_measured { QUERY_COUNT } else { DEPTH_VIEW_BEGIN };
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, self.resolve_buffer.size());
+    }
+
+    // Starts reading the timestamps back, call after the frame was submitted
+    pub fn end_frame(&mut self) {
+        if !self.measuring {
+            return;
+        }
+        self.pending_depth_view = self.depth_view_measured;
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver is gone if the profiler was dropped in the meantime
+            let _ = sender.send(result);
+        });
+        self.readback = Some(receiver);
+    }
+
+    fn read_results(&mut self) {
+        let ticks_to_ms = |begin: u64, end: u64| end.wrapping_sub(begin) as f32 * self.period / 1_000_000.0;
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            self.last_timings = Some(PassTimings {
+                scene_ms: ticks_to_ms(timestamps[0], timestamps[1]),
+                depth_view_ms: self.pending_depth_view.then(|| ticks_to_ms(timestamps[2], timestamps[3])),
+            });
+        }
+        self.readback_buffer.unmap();
+    }
+
+    // Unmaps the readback buffer, a measurement still in flight is dropped
+    pub fn release(&mut self) {
+        if self.readback.take().is_some() {
+            self.readback_buffer.unmap();
+        }
+        self.measuring = false;
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::{Arc, OnceLock};
+
+use wgpu::{Device, TextureFormat};
+
+use crate::tonemap::ToneMapper;
+
+// Frames copied but not yet taken, a new frame is dropped when all of them are in use
+const RING_SIZE: usize = 3;
+
+enum SlotState {
+    Free,
+    // The copy was encoded, mapping starts once it's submitted
+    Copied,
+    // Set by the map_async callback
+    Mapping(Arc<OnceLock<Result<(), wgpu::BufferAsyncError>>>),
+}
+
+struct Slot {
+    buffer: wgpu::Buffer,
+    state: SlotState,
+    // Number of the frame copied into the buffer
+    frame: u64,
+}
+
+// Tone maps every rendered frame into a texture of its own size and copies it into
+// a ring of staging buffers read without blocking, see State::take_frame
+pub struct Recorder {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    pipeline: wgpu::RenderPipeline,
+    padded_bytes_per_row: u32,
+    slots: Vec<Slot>,
+    next_frame: u64,
+}
+
+impl Recorder {
+    // The frames are gamma encoded like `output_format`
+    pub fn new(device: &Device, tone_mapper: &ToneMapper, output_format: TextureFormat, width: u32, height: u32) -> Self {
+        let format = if output_format.is_srgb() {
+            TextureFormat::Rgba8UnormSrgb
+        } else {
+            TextureFormat::Rgba8Unorm
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("recording_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Buffer copies require rows aligned to 256 bytes
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (4 * width).div_ceil(align) * align;
+        let slots = (0..RING_SIZE)
+            .map(|_| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Recording Buffer"),
+                    size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                state: SlotState::Free,
+                frame: 0,
+            })
+            .collect();
+        Self {
+            pipeline: tone_mapper.create_pipeline_for(device, format),
+            texture,
+            view,
+            padded_bytes_per_row,
+            slots,
+            next_frame: 0,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.texture.width(), self.texture.height())
+    }
+
+    // Call `submitted` once the encoder was submitted
+    pub fn record(&mut self, encoder: &mut wgpu::CommandEncoder, tone_mapper: &ToneMapper) {
+        let Some(slot) = self.slots.iter_mut().find(|slot| matches!(slot.state, SlotState::Free)) else {
+            log::debug!("Dropping a recorded frame, all staging buffers are waiting to be taken");
+            return;
+        };
+        tone_mapper.render_with(encoder, &self.view, &self.pipeline);
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &slot.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.texture.height()),
+                },
+            },
+            self.texture.size(),
+        );
+        slot.state = SlotState::Copied;
+        slot.frame = self.next_frame;
+        self.next_frame += 1;
+    }
+
+    pub fn submitted(&mut self) {
+        for slot in &mut self.slots {
+            if let SlotState::Copied = slot.state {
+                let result = Arc::new(OnceLock::new());
+                let callback_result = result.clone();
+                slot.buffer.slice(..).map_async(wgpu::MapMode::Read, move |r| {
+                    let _ = callback_result.set(r);
+                });
+                slot.state = SlotState::Mapping(result);
+            }
+        }
+    }
+
+    // Tightly packed RGBA rows of the newest mapped frame, older mapped frames are dropped
+    pub fn take_frame(&mut self, device: &Device) -> Option<Vec<u8>> {
+        // Only runs the callbacks of finished mappings, never waits
+        device.poll(wgpu::Maintain::Poll);
+        let mut mapped = Vec::new();
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            let SlotState::Mapping(result) = &slot.state else {
+                continue;
+            };
+            match result.get() {
+                None => {}
+                Some(Ok(())) => mapped.push(i),
+                Some(Err(e)) => {
+                    log::warn!("Failed to map a recording buffer: {}", e);
+                    slot.state = SlotState::Free;
+                }
+            }
+        }
+        let newest = mapped.iter().copied().max_by_key(|&i| self.slots[i].frame)?;
+
+        let (width, height) = self.size();
+        let unpadded_bytes_per_row = (4 * width) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * height as usize);
+        let slice = self.slots[newest].buffer.slice(..);
+        for row in slice.get_mapped_range().chunks(self.padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+        }
+        for i in mapped {
+            self.slots[i].buffer.unmap();
+            self.slots[i].state = SlotState::Free;
+        }
+        Some(pixels)
+    }
+
+    // Unmaps every staging buffer, frames not taken yet are dropped
+    pub fn release(&mut self) {
+        for slot in &mut self.slots {
+            if let SlotState::Mapping(_) = slot.state {
+                slot.buffer.unmap();
+            }
+            slot.state = SlotState::Free;
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+// What the passes of a frame read and write. The depth texture is cleared again by the
+// scene pass, so the prepass' depth is a resource of its own.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    ShadowMap,
+    PrepassDepth,
+    AmbientOcclusion,
+    // The scene's depth buffer
+    Depth,
+    // The scene before tone mapping
+    Hdr,
+    // The surface texture or the headless target
+    Frame,
+}
+
+// The passes State renders a frame with, see State::render_graph_mut
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PassId {
+    Shadows,
+    DepthPrepass,
+    Ssao,
+    // Skybox, ground plane, instances and the debug geometry
+    Scene,
+    Bloom,
+    // Followed by FXAA when it's enabled
+    ToneMap,
+    Recorder,
+    // See State::set_overlay_renderer
+    Overlay,
+    DepthView,
+    AxisGizmo,
+    #[cfg(feature = "egui")]
+    Egui,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderGraphError {
+    DuplicatePass(PassId),
+    // The pass depends on itself through the passes it reads from
+    Cycle(PassId),
+}
+
+impl fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderGraphError::DuplicatePass(pass) => write!(f, "{:?} is already part of the render graph", pass),
+            RenderGraphError::Cycle(pass) => write!(f, "{:?} depends on its own output", pass),
+        }
+    }
+}
+
+impl std::error::Error for RenderGraphError {}
+
+struct Pass {
+    id: PassId,
+    inputs: Vec<Resource>,
+    outputs: Vec<Resource>,
+    enabled: bool,
+}
+
+// Orders passes by the resources they use: a pass reading a resource it doesn't write runs
+// after every pass writing it, passes writing the same resource run in the order they were
+// added, e.g. everything drawn over the frame after the tone mapper filled it. Otherwise
+// the order they were added in is kept.
+pub struct RenderGraph {
+    passes: Vec<Pass>,
+    // Indices into passes
+    order: Vec<usize>,
+}
+
+impl RenderGraph {
+    pub(crate) fn new() -> Self {
+        Self { passes: Vec::new(), order: Vec::new() }
+    }
+
+    pub(crate) fn add_pass(&mut self, id: PassId, inputs: &[Resource], outputs: &[Resource]) -> Result<(), RenderGraphError> {
+        if self.position(id).is_some() {
+            return Err(RenderGraphError::DuplicatePass(id));
+        }
+        self.passes.push(Pass { id, inputs: inputs.to_vec(), outputs: outputs.to_vec(), enabled: true });
+        match Self::sort(&self.passes) {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            }
+            Err(e) => {
+                self.passes.pop();
+                Err(e)
+            }
+        }
+    }
+
+    fn position(&self, id: PassId) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.id == id)
+    }
+
+    // In the order they were added
+    pub fn passes(&self) -> impl Iterator<Item = PassId> + '_ {
+        self.passes.iter().map(|pass| pass.id)
+    }
+
+    // False for passes that aren't part of the graph
+    pub fn is_enabled(&self, id: PassId) -> bool {
+        self.position(id).is_some_and(|i| self.passes[i].enabled)
+    }
+
+    // Disabled passes are skipped even when their feature is on, e.g. to hide the depth view
+    // without dropping it. Does nothing for passes that aren't part of the graph.
+    pub fn set_enabled(&mut self, id: PassId, on: bool) {
+        if let Some(i) = self.position(id) {
+            self.passes[i].enabled = on;
+        }
+    }
+
+    // Moves the pass to `index` in the order passes were added in, which decides the order
+    // of passes writing the same resource. Fails and keeps the order if that would make a
+    // pass depend on itself.
+    pub fn move_pass(&mut self, id: PassId, index: usize) -> Result<(), RenderGraphError> {
+        let Some(from) = self.position(id) else {
+            return Ok(());
+        };
+        let pass = self.passes.remove(from);
+        self.passes.insert(index.min(self.passes.len()), pass);
+        match Self::sort(&self.passes) {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            }
+            Err(e) => {
+                let pass = self.passes.remove(index.min(self.passes.len() - 1));
+                self.passes.insert(from, pass);
+                Err(e)
+            }
+        }
+    }
+
+    // The enabled passes in the order they run in
+    pub fn execution_order(&self) -> Vec<PassId> {
+        self.order.iter()
+            .map(|&i| &self.passes[i])
+            .filter(|pass| pass.enabled)
+            .map(|pass| pass.id)
+            .collect()
+    }
+
+    // Topological order, picking the earliest added pass whenever several are ready
+    fn sort(passes: &[Pass]) -> Result<Vec<usize>, RenderGraphError> {
+        let dependencies: Vec<Vec<usize>> = passes.iter().enumerate()
+            .map(|(i, pass)| {
+                passes.iter().enumerate()
+                    .filter(|&(j, other)| {
+                        let reads = pass.inputs.iter().any(|r| other.outputs.contains(r) && !pass.outputs.contains(r));
+                        let writes_after = j < i && pass.outputs.iter().any(|r| other.outputs.contains(r));
+                        j != i && (reads || writes_after)
+                    })
+                    .map(|(j, _)| j)
+                    .collect()
+            })
+            .collect();
+        let mut done = vec![false; passes.len()];
+        let mut order = Vec::with_capacity(passes.len());
+        while order.len() < passes.len() {
+            let ready = (0..passes.len()).find(|&i| !done[i] && dependencies[i].iter().all(|&j| done[j]));
+            let Some(next) = ready else {
+                let stuck = (0..passes.len()).find(|&i| !done[i]).unwrap();
+                return Err(RenderGraphError::Cycle(passes[stuck].id));
+            };
+            done[next] = true;
+            order.push(next);
+        }
+        Ok(order)
+    }
+}
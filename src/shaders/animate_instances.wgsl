@@ -0,0 +1,57 @@
+// Advances the spin of every instance and rebuilds its transform
+
+struct Instance {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    layer: u32,
+};
+
+struct Spin {
+    // Transform of the instance without its spin
+    base: mat4x4<f32>,
+    axis: vec3<f32>,
+    radians_per_second: f32,
+    angle: f32,
+};
+
+struct Params {
+    dt: f32,
+};
+
+const TAU: f32 = 6.283185307179586;
+
+@group(0) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+@group(0) @binding(1)
+var<storage, read_write> spins: array<Spin>;
+@group(1) @binding(0)
+var<uniform> params: Params;
+
+// Same as cgmath's Matrix4::from_axis_angle
+fn axis_angle(axis: vec3<f32>, angle: f32) -> mat4x4<f32> {
+    let s = sin(angle);
+    let c = cos(angle);
+    let c1 = 1.0 - c;
+    let x = axis.x;
+    let y = axis.y;
+    let z = axis.z;
+    return mat4x4<f32>(
+        vec4<f32>(c1 * x * x + c, c1 * x * y + s * z, c1 * x * z - s * y, 0.0),
+        vec4<f32>(c1 * x * y - s * z, c1 * y * y + c, c1 * y * z + s * x, 0.0),
+        vec4<f32>(c1 * x * z + s * y, c1 * y * z - s * x, c1 * z * z + c, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    );
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= arrayLength(&spins)) {
+        return;
+    }
+    let spin = spins[i];
+    var angle = spin.angle + spin.radians_per_second * params.dt;
+    angle = angle - TAU * floor(angle / TAU);
+    spins[i].angle = angle;
+    instances[i].transform = spin.base * axis_angle(spin.axis, angle);
+}
@@ -0,0 +1,57 @@
+// Advances every particle by the elapsed time, particles past their lifetime are
+// respawned at the emitter
+
+struct Particle {
+    position: vec3<f32>,
+    // Seconds since the particle was spawned, negative until the first spawn
+    age: f32,
+    velocity: vec3<f32>,
+    _padding: f32,
+};
+
+struct Params {
+    dt: f32,
+    lifetime: f32,
+    // Changes every step so respawned particles get new velocities
+    seed: u32,
+};
+
+const EMITTER: vec3<f32> = vec3<f32>(0.0, -1.0, 0.8);
+const GRAVITY: vec3<f32> = vec3<f32>(0.0, -4.0, 0.0);
+
+@group(0) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+@group(1) @binding(0)
+var<uniform> params: Params;
+
+// PCG hash, uniform in 0..1
+fn random(seed: u32) -> f32 {
+    let state = seed * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return f32((word >> 22u) ^ word) / 4294967295.0;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= arrayLength(&particles)) {
+        return;
+    }
+    var particle = particles[i];
+    let age = particle.age + params.dt;
+    if ((particle.age < 0.0 && age >= 0.0) || age >= params.lifetime) {
+        // Respawn with a random velocity inside an upward cone
+        let seed = i * 3u + params.seed * 7919u;
+        let angle = random(seed) * 6.283185307179586;
+        let spread = random(seed + 1u) * 0.8;
+        let speed = 2.5 + random(seed + 2u);
+        particle.position = EMITTER;
+        particle.velocity = vec3<f32>(cos(angle) * spread, speed, sin(angle) * spread);
+        particle.age = age % params.lifetime;
+    } else {
+        particle.velocity += GRAVITY * params.dt;
+        particle.position += particle.velocity * params.dt;
+        particle.age = age;
+    }
+    particles[i] = particle;
+}
@@ -0,0 +1,36 @@
+// World axes turned like the scene, see AxisGizmo
+
+struct GizmoUniform {
+    view_rotation: mat4x4<f32>,
+};
+
+// Of the axes' length, leaves a margin to the viewport's edges
+const SCALE: f32 = 0.8;
+
+@group(0) @binding(0)
+var<uniform> gizmo: GizmoUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    // Orthographic, the direction towards the camera is dropped
+    let view_position = gizmo.view_rotation * vec4<f32>(in.position, 0.0);
+    out.clip_position = vec4<f32>(view_position.xy * SCALE, 0.5, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
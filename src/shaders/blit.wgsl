@@ -0,0 +1,39 @@
+// Copies a texture or one of its layers into the render target, used to downsample mip levels
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    out.clip_position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.tex_coords = vec2<f32>(x, y);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_source, s_source, in.tex_coords);
+}
+
+// The same for one layer of a texture array
+@group(0) @binding(2)
+var t_layers: texture_2d_array<f32>;
+// Only x is used, the rest pads the uniform to 16 bytes
+@group(0) @binding(3)
+var<uniform> layer: vec4<u32>;
+
+@fragment
+fn fs_layer(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_layers, s_source, in.tex_coords, layer.x);
+}
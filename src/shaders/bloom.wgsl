@@ -0,0 +1,70 @@
+// Bright pass, separable gaussian blur and composite of the bloom, see Bloom
+
+struct Params {
+    // Luminance above which colors bloom
+    threshold: f32,
+    intensity: f32,
+};
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // 0 to 1 across the target
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    var out: VertexOutput;
+    out.position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.uv = vec2<f32>(x, y);
+    return out;
+}
+
+// The part of the scene above the threshold, downsampled by the linear filter
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = max(textureSample(source, source_sampler, in.uv).rgb, vec3<f32>(0.0));
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    // Scales the color instead of cutting it off, keeping its hue
+    let factor = max(luminance - params.threshold, 0.0) / max(luminance, 1e-4);
+    return vec4<f32>(color * factor, 1.0);
+}
+
+// 9 tap gaussian in 5 samples, the linear filter blends the pairs of taps in between
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let texel = direction / vec2<f32>(textureDimensions(source));
+    var offsets = array<f32, 3>(0.0, 1.3846153846, 3.2307692308);
+    var weights = array<f32, 3>(0.2270270270, 0.3162162162, 0.0702702703);
+    var color = textureSample(source, source_sampler, uv).rgb * weights[0];
+    for (var i = 1; i < 3; i++) {
+        color += textureSample(source, source_sampler, uv + texel * offsets[i]).rgb * weights[i];
+        color += textureSample(source, source_sampler, uv - texel * offsets[i]).rgb * weights[i];
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+// Added onto the scene by the blend state
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(source, source_sampler, in.uv).rgb * params.intensity, 0.0);
+}
@@ -0,0 +1,28 @@
+// Copies one texel of the depth buffer into a 1x1 target, see DepthReadback
+
+// DepthTexture is declared by DepthReadback depending on whether the depth buffer is multisampled
+@group(0) @binding(0)
+var depth_texture: DepthTexture;
+
+struct Texel {
+    coords: vec2<u32>,
+};
+
+@group(0) @binding(1)
+var<uniform> texel: Texel;
+
+// A single triangle covering the target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    return vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+}
+
+// The bits of the depth, integer targets are renderable everywhere and keep them exact
+@fragment
+fn fs_main() -> @location(0) vec4<u32> {
+    // Level 0 for a regular texture, sample 0 for a multisampled one
+    let depth = textureLoad(depth_texture, texel.coords, 0).x;
+    return vec4<u32>(bitcast<u32>(depth), 0u, 0u, 0u);
+}
@@ -1,9 +1,23 @@
 
 
+// DepthTexture is declared by DepthView depending on whether the depth buffer is multisampled
 @group(0) @binding(0)
-var depth_texture: texture_2d<f32>;
-@group(0) @binding(1)
-var depth_texture_sampler: sampler;
+var depth_texture: DepthTexture;
+
+struct ClipPlanes {
+    near: f32,
+    far: f32,
+    // Non zero when the depth buffer is reversed, 1.0 at the near plane
+    reverse_z: u32,
+    // 0 grayscale, 1 turbo, 2 inverted grayscale, see Colormap
+    colormap: u32,
+}
+
+@group(0) @binding(2)
+var<uniform> clip_planes: ClipPlanes;
+
+// 1 when the target is an sRGB texture, which encodes the linear colors written to it
+override srgb_target: f32 = 0.0;
 
 struct VertexOutput {
     @builtin(position) position: vec4f,
@@ -25,14 +39,56 @@ fn depth_view_vs(@builtin(vertex_index) vertex_index : u32) -> VertexOutput {
       var out: VertexOutput;
 
       out.position = vec4f(pos[vertex_index], 0, 1);
-      out.tex_coords = pos[vertex_index];
+      // Clip space y points up, texture v points down
+      out.tex_coords = pos[vertex_index] * vec2(0.5, -0.5) + vec2(0.5);
 
       return out;
 }
 
 @fragment
 fn depth_view_fs(in: VertexOutput) -> @location(0) vec4f {
-    var x = textureSample(depth_texture, depth_texture_sampler, in.tex_coords).x;
-    return vec4(0.0, 1.0, 0.0, x);
+    let size = textureDimensions(depth_texture);
+    let coords = min(vec2<u32>(in.tex_coords * vec2<f32>(size)), size - vec2(1u));
+    // Level 0 for a regular texture, sample 0 for a multisampled one
+    let stored_depth = textureLoad(depth_texture, coords, 0).x;
+    let depth = select(stored_depth, 1.0 - stored_depth, clip_planes.reverse_z != 0u);
+    // Undo the perspective division, depth is in 0..1 between the near and far planes
+    let near = clip_planes.near;
+    let far = clip_planes.far;
+    let view_depth = near * far / (far - depth * (far - near));
+    let x = (view_depth - near) / (far - near);
+    switch clip_planes.colormap {
+        case 1u: {
+            var color = turbo(x);
+            if (srgb_target != 0.0) {
+                color = pow(color, vec3(2.2));
+            }
+            return vec4(color, 1.0);
+        }
+        case 2u: {
+            return vec4(vec3(1.0 - x), 1.0);
+        }
+        default: {
+            return vec4(vec3(x), x);
+        }
+    }
+}
+
+// Polynomial fit of the Turbo colormap, x in 0..1
+fn turbo(x: f32) -> vec3f {
+    let v = clamp(x, 0.0, 1.0);
+    let v4 = vec4(1.0, v, v * v, v * v * v);
+    let v2 = v4.zw * v4.z;
+    let red = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+    let green = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+    let blue = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+    let red2 = vec2(-152.94239396, 59.28637943);
+    let green2 = vec2(4.27729857, 2.82956604);
+    let blue2 = vec2(-89.90310912, 27.34824973);
+    return vec3(
+        dot(v4, red) + dot(v2, red2),
+        dot(v4, green) + dot(v2, green2),
+        dot(v4, blue) + dot(v2, blue2),
+    );
 }
 
@@ -0,0 +1,77 @@
+// Fast approximate anti-aliasing of the tone mapped frame, see Fxaa
+
+// Largest blur along an edge in texels
+const SPAN_MAX: f32 = 8.0;
+// Keep flat areas and faint edges from being blurred
+const REDUCE_MUL: f32 = 1.0 / 8.0;
+const REDUCE_MIN: f32 = 1.0 / 128.0;
+
+// 1 when the input is an sRGB texture, which reads back linear colors
+override srgb_input: f32 = 0.0;
+
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+@group(0) @binding(1)
+var frame_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // 0 to 1 across the target
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    var out: VertexOutput;
+    out.position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.uv = vec2<f32>(x, y);
+    return out;
+}
+
+// Perceptual luma, edges are found where it changes
+fn luma(color: vec3<f32>) -> f32 {
+    var c = color;
+    if (srgb_input != 0.0) {
+        c = sqrt(c);
+    }
+    return dot(c, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn sample(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(frame, frame_sampler, uv, 0.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(frame));
+    let center = sample(in.uv);
+    let luma_nw = luma(sample(in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(sample(in.uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(sample(in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(sample(in.uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let luma_m = luma(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Along the edge, across the luma gradient
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-SPAN_MAX), vec2<f32>(SPAN_MAX)) * texel;
+
+    let near = 0.5 * (sample(in.uv + direction * (1.0 / 3.0 - 0.5)).rgb
+        + sample(in.uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    let far = near * 0.5 + 0.25 * (sample(in.uv - direction * 0.5).rgb + sample(in.uv + direction * 0.5).rgb);
+    let luma_far = luma(far);
+    // The wider blur crossed another edge
+    if (luma_far < luma_min || luma_far > luma_max) {
+        return vec4<f32>(near, center.a);
+    }
+    return vec4<f32>(far, center.a);
+}
@@ -0,0 +1,62 @@
+// Enlarged copies of the instances in a flat color, the stencil test keeps
+// only the parts sticking out from behind the instances themselves
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+struct RotationUniform {
+    rotation: mat4x4<f32>,
+};
+
+struct Instance {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    layer: u32,
+};
+
+struct OutlineUniform {
+    color: vec4<f32>,
+};
+
+// Of the meshes around their origin, sets the outline width
+const SCALE: f32 = 1.08;
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var<uniform> rotation: RotationUniform;
+
+@group(2) @binding(0)
+var<storage, read> instances: array<Instance>;
+
+@group(3) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> @builtin(position) vec4<f32> {
+    let model = instances[instance_index].transform * rotation.rotation;
+    return camera.view_proj * model * vec4<f32>(position * SCALE, 1.0);
+}
+
+// Takes the instance as vertex attributes instead, see Instances::as_vertex_buffer_layout
+@vertex
+fn vs_main_instanced(
+    @location(0) position: vec3<f32>,
+    @location(5) transform_0: vec4<f32>,
+    @location(6) transform_1: vec4<f32>,
+    @location(7) transform_2: vec4<f32>,
+    @location(8) transform_3: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(transform_0, transform_1, transform_2, transform_3) * rotation.rotation;
+    return camera.view_proj * model * vec4<f32>(position * SCALE, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
@@ -0,0 +1,60 @@
+// Draws every particle as a camera facing quad, see animate_particles.wgsl
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+// Half the quad's extent in clip space units, shrinks with the distance like a world size would
+const SIZE: f32 = 0.04;
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @builtin(vertex_index) vertex_index: u32,
+    // The particle's position and age
+    @location(0) position_age: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1..1 across the quad
+    @location(0) corner: vec2<f32>,
+    // 0 when spawned, 1 at the end of the lifetime
+    @location(1) life: f32,
+};
+
+// The lifetime as an override so the vertex stage doesn't need the compute params
+override lifetime: f32 = 3.0;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    // Two triangles
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[in.vertex_index];
+    var out: VertexOutput;
+    out.corner = corner;
+    out.life = in.position_age.w / lifetime;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position_age.xyz, 1.0);
+    out.clip_position = vec4<f32>(out.clip_position.xy + corner * SIZE, out.clip_position.zw);
+    if (in.position_age.w < 0.0) {
+        // Not spawned yet, outside the clip volume
+        out.clip_position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+    }
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.corner);
+    if (distance > 1.0) {
+        discard;
+    }
+    // From a bright yellow to a dim red
+    let color = mix(vec3<f32>(4.0, 3.0, 1.0), vec3<f32>(1.0, 0.1, 0.0), in.life);
+    let alpha = (1.0 - distance) * (1.0 - in.life);
+    return vec4<f32>(color * alpha, alpha);
+}
@@ -2,14 +2,58 @@
 
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    sky_rays: mat4x4<f32>,
+    // The eye in world space, w is 1
+    view_position: vec4<f32>,
 };
 
-struct RotatorUniform {
+struct RotationUniform {
     rotation: mat4x4<f32>,
 };
 
+struct Instance {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    layer: u32,
+};
+
+struct LightUniform {
+    // Light-space view projection of the shadow map
+    view_proj: mat4x4<f32>,
+    // Direction the light travels in
+    direction: vec3<f32>,
+    shadows: u32,
+    color: vec3<f32>,
+    ambient: f32,
+    // Number of lights used
+    light_count: u32,
+    // Blinn-Phong exponent
+    shininess: f32,
+    specular_strength: f32,
+    // 1 when the ambient term is multiplied by ambient_occlusion
+    ambient_occlusion: u32,
+};
+
+// One of the additional lights, see State::set_lights
+struct Light {
+    // The direction the light travels in for directional lights
+    position: vec3<f32>,
+    // 0 for point, 1 for directional lights
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+};
+
+// Pipeline-overridable constants, set with State::set_shader_constant
+
+// Multiplies the lit color
+override brightness: f32 = 1.0;
+// Multiplies the texture coordinates, values below 1 zoom into the texture
+override uv_scale: f32 = 1.0;
+
+// Single textures are arrays with one layer, see State::load_texture_array
 @group(0) @binding(0)
-var tree_texture: texture_2d<f32>;
+var tree_texture: texture_2d_array<f32>;
 @group(0) @binding(1)
 var tree_texture_sampler: sampler;
 
@@ -17,36 +61,184 @@ var tree_texture_sampler: sampler;
 var<uniform> camera: CameraUniform;
 
 @group(2) @binding(0)
-var<uniform> rotator: RotatorUniform;
+var<uniform> rotation: RotationUniform;
 
 @group(3) @binding(0)
-var<storage, read> transformations: array<mat4x4<f32>>;
+var<storage, read> instances: array<Instance>;
+
+@group(4) @binding(0)
+var<uniform> light: LightUniform;
+@group(4) @binding(1)
+var shadow_map: texture_depth_2d;
+@group(4) @binding(2)
+var shadow_sampler: sampler_comparison;
+// Replaced with a uniform array where storage buffers aren't supported, see light.rs
+@group(4) @binding(3)
+var<storage, read> lights: array<Light>;
+// Per pixel ambient factor of the SSAO pass, see State::set_ssao
+@group(4) @binding(4)
+var ambient_occlusion: texture_2d<f32>;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) color: vec4<f32>,
     @builtin(instance_index) instance_index: u32
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) light_position: vec4<f32>,
+    @location(4) vertex_color: vec4<f32>,
+    @location(5) world_position: vec3<f32>,
+    @location(6) @interpolate(flat) layer: u32
+};
+
+// The instance as vertex attributes, where the vertex stage can't read storage buffers
+struct InstanceInput {
+    @location(5) transform_0: vec4<f32>,
+    @location(6) transform_1: vec4<f32>,
+    @location(7) transform_2: vec4<f32>,
+    @location(8) transform_3: vec4<f32>,
+    @location(9) color: vec4<f32>,
+    @location(10) layer: u32,
 };
 
 @vertex
 fn vs_main(
     vertex: VertexInput,
 ) -> VertexOutput {
+    return transform_vertex(vertex, instances[vertex.instance_index]);
+}
+
+@vertex
+fn vs_main_instanced(
+    vertex: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let transform = mat4x4<f32>(instance.transform_0, instance.transform_1, instance.transform_2, instance.transform_3);
+    return transform_vertex(vertex, Instance(transform, instance.color, instance.layer));
+}
+
+fn transform_vertex(vertex: VertexInput, instance: Instance) -> VertexOutput {
     var out: VertexOutput;
-    let tr = transformations[vertex.instance_index];
-    out.clip_position = camera.view_proj * tr * rotator.rotation * vec4<f32>(vertex.position, 1.0);
+    let model = instance.transform * rotation.rotation;
+    let world_position = model * vec4<f32>(vertex.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.light_position = light.view_proj * world_position;
     out.tex_coords = vertex.tex_coords;
+    // Instance transforms are rotations and translations only, so no inverse transpose is needed
+    out.normal = (model * vec4<f32>(vertex.normal, 0.0)).xyz;
+    out.color = instance.color;
+    out.vertex_color = vertex.color;
+    out.world_position = world_position.xyz;
+    out.layer = instance.layer;
     return out;
 }
 
 
+// 1 when lit, 0 when in shadow, averaged over 3x3 texels for soft edges
+fn shadow_factor(light_position: vec4<f32>) -> f32 {
+    if (light.shadows == 0u) {
+        return 1.0;
+    }
+    let ndc = light_position.xyz / light_position.w;
+    // Texture coordinates have y pointing down
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    // Everything outside the shadow map is lit
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Diffuse (x) and Blinn-Phong specular (y) factors of a light travelling along `direction`.
+// `view` points from the surface to the eye.
+fn diffuse_specular(direction: vec3<f32>, normal: vec3<f32>, view: vec3<f32>) -> vec2<f32> {
+    let diffuse = max(dot(normal, -direction), 0.0);
+    if (diffuse == 0.0) {
+        return vec2<f32>(0.0);
+    }
+    let half_vector = normalize(view - direction);
+    let specular = pow(max(dot(normal, half_vector), 0.0), light.shininess) * light.specular_strength;
+    return vec2<f32>(diffuse, specular);
+}
+
+// Diffuse and specular factors of one of the additional lights, scaled by its intensity
+// and attenuation
+fn light_contribution(added: Light, normal: vec3<f32>, view: vec3<f32>, world_position: vec3<f32>) -> vec2<f32> {
+    var direction = added.position;
+    var attenuation = 1.0;
+    if (added.kind == 0u) {
+        let offset = world_position - added.position;
+        let distance_squared = dot(offset, offset);
+        direction = offset * inverseSqrt(max(distance_squared, 1e-8));
+        attenuation = 1.0 / (1.0 + distance_squared);
+    }
+    return added.intensity * attenuation * diffuse_specular(direction, normal, view);
+}
+
+// Highlights take the light's color, not the object's
+fn lit(object_color: vec4<f32>, in: VertexOutput) -> vec4<f32> {
+    let normal = normalize(in.normal);
+    let view = normalize(camera.view_position.xyz - in.world_position);
+    let main = diffuse_specular(light.direction, normal, view) * shadow_factor(in.light_position);
+    var ambient = light.ambient;
+    if (light.ambient_occlusion != 0u) {
+        ambient *= textureLoad(ambient_occlusion, vec2<i32>(in.clip_position.xy), 0).r;
+    }
+    var diffuse = light.color * (ambient + main.x);
+    var specular = light.color * main.y;
+    for (var i = 0u; i < light.light_count; i++) {
+        let added = light_contribution(lights[i], normal, view, in.world_position);
+        diffuse += lights[i].color * added.x;
+        specular += lights[i].color * added.y;
+    }
+    return vec4<f32>((object_color.rgb * diffuse + specular) * brightness, object_color.a);
+}
+
+// One fragment entry point per ShadingMode
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(tree_texture, tree_texture_sampler, in.tex_coords);
+    // The layer is clamped to the last one
+    return lit(textureSample(tree_texture, tree_texture_sampler, in.tex_coords * uv_scale, in.layer) * in.color, in);
+}
+
+@fragment
+fn fs_vertex_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    return lit(in.vertex_color * in.color, in);
+}
+
+// Vertex colors times the instance color without the texture and the lighting,
+// lines and points have no meaningful normals or tex coords
+@fragment
+fn fs_unlit(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = in.vertex_color * in.color;
+    return vec4<f32>(color.rgb * brightness, color.a);
+}
+
+// Back faces drawn over the instances, see State::set_show_backfaces
+@fragment
+fn fs_backface() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+}
+
+// World space normals mapped from [-1, 1] to [0, 1], unlit
+@fragment
+fn fs_normals(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(normalize(in.normal) * 0.5 + 0.5, 1.0);
 }
  
\ No newline at end of file
@@ -0,0 +1,46 @@
+// Depth only pass rendering the instances from the light's point of view
+
+struct LightSpace {
+    view_proj: mat4x4<f32>,
+};
+
+struct RotationUniform {
+    rotation: mat4x4<f32>,
+};
+
+struct Instance {
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+    layer: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> light: LightSpace;
+
+@group(1) @binding(0)
+var<uniform> rotation: RotationUniform;
+
+@group(2) @binding(0)
+var<storage, read> instances: array<Instance>;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> @builtin(position) vec4<f32> {
+    let model = instances[instance_index].transform * rotation.rotation;
+    return light.view_proj * model * vec4<f32>(position, 1.0);
+}
+
+// Takes the instance as vertex attributes instead, see Instances::as_vertex_buffer_layout
+@vertex
+fn vs_main_instanced(
+    @location(0) position: vec3<f32>,
+    @location(5) transform_0: vec4<f32>,
+    @location(6) transform_1: vec4<f32>,
+    @location(7) transform_2: vec4<f32>,
+    @location(8) transform_3: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(transform_0, transform_1, transform_2, transform_3) * rotation.rotation;
+    return light.view_proj * model * vec4<f32>(position, 1.0);
+}
@@ -0,0 +1,36 @@
+// Fullscreen triangle sampling the cubemap in the view direction of every pixel
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    // Maps (x, y, 1, 0) in NDC to the world space view ray direction
+    sky_rays: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var sky_texture: texture_cube<f32>;
+@group(1) @binding(1)
+var sky_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Covers the screen with (-1, -1), (3, -1) and (-1, 3)
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.direction = (camera.sky_rays * vec4<f32>(ndc, 1.0, 0.0)).xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(sky_texture, sky_sampler, in.direction);
+}
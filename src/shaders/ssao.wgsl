@@ -0,0 +1,135 @@
+// Screen-space ambient occlusion from the depth prepass and its blur, see Ssao
+
+// Hemisphere samples per pixel
+const SAMPLE_COUNT: u32 = 16u;
+// View space distance keeping surfaces from occluding themselves
+const BIAS: f32 = 0.025;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+struct Params {
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    // View space radius of the hemisphere
+    radius: f32,
+    strength: f32,
+    // Depth the prepass clears to, nothing was drawn there
+    far_depth: f32,
+};
+
+// DepthTexture is declared by Ssao depending on whether the depth buffer is multisampled
+@group(0) @binding(0)
+var depth_texture: DepthTexture;
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+// Output of fs_ssao, in its own bind group layout
+@group(0) @binding(2)
+var occlusion: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // 0 to 1 across the target
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    var out: VertexOutput;
+    out.position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.uv = vec2<f32>(x, y);
+    return out;
+}
+
+fn load_depth(coord: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(depth_texture));
+    return textureLoad(depth_texture, clamp(coord, vec2<i32>(0), size - 1), 0).r;
+}
+
+fn view_position(coord: vec2<i32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let uv = (vec2<f32>(coord) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, load_depth(coord), 1.0);
+    let position = params.inv_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// Of the neighbours on either side, the one on the same surface is closer in depth
+fn tangent(center: vec3<f32>, before: vec3<f32>, after: vec3<f32>) -> vec3<f32> {
+    if (abs(after.z - center.z) < abs(center.z - before.z)) {
+        return after - center;
+    }
+    return center - before;
+}
+
+// Rotates the sample pattern per pixel, repeating every 4x4 pixels for fs_blur to average out
+fn noise(coord: vec2<i32>) -> f32 {
+    let tile = vec2<f32>(coord & vec2<i32>(3));
+    return fract(sin(dot(tile, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.position.xy);
+    if (load_depth(coord) == params.far_depth) {
+        return vec4<f32>(1.0);
+    }
+    let position = view_position(coord);
+    let dx = tangent(position, view_position(coord - vec2<i32>(1, 0)), view_position(coord + vec2<i32>(1, 0)));
+    let dy = tangent(position, view_position(coord - vec2<i32>(0, 1)), view_position(coord + vec2<i32>(0, 1)));
+    var normal = normalize(cross(dx, dy));
+    // The camera looks down -z from the origin
+    if (dot(normal, -position) < 0.0) {
+        normal = -normal;
+    }
+
+    let angle = noise(coord) * 6.28318531;
+    var axis = vec3<f32>(cos(angle), sin(angle), 0.0);
+    if (abs(dot(axis, normal)) > 0.99) {
+        axis = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent_x = normalize(axis - normal * dot(axis, normal));
+    let tangent_y = cross(normal, tangent_x);
+
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    var occluded = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        // Cosine weighted spiral, denser close to the center
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT);
+        let phi = f32(i) * GOLDEN_ANGLE;
+        let r = sqrt(t);
+        let direction = tangent_x * r * cos(phi) + tangent_y * r * sin(phi) + normal * sqrt(1.0 - t);
+        let sample = position + direction * params.radius * mix(0.1, 1.0, t * t);
+
+        let clip = params.proj * vec4<f32>(sample, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            continue;
+        }
+        let scene = view_position(vec2<i32>(uv * size));
+        // Geometry far in front of the hemisphere doesn't occlude it
+        let range = smoothstep(0.0, 1.0, params.radius / abs(position.z - scene.z));
+        if (scene.z >= sample.z + BIAS) {
+            occluded += range;
+        }
+    }
+    let ambient = 1.0 - occluded / f32(SAMPLE_COUNT) * params.strength;
+    return vec4<f32>(clamp(ambient, 0.0, 1.0), 0.0, 0.0, 1.0);
+}
+
+// 4x4 box blur, the size of the noise's tile
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.position.xy);
+    let size = vec2<i32>(textureDimensions(occlusion));
+    var sum = 0.0;
+    for (var y = -2; y < 2; y++) {
+        for (var x = -2; x < 2; x++) {
+            sum += textureLoad(occlusion, clamp(coord + vec2<i32>(x, y), vec2<i32>(0), size - 1), 0).r;
+        }
+    }
+    return vec4<f32>(sum / 16.0, 0.0, 0.0, 1.0);
+}
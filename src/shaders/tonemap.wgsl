@@ -0,0 +1,51 @@
+// Maps the HDR scene to the output, see ToneMapper
+
+struct Params {
+    // 0 none, 1 Reinhard, 2 ACES
+    op: u32,
+    // Scales the scene before the tone mapping
+    exposure: f32,
+    // 1.0 for sRGB outputs, the hardware encodes those
+    gamma: f32,
+};
+
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // 0 to 1 across the target
+    @location(0) uv: vec2<f32>,
+};
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let x = f32((in_vertex_index << 1u) & 2u);
+    let y = f32(in_vertex_index & 2u);
+    var out: VertexOutput;
+    out.position = vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
+    out.uv = vec2<f32>(x, y);
+    return out;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Nearest texel, targets of another size than the scene get it scaled
+    let hdr = textureLoad(hdr_texture, vec2<i32>(in.uv * vec2<f32>(textureDimensions(hdr_texture))), 0);
+    var color = max(hdr.rgb, vec3<f32>(0.0)) * params.exposure;
+    if (params.op == 1u) {
+        color = color / (1.0 + color);
+    } else if (params.op == 2u) {
+        color = aces(color);
+    }
+    color = pow(color, vec3<f32>(1.0 / params.gamma));
+    return vec4<f32>(color, hdr.a);
+}
@@ -0,0 +1,185 @@
+use wgpu::{BindGroupLayout, Device};
+
+use crate::instances::Instances;
+use crate::mesh::Mesh;
+use crate::texture::{SamplerConfig, Texture};
+
+pub const DEFAULT_SIZE: u32 = 2048;
+
+// Depth of the scene seen from the directional light, sampled with a
+// comparison sampler in the scene shader
+pub struct ShadowMap {
+    texture: Texture,
+    size: u32,
+    // Only the light-space view projection, the scene layout also holds the shadow map
+    pub light_layout: BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    pub fn new(device: &Device,
+               size: u32,
+               rotation_layout: &BindGroupLayout,
+               instances_layout: &BindGroupLayout,
+               instance_vertex_buffer: bool) -> Self {
+        let light_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_light_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline = Self::create_pipeline(device, &[&light_layout, rotation_layout, instances_layout], instance_vertex_buffer);
+        Self {
+            texture: Self::create_texture(device, size),
+            size,
+            light_layout,
+            pipeline,
+        }
+    }
+
+    fn create_texture(device: &Device, size: u32) -> Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_map"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Compares against the fragment's light-space depth, Linear filtering
+        // blends the four nearest comparison results
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_map_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        Texture { texture, view, sampler, sampler_config: SamplerConfig { min_filter: wgpu::FilterMode::Linear, ..Default::default() } }
+    }
+
+    // Has to be called when the instances switch between storage and vertex attributes
+    pub fn rebuild_pipeline(&mut self,
+                            device: &Device,
+                            rotation_layout: &BindGroupLayout,
+                            instances_layout: &BindGroupLayout,
+                            instance_vertex_buffer: bool) {
+        self.pipeline = Self::create_pipeline(device, &[&self.light_layout, rotation_layout, instances_layout], instance_vertex_buffer);
+    }
+
+    fn create_pipeline(device: &Device, bind_group_layouts: &[&BindGroupLayout], instance_vertex_buffer: bool) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shadow.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let (entry_point, buffers) = Instances::vertex_input(instance_vertex_buffer);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point,
+                buffers: &buffers,
+                compilation_options: Default::default(),
+            },
+            // Depth only
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                // Pushes the stored depth away from the light against shadow acne
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    // The light's bind group has to be recreated afterwards
+    pub fn resize(&mut self, device: &Device, size: u32) {
+        self.size = size;
+        self.texture = Self::create_texture(device, size);
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.texture.sampler
+    }
+
+    // Renders all instances of `mesh` into the shadow map
+    pub fn render(&self,
+                  encoder: &mut wgpu::CommandEncoder,
+                  light_bind_group: &wgpu::BindGroup,
+                  rotation_bind_group: &wgpu::BindGroup,
+                  instances: &Instances,
+                  mesh: &Mesh) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, light_bind_group, &[]);
+        render_pass.set_bind_group(1, rotation_bind_group, &[]);
+        render_pass.set_bind_group(2, &instances.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instances.buffer().slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format());
+        render_pass.draw_indexed(0..mesh.num_indices(), 0, 0..instances.count());
+    }
+}
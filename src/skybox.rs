@@ -0,0 +1,145 @@
+use wgpu::{BindGroupLayout, Device, TextureFormat};
+
+use crate::texture::Cubemap;
+
+// Cubemap drawn behind the scene, only the camera rotation moves it
+pub struct Skybox {
+    pipeline: wgpu::RenderPipeline,
+    cubemap_layout: BindGroupLayout,
+    // None until a cubemap is loaded
+    cubemap_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Skybox {
+    pub fn new(device: &Device,
+               target_texture_format: TextureFormat,
+               sample_count: u32,
+               depth_format: TextureFormat,
+               camera_layout: &BindGroupLayout) -> Self {
+        let cubemap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("skybox_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        Self {
+            pipeline: Self::create_pipeline(device, target_texture_format, sample_count, depth_format, camera_layout, &cubemap_layout),
+            cubemap_layout,
+            cubemap_bind_group: None,
+        }
+    }
+
+    pub fn rebuild_pipeline(&mut self,
+                            device: &Device,
+                            target_texture_format: TextureFormat,
+                            sample_count: u32,
+                            depth_format: TextureFormat,
+                            camera_layout: &BindGroupLayout) {
+        self.pipeline = Self::create_pipeline(device, target_texture_format, sample_count, depth_format, camera_layout, &self.cubemap_layout);
+    }
+
+    fn create_pipeline(device: &Device,
+                       target_texture_format: TextureFormat,
+                       sample_count: u32,
+                       depth_format: TextureFormat,
+                       camera_layout: &BindGroupLayout,
+                       cubemap_layout: &BindGroupLayout) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/skybox.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, cubemap_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_texture_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            // Drawn first and leaves the depth buffer at the far plane, so
+            // everything drawn afterwards ends up in front of it
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    pub fn set_cubemap(&mut self, device: &Device, cubemap: &Cubemap) {
+        self.cubemap_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.cubemap_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        }));
+    }
+
+    // Back to the clear color, until the next set_cubemap
+    pub fn clear_cubemap(&mut self) {
+        self.cubemap_bind_group = None;
+    }
+
+    // No-op until a cubemap is loaded
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        let Some(cubemap_bind_group) = &self.cubemap_bind_group else {
+            return;
+        };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, cubemap_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
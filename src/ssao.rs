@@ -0,0 +1,370 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, CommandEncoder, Device, Queue, TextureFormat, TextureView};
+
+use crate::camera::CameraModel;
+use crate::depth_view::with_depth_texture_alias;
+use crate::instances::Instances;
+use crate::texture::Texture;
+
+// Single channel ambient factor, 1 where nothing occludes
+const OCCLUSION_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    radius: f32,
+    strength: f32,
+    far_depth: f32,
+    _padding: f32,
+}
+
+// What the pipelines depend on besides the layouts, see `Ssao::rebuild_pipelines`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PrepassConfig {
+    pub sample_count: u32,
+    pub depth_format: TextureFormat,
+    pub depth_compare: wgpu::CompareFunction,
+    pub cull_mode: Option<wgpu::Face>,
+    pub instance_vertex_buffer: bool,
+}
+
+struct Pipelines {
+    prepass: wgpu::RenderPipeline,
+    ssao: wgpu::RenderPipeline,
+    blur: wgpu::RenderPipeline,
+}
+
+// Full resolution textures of the raw and the blurred occlusion, recreated with the scene's size
+struct Targets {
+    raw_view: TextureView,
+    view: TextureView,
+    ssao_bind_group: wgpu::BindGroup,
+    blur_bind_group: wgpu::BindGroup,
+}
+
+// Darkens the ambient light in creases and corners. The depth prepass fills the scene's
+// depth texture, occlusion is estimated from it by sampling a hemisphere around every
+// pixel, blurred and read by the scene shader in place of a constant ambient factor.
+pub struct Ssao {
+    params_buffer: wgpu::Buffer,
+    // Depends on whether the depth texture is multisampled
+    ssao_layout: BindGroupLayout,
+    sample_count: u32,
+    blur_layout: BindGroupLayout,
+    pipelines: Pipelines,
+    targets: Targets,
+    radius: f32,
+    strength: f32,
+}
+
+impl Ssao {
+    pub const DEFAULT_RADIUS: f32 = 0.5;
+    pub const DEFAULT_STRENGTH: f32 = 1.0;
+
+    // `layouts` are the camera, rotation and instances layouts
+    pub fn new(device: &Device,
+               config: PrepassConfig,
+               layouts: [&BindGroupLayout; 3],
+               depth_texture: &Texture,
+               width: u32,
+               height: u32) -> Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Params Buffer"),
+            contents: bytemuck::cast_slice(&[ParamsUniform {
+                proj: cgmath::Matrix4::identity().into(),
+                inv_proj: cgmath::Matrix4::identity().into(),
+                radius: Self::DEFAULT_RADIUS,
+                strength: Self::DEFAULT_STRENGTH,
+                far_depth: 1.0,
+                _padding: 0.0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let blur_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ssao_blur_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+        let ssao_layout = Self::create_ssao_layout(device, config.sample_count);
+        let pipelines = Self::create_pipelines(device, config, layouts, &ssao_layout, &blur_layout);
+        let targets = Self::create_targets(device, &ssao_layout, &blur_layout, &params_buffer, depth_texture, width, height);
+        Self {
+            params_buffer,
+            ssao_layout,
+            sample_count: config.sample_count,
+            blur_layout,
+            pipelines,
+            targets,
+            radius: Self::DEFAULT_RADIUS,
+            strength: Self::DEFAULT_STRENGTH,
+        }
+    }
+
+    fn create_ssao_layout(device: &Device, sample_count: u32) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ssao_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        // Not Depth, GLSL can't textureLoad from depth textures
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: sample_count > 1,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    fn create_pipelines(device: &Device,
+                        config: PrepassConfig,
+                        [camera_layout, rotation_layout, instances_layout]: [&BindGroupLayout; 3],
+                        ssao_layout: &BindGroupLayout,
+                        blur_layout: &BindGroupLayout) -> Pipelines {
+        // The light space shader only projects the instances, the camera's view_proj comes first in its uniform too
+        let prepass_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Prepass Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shadow.wgsl").into()),
+        });
+        let prepass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Prepass Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, rotation_layout, instances_layout],
+            push_constant_ranges: &[],
+        });
+        let (entry_point, buffers) = Instances::vertex_input(config.instance_vertex_buffer);
+        let prepass = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("SSAO Prepass Pipeline"),
+            layout: Some(&prepass_layout),
+            vertex: wgpu::VertexState {
+                module: &prepass_shader,
+                entry_point,
+                buffers: &buffers,
+                compilation_options: Default::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: config.cull_mode,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: config.depth_format,
+                depth_write_enabled: true,
+                depth_compare: config.depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: config.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        let source = with_depth_texture_alias(config.sample_count, include_str!("shaders/ssao.wgsl"));
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let create = |label: &str, entry_point: &str, layout: &BindGroupLayout| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: OCCLUSION_FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let ssao = create("SSAO Pipeline", "fs_ssao", ssao_layout);
+        let blur = create("SSAO Blur Pipeline", "fs_blur", blur_layout);
+        Pipelines { prepass, ssao, blur }
+    }
+
+    fn create_targets(device: &Device,
+                      ssao_layout: &BindGroupLayout,
+                      blur_layout: &BindGroupLayout,
+                      params_buffer: &wgpu::Buffer,
+                      depth_texture: &Texture,
+                      width: u32,
+                      height: u32) -> Targets {
+        let create_view = || {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("ssao_texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: OCCLUSION_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let raw_view = create_view();
+        let view = create_view();
+        // Only the depth aspect of combined depth-stencil formats can be sampled
+        let depth_only_view = depth_texture.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        });
+        let ssao_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: ssao_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_only_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("ssao_bind_group"),
+        });
+        let blur_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: blur_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&raw_view),
+            }],
+            label: Some("ssao_blur_bind_group"),
+        });
+        Targets { raw_view, view, ssao_bind_group, blur_bind_group }
+    }
+
+    // Call with the recreated depth texture after a resize or a sample count change
+    pub fn resize(&mut self, device: &Device, depth_texture: &Texture, width: u32, height: u32) {
+        self.targets = Self::create_targets(device, &self.ssao_layout, &self.blur_layout, &self.params_buffer, depth_texture, width, height);
+    }
+
+    // Has to be called when any of `config` changes and when the instances switch between
+    // storage and vertex attributes. A new sample count needs a `resize` afterwards.
+    pub fn rebuild_pipelines(&mut self, device: &Device, config: PrepassConfig, layouts: [&BindGroupLayout; 3]) {
+        if config.sample_count != self.sample_count {
+            self.sample_count = config.sample_count;
+            self.ssao_layout = Self::create_ssao_layout(device, config.sample_count);
+        }
+        self.pipelines = Self::create_pipelines(device, config, layouts, &self.ssao_layout, &self.blur_layout);
+    }
+
+    // The blurred ambient factor
+    pub fn view(&self) -> &TextureView {
+        &self.targets.view
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    pub fn set_params(&mut self, radius: f32, strength: f32) {
+        self.radius = radius;
+        self.strength = strength;
+    }
+
+    // Uploads the camera's projection, call before rendering when it or the params changed
+    pub fn update(&self, queue: &Queue, camera: &CameraModel) {
+        let proj = camera.build_projection_matrix();
+        let params = ParamsUniform {
+            proj: proj.into(),
+            // Projections are always invertible
+            inv_proj: proj.invert().unwrap().into(),
+            radius: self.radius,
+            strength: self.strength,
+            far_depth: if camera.reverse_z { 0.0 } else { 1.0 },
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    // The depth prepass, drawn with the camera, rotation and instances bind groups at 0 to 2
+    pub fn prepass_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipelines.prepass
+    }
+
+    // Estimates the occlusion from the depth texture the prepass filled
+    pub fn render(&self, encoder: &mut CommandEncoder) {
+        Self::run(encoder, "SSAO Pass", &self.targets.raw_view, &self.pipelines.ssao, &self.targets.ssao_bind_group);
+        Self::run(encoder, "SSAO Blur Pass", &self.targets.view, &self.pipelines.blur, &self.targets.blur_bind_group);
+    }
+
+    fn run(encoder: &mut CommandEncoder,
+           label: &str,
+           target: &TextureView,
+           pipeline: &wgpu::RenderPipeline,
+           bind_group: &wgpu::BindGroup) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -1,95 +1,550 @@
+use std::collections::HashMap;
 use std::f64::consts::PI;
+use std::fmt;
+use std::time::Duration;
 
+use cgmath::{Deg, Point3, Vector3};
+use web_time::Instant;
 use wgpu::{BindGroupLayout, CommandEncoder, Device, StoreOp, SurfaceConfiguration, TextureView};
-use wgpu::hal::empty::Encoder;
 use winit::{
-    dpi::PhysicalPosition,
-    event::WindowEvent,
-    window::Window,
+    dpi::{LogicalPosition, PhysicalPosition},
+    event::{ElementState, KeyEvent, MouseButton, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{CursorGrabMode, Window},
 };
 
 use crate::instances::{Instances, Rotation};
-use crate::mesh::{Mesh, Vertex};
-use crate::{camera::{CameraState}, texture::{self, Texture}};
-use crate::depth_view::DepthView;
+use crate::mesh::Mesh;
+use crate::{camera::{CameraMode, CameraState, Projection, Viewpoint}, texture::{self, Cubemap, SamplerConfig, Texture, TextureError}};
+use crate::depth_readback::DepthReadback;
+use crate::depth_view::{Colormap, DepthView};
+use crate::light::{self, Light, LightState};
+use crate::frame_timer::FrameTimer;
+use crate::profiler::{GpuProfiler, PassTimings};
+use crate::ground_grid::GroundGrid;
+use crate::bounds_box::BoundsBox;
+use crate::axis_gizmo::{AxisGizmo, Corner};
+use crate::bloom::Bloom;
+use crate::fxaa::Fxaa;
+use crate::ssao::{PrepassConfig, Ssao};
+use crate::outline::{self, Outline};
+use crate::skybox::Skybox;
+use crate::tonemap::{ToneMapOp, ToneMapper};
+use crate::recorder::Recorder;
+use crate::instance_animation::InstanceAnimation;
+use crate::particles::{ParticleRenderer, Particles};
+use crate::shadow_map::{self, ShadowMap};
+use crate::render_graph::{PassId, RenderGraph, Resource};
+#[cfg(feature = "egui")]
+use crate::egui_overlay::EguiOverlay;
 
+// Host rendering into the frame, see State::set_overlay_renderer
+pub type OverlayRenderer = Box<dyn Fn(&mut CommandEncoder, &TextureView)>;
+
+// Time advanced by a single `step`
+const STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// The override declarations of shaders.wgsl
+const SCENE_SHADER_CONSTANTS: &[&str] = &["brightness", "uv_scale"];
+
+// Range of `set_fov`, and the change per +/- key press
+const MIN_FOVY: Deg<f32> = Deg(10.0);
+const MAX_FOVY: Deg<f32> = Deg(120.0);
+const FOVY_STEP: Deg<f32> = Deg(5.0);
+
+// Of the textures the State loads itself, where the adapter supports anisotropic filtering
+const DEFAULT_ANISOTROPY: u16 = 16;
+
+// Two left clicks closer than this in time and in pixels focus the camera, see `focus_at`
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+const DOUBLE_CLICK_DISTANCE: f64 = 4.0;
+// Of the camera animation to a focused point
+const FOCUS_DURATION: Duration = Duration::from_millis(500);
+
+// Number of particles created by `set_particles_enabled`
+const DEFAULT_PARTICLE_COUNT: u32 = 4096;
+
+// Upper bound of `set_frame_latency`, more only adds lag
+const MAX_FRAME_LATENCY: u32 = 3;
+
+// Format of the texture rendered into by a headless State
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Debug)]
+pub struct PresentModeError {
+    pub requested: wgpu::PresentMode,
+    pub supported: Vec<wgpu::PresentMode>,
+}
+
+impl fmt::Display for PresentModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "present mode {:?} is not supported, supported modes are {:?}", self.requested, self.supported)
+    }
+}
+
+impl std::error::Error for PresentModeError {}
+
+#[derive(Debug)]
+pub struct AlphaModeError {
+    pub requested: wgpu::CompositeAlphaMode,
+    pub supported: Vec<wgpu::CompositeAlphaMode>,
+}
+
+impl fmt::Display for AlphaModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "alpha mode {:?} is not supported, supported modes are {:?}", self.requested, self.supported)
+    }
+}
+
+impl std::error::Error for AlphaModeError {}
+
+#[derive(Debug)]
+pub struct ShaderConstantError {
+    pub name: String,
+}
+
+impl fmt::Display for ShaderConstantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "shaders.wgsl has no override constant `{}`, the available ones are {:?}", self.name, SCENE_SHADER_CONSTANTS)
+    }
+}
+
+impl std::error::Error for ShaderConstantError {}
+
+#[derive(Debug)]
+pub struct DepthFormatError {
+    pub format: wgpu::TextureFormat,
+}
+
+impl fmt::Display for DepthFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} can't be used as the depth buffer, it has to be renderable and sampleable as depth on the adapter", self.format)
+    }
+}
+
+impl std::error::Error for DepthFormatError {}
+
+#[derive(Debug)]
+pub struct MeshIndexError {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl fmt::Display for MeshIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mesh index {} is out of range, there are {} meshes", self.index, self.count)
+    }
+}
+
+impl std::error::Error for MeshIndexError {}
+
+// Depth test of the scene pipeline. The depth buffer is cleared to the far plane, 1.0
+// unless reverse-Z is on, so Greater and GreaterEqual discard everything without
+// reverse-Z and Less and LessEqual with it. State::set_reverse_z flips the compare function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DepthSettings {
+    pub compare: wgpu::CompareFunction,
+    pub write_enabled: bool,
+}
+
+impl Default for DepthSettings {
+    fn default() -> Self {
+        Self {
+            compare: wgpu::CompareFunction::Less,
+            write_enabled: true,
+        }
+    }
+}
+
+// Whether frames change without input, see State::render_mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderMode {
+    // Something is animating, redraw every frame, e.g. with ControlFlow::Poll
+    Continuous,
+    // Frames only change on input, e.g. wait for events with ControlFlow::Wait
+    Idle,
+}
+
+// What the scene is cleared to, a loaded skybox is drawn over it
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BackgroundMode {
+    Solid(wgpu::Color),
+    // Follows the cursor position
+    CursorTracking,
+    // Goes once around the hue circle every `period`
+    HueCycle { period: Duration },
+}
+
+// Adapter selection, the default lets wgpu pick among all backends
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StateConfig {
+    pub power_preference: wgpu::PowerPreference,
+    pub backends: wgpu::Backends,
+    // Software rendering, e.g. for reproducible screenshots
+    pub force_fallback_adapter: bool,
+    pub settings: Settings,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            power_preference: wgpu::PowerPreference::default(),
+            backends: wgpu::Backends::all(),
+            force_fallback_adapter: false,
+            settings: Settings::default(),
+        }
+    }
+}
+
+// The scene a State starts with. With the `serde` feature it can be loaded from a config
+// file, fields missing there keep their defaults.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct Settings {
+    pub background: BackgroundMode,
+    // Degrees per second around x and y
+    pub rotation_speed: (f32, f32),
+    // Of the instance grid, rows and cols are clamped to at least 1
+    pub grid_rows: u32,
+    pub grid_cols: u32,
+    pub grid_spacing: f32,
+    pub camera: Viewpoint,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            background: BackgroundMode::CursorTracking,
+            rotation_speed: (60.0, 48.0),
+            grid_rows: 4,
+            grid_cols: 4,
+            grid_spacing: 2.0,
+            // One unit up and 2 units back looking at the origin, +z is out of the screen
+            camera: Viewpoint {
+                eye: Point3::new(0.0, 1.0, 2.0),
+                target: Point3::new(0.0, 0.0, 0.0),
+            },
+        }
+    }
+}
+
+// How the instances are colored
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ShadingMode {
+    // The texture times the instance color
+    #[default]
+    Textured,
+    // The mesh's vertex colors times the instance color
+    VertexColor,
+    // World space normals as RGB, unlit
+    Normals,
+}
+
+impl ShadingMode {
+    fn fragment_entry_point(self) -> &'static str {
+        match self {
+            ShadingMode::Textured => "fs_main",
+            ShadingMode::VertexColor => "fs_vertex_color",
+            ShadingMode::Normals => "fs_normals",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StateInitError {
+    CreateSurface(wgpu::CreateSurfaceError),
+    // No adapter matched the request, `surface` tells whether one compatible with a window was needed
+    NoAdapter { surface: bool },
+    RequestDevice(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for StateInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateInitError::CreateSurface(e) => write!(f, "failed to create a surface for the window: {}", e),
+            StateInitError::NoAdapter { surface: true } => write!(f, "no graphics adapter can present to the window"),
+            StateInitError::NoAdapter { surface: false } => write!(f, "no graphics adapter found"),
+            StateInitError::RequestDevice(e) => write!(f, "failed to request a device from the adapter: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateInitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateInitError::CreateSurface(e) => Some(e),
+            StateInitError::NoAdapter { .. } => None,
+            StateInitError::RequestDevice(e) => Some(e),
+        }
+    }
+}
+
+// Variants of the scene pipeline, they only differ in rasterization and blending
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ScenePipelineKind {
+    Fill,
+    Wireframe,
+    // Alpha blended without depth writes, instances have to be drawn back to front
+    Transparent,
+    // Only the back faces in a flat red, see `set_show_backfaces`
+    Backfaces,
+}
+
+// Everything the scene pipelines are built from besides the shader and the layouts
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ScenePipelineConfig {
+    sample_count: u32,
+    depth_format: wgpu::TextureFormat,
+    depth_settings: DepthSettings,
+    cull_mode: Option<wgpu::Face>,
+    shading: ShadingMode,
+    instance_vertex_buffer: bool,
+}
+
+struct ScenePipelines {
+    fill: wgpu::RenderPipeline,
+    // None when the device doesn't support POLYGON_MODE_LINE and for lines and points
+    wireframe: Option<wgpu::RenderPipeline>,
+    transparent: wgpu::RenderPipeline,
+    // None for lines and points, which have no faces
+    backfaces: Option<wgpu::RenderPipeline>,
+}
+
+impl ScenePipelines {
+    // Falls back to fill when there is no wireframe pipeline
+    fn select(&self, wireframe: bool, transparent: bool) -> &wgpu::RenderPipeline {
+        match &self.wireframe {
+            Some(wireframe_pipeline) if wireframe => wireframe_pipeline,
+            _ if transparent => &self.transparent,
+            _ => &self.fill,
+        }
+    }
+}
+
+// Untextured plane below the default instance grid, drawn with the scene pipeline
+struct GroundPlane {
+    mesh: Mesh,
+    // 1x1 white texture
+    texture_bind_group: wgpu::BindGroup,
+    // Never advanced, the plane doesn't spin with the instances
+    rotation: Rotation,
+    instance_buffer: wgpu::Buffer,
+    instance_bind_group: wgpu::BindGroup,
+}
+
+impl GroundPlane {
+    const SIZE: f32 = 20.0;
+    const HEIGHT: f32 = -4.5;
+    const COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+    fn new(device: &Device,
+           queue: &wgpu::Queue,
+           backend: wgpu::Backend,
+           texture_layout: &BindGroupLayout,
+           rotation_layout: &BindGroupLayout,
+           instances: &Instances) -> Self {
+        let white = image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]));
+        let texture = Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(white), Some("white")).unwrap();
+        let transform = cgmath::Matrix4::from_translation(Vector3::new(0.0, Self::HEIGHT, 0.0));
+        let (instance_buffer, instance_bind_group) = instances.single_instance(device, transform, Self::COLOR);
+        Self {
+            mesh: Mesh::plane(device, Self::SIZE, 1),
+            texture_bind_group: State::create_texture_bind_group(device, texture_layout, &State::scene_texture_view(device, queue, backend, &texture.texture), &texture.sampler),
+            rotation: Rotation::new(device, rotation_layout),
+            instance_buffer,
+            instance_bind_group,
+        }
+    }
+}
+
+// Owns the device and everything rendered with it. Create it with `new` for a window
+// (then forward window events to `input`, call `update` and `render` every frame
+// and `resize` on resize) or with `new_headless` to render into a texture.
 pub struct State<'a> {
-    surface: wgpu::Surface<'a>,
-    window: &'a Window,
+    // Both are None for a headless State
+    surface: Option<wgpu::Surface<'a>>,
+    window: Option<&'a Window>,
+    // Render target used instead of the surface when headless
+    offscreen_texture: Option<wgpu::Texture>,
+    // The adapter the device was requested from
+    adapter: wgpu::Adapter,
+    // What the device was created with, optional features are only requested when available
+    features: wgpu::Features,
+    limits: wgpu::Limits,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
-    pub size: winit::dpi::PhysicalSize<u32>,
+    // Of the surface with the adapter, queried once at construction
+    surface_caps: wgpu::SurfaceCapabilities,
+    size: winit::dpi::PhysicalSize<u32>,
+    // The device's max_texture_dimension_2d, render targets can't be larger
+    max_texture_dimension: u32,
+    // Physical pixels per logical pixel of the window's monitor, 1.0 when headless
+    scale_factor: f64,
     background_color: wgpu::Color,
-    render_pipeline: wgpu::RenderPipeline,
-    mesh: Mesh,
+    background_mode: BackgroundMode,
+    // Animation time of the hue cycle, doesn't advance while paused
+    background_time: Duration,
+    scene_shader: wgpu::ShaderModule,
+    // Source of scene_shader, changes when the shaders are reloaded
+    scene_shader_source: String,
+    // Values of the scene shader's override constants, unset ones keep their WGSL default
+    shader_constants: HashMap<String, f64>,
+    // By the topology of the meshes drawn with them
+    scene_pipelines: HashMap<wgpu::PrimitiveTopology, ScenePipelines>,
+    // Of the scene's depth buffer, the shadow map keeps Texture::DEPTH_FORMAT
+    depth_format: wgpu::TextureFormat,
+    depth_settings: DepthSettings,
+    // None draws both sides of every triangle
+    cull_mode: Option<wgpu::Face>,
+    shading: ShadingMode,
+    wireframe: bool,
+    transparent: bool,
+    // Back faces of the instances are drawn over them in red
+    show_backfaces: bool,
+    indirect_draw_supported: bool,
+    // Draw the instances with draw_indexed_indirect
+    indirect_draw: bool,
+    // Only the instances in view of a camera are drawn, see `set_frustum_culling`
+    frustum_culling: bool,
+    // Instances drawn by the scene pass of the last frame
+    last_draw_count: u32,
+    // False e.g. on WebGL, the instances then always come from a vertex buffer
+    vertex_storage_supported: bool,
+    // Instances are read as vertex attributes instead of from a storage buffer
+    instance_vertex_buffer: bool,
+    // Starts with the cube, only the selected one is drawn
+    meshes: Vec<Mesh>,
+    selected_mesh: usize,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    // Of the texture drawn on the instances, or of a copy of it where it can't be bound as
+    // is, see `scene_texture_view`. Keeps the texture alive.
+    texture_view: TextureView,
+    texture_sampler_config: SamplerConfig,
+    texture_sampler: wgpu::Sampler,
     texture_bind_group: wgpu::BindGroup,
-    camera_state: CameraState,
-    rotator: Rotation,
+    // Given to the textures loaded by the State, 1 without DownlevelFlags::ANISOTROPIC_FILTERING
+    anisotropy: u16,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // Draws the right half while the split view is on, see `set_split_view`
+    split_camera: Option<CameraState>,
+    pub camera_state: CameraState,
+    rotation_bind_group_layout: wgpu::BindGroupLayout,
+    pub rotation: Rotation,
     pub instances: Instances,
+    // None when the device can't run compute shaders
+    instance_animation: Option<InstanceAnimation>,
+    // Some while the particles are shown, needs compute shaders
+    particles: Option<Particles>,
+    particle_renderer: ParticleRenderer,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    light: LightState,
+    shadow_map: ShadowMap,
+    // Receives the shadows, only drawn while shadows are on
+    ground_plane: GroundPlane,
     depth_texture: Texture,
-    depth_view: Option<DepthView>
+    depth_view: Option<DepthView>,
+    // For `world_position_at`
+    depth_readback: DepthReadback,
+    ground_grid: GroundGrid,
+    ground_grid_visible: bool,
+    // Encloses all instances
+    bounds_box: BoundsBox,
+    bounds_visible: bool,
+    // Drawn over the finished frame, after the depth view
+    axis_gizmo: AxisGizmo,
+    axis_gizmo_enabled: bool,
+    axis_gizmo_corner: Corner,
+    // Host drawing of its own, see `set_overlay_renderer`
+    overlay_renderer: Option<OverlayRenderer>,
+    // Needs a depth format with stencil, see `set_outline`
+    outline: Outline,
+    outline_enabled: bool,
+    // Replaces the background color once a cubemap is loaded
+    skybox: Skybox,
+    // The scene is rendered into its HDR texture
+    tone_mapper: ToneMapper,
+    // Added onto the HDR scene before tone mapping when enabled
+    bloom: Bloom,
+    bloom_enabled: bool,
+    // Some when FXAA is on, the scene is tone mapped into its texture
+    fxaa: Option<Fxaa>,
+    // Modulates the ambient light when enabled, after a depth prepass
+    ssao: Ssao,
+    ssao_enabled: bool,
+    // Some between `start_recording` and `stop_recording`
+    recorder: Option<Recorder>,
+    // The order `render` runs its passes in
+    render_graph: RenderGraph,
+    // Sample counts usable for both the HDR target and the depth format
+    msaa_flags: wgpu::TextureFormatFeatureFlags,
+    sample_count: u32,
+    // Multisampled color target resolved into the surface texture, None without MSAA
+    msaa_view: Option<TextureView>,
+    last_update: Instant,
+    // None renders as fast as the present mode allows
+    target_fps: Option<u32>,
+    // When `render` may present the next frame with a target_fps
+    next_frame: Instant,
+    // Animations don't advance, see `step`
+    paused: bool,
+    // The frame the animations are computed for instead of advancing, see `set_deterministic`
+    deterministic_frame: Option<u64>,
+    // Set by a resize to zero, as on minimizing, until the next proper size
+    minimized: bool,
+    // Set by `set_visible`, e.g. on WindowEvent::Occluded
+    occluded: bool,
+    // In physical pixels, None until the cursor entered the window
+    cursor_position: Option<PhysicalPosition<f64>>,
+    // For detecting double clicks
+    last_click: Option<(Instant, PhysicalPosition<f64>)>,
+    frame_timer: FrameTimer,
+    // None when timestamp queries aren't supported
+    profiler: Option<GpuProfiler>,
+    #[cfg(feature = "egui")]
+    egui_overlay: EguiOverlay,
 }
 
 impl <'a> State<'a> {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &'a Window) -> Self {
+    pub async fn new(window: &'a Window, settings: Settings) -> Result<Self, StateInitError> {
+        Self::with_config(window, StateConfig { settings, ..Default::default() }).await
+    }
+
+    pub async fn with_config(window: &'a Window, state_config: StateConfig) -> Result<Self, StateInitError> {
         let size = window.inner_size();
 
-        // The instance is a handle to our GPU
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            flags: Default::default(),
-            dx12_shader_compiler: Default::default(),
-            gles_minor_version: Default::default(),
-        });
+        let instance = Self::create_instance(state_config.backends);
 
-        let surface = instance.create_surface(window).unwrap();
+        let surface = instance.create_surface(window).map_err(StateInitError::CreateSurface)?;
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
+                power_preference: state_config.power_preference,
                 compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                force_fallback_adapter: state_config.force_fallback_adapter,
             })
             .await
-            .unwrap();
+            .ok_or(StateInitError::NoAdapter { surface: true })?;
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    required_limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                    label: None,
-                    required_features: Default::default(),
-                    memory_hints: Default::default(),
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = Self::request_device(&adapter).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
+        // Prefer an sRGB surface so the hardware does the encoding, otherwise the
+        // tone mapping pass applies it in the shader
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
+        let max = device.limits().max_texture_dimension_2d;
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width: size.width,
-            height: size.height,
+            width: size.width.min(max),
+            height: size.height.min(max),
             present_mode: surface_caps.present_modes[0],
             desired_maximum_frame_latency: 1,
             alpha_mode: surface_caps.alpha_modes[0],
@@ -97,8 +552,137 @@ impl <'a> State<'a> {
         };
         surface.configure(&device, &config);
 
+        Ok(Self::from_parts(Some(surface), Some(window), adapter, device, queue, config, surface_caps).with_settings(state_config.settings))
+    }
+
+    // Renders into an owned texture instead of a window surface, see `render_headless`.
+    // Sides of zero or above the device limit are clamped.
+    pub async fn new_headless(width: u32, height: u32) -> Result<State<'static>, StateInitError> {
+        Self::headless_with_config(width, height, StateConfig::default()).await
+    }
+
+    pub async fn headless_with_config(width: u32, height: u32, state_config: StateConfig) -> Result<State<'static>, StateInitError> {
+        let instance = Self::create_instance(state_config.backends);
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: state_config.power_preference,
+                compatible_surface: None,
+                force_fallback_adapter: state_config.force_fallback_adapter,
+            })
+            .await
+            .ok_or(StateInitError::NoAdapter { surface: false })?;
+
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        // The offscreen texture can't be empty or exceed the device limit
+        let max = device.limits().max_texture_dimension_2d;
+        let (clamped_width, clamped_height) = (width.clamp(1, max), height.clamp(1, max));
+        if (clamped_width, clamped_height) != (width, height) {
+            log::warn!("{}x{} is outside 1..={} per side, rendering at {}x{}",
+                       width, height, max, clamped_width, clamped_height);
+        }
+
+        // There is no surface to configure, but the rest of State only
+        // needs the size and the format from the configuration.
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: HEADLESS_FORMAT,
+            width: clamped_width,
+            height: clamped_height,
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 1,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+
+        // Nothing is presented, so only the configured modes are reported
+        let surface_caps = wgpu::SurfaceCapabilities {
+            formats: vec![HEADLESS_FORMAT],
+            present_modes: vec![config.present_mode],
+            alpha_modes: vec![config.alpha_mode],
+            usages: config.usage,
+        };
+        Ok(State::from_parts(None, None, adapter, device, queue, config, surface_caps).with_settings(state_config.settings))
+    }
+
+    fn create_instance(backends: wgpu::Backends) -> wgpu::Instance {
+        // The instance is a handle to our GPU
+        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            flags: Default::default(),
+            dx12_shader_compiler: Default::default(),
+            gles_minor_version: Default::default(),
+        })
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), StateInitError> {
+        // Optional features, only requested when the adapter has them.
+        // TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES is needed for sample counts other than 1 and 4.
+        // Indirect drawing isn't a feature but a downlevel flag, WebGL lacks it.
+        let optional_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | wgpu::Features::TIMESTAMP_QUERY
+            | wgpu::Features::POLYGON_MODE_LINE
+            | wgpu::Features::DEPTH32FLOAT_STENCIL8;
+        let required_features = adapter.features() & optional_features;
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    // WebGL doesn't support all of wgpu's features, so if
+                    // we're building for the web we'll have to disable some.
+                    required_limits: if cfg!(target_arch = "wasm32") {
+                        wgpu::Limits::downlevel_webgl2_defaults()
+                    } else {
+                        // The scene pipeline needs more than the default 4 bind groups
+                        wgpu::Limits {
+                            max_bind_groups: adapter.limits().max_bind_groups,
+                            // Allows windows as large as the adapter can render
+                            max_texture_dimension_2d: adapter.limits().max_texture_dimension_2d,
+                            ..wgpu::Limits::default()
+                        }
+                    },
+                    label: None,
+                    required_features,
+                    memory_hints: Default::default(),
+                },
+                None, // Trace path
+            )
+            .await
+            .map_err(StateInitError::RequestDevice)
+    }
+
+    fn from_parts(
+        surface: Option<wgpu::Surface<'a>>,
+        window: Option<&'a Window>,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        surface_caps: wgpu::SurfaceCapabilities,
+    ) -> Self {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
+        let features = device.features();
+        let limits = device.limits();
+        let max_texture_dimension = limits.max_texture_dimension_2d;
+
+        let depth_format = Texture::DEPTH_FORMAT;
+        let msaa_flags = Self::format_features(&adapter, features, ToneMapper::HDR_FORMAT).flags
+            & Self::format_features(&adapter, features, depth_format).flags;
+        let indirect_draw_supported = adapter.get_downlevel_capabilities().flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+        let vertex_storage_supported = limits.max_storage_buffers_per_shader_stage > 0
+            && adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE);
+        let instance_vertex_buffer = !vertex_storage_supported;
+
+        let anisotropy = if adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING) {
+            DEFAULT_ANISOTROPY
+        } else {
+            1
+        };
         let tree_texture_bytes = include_bytes!("textures/happy-tree.png");
-        let tree_texture = texture::Texture::from_bytes(&device, &queue, tree_texture_bytes, "happy-tree.png").unwrap();
+        let mut tree_texture = texture::Texture::from_bytes(&device, &queue, tree_texture_bytes, "happy-tree.png").unwrap();
+        tree_texture.set_anisotropy(&device, anisotropy);
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -108,7 +692,8 @@ impl <'a> State<'a> {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            // Instances pick a layer, plain textures are a single one
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
                             sample_type: wgpu::TextureSampleType::Float { filterable: true },
                         },
                         count: None,
@@ -125,219 +710,2501 @@ impl <'a> State<'a> {
                 label: Some("texture_bind_group_layout"),
             });
 
-        let texture_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&tree_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&tree_texture.sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
+        let backend = adapter.get_info().backend;
+        let texture_view = Self::scene_texture_view(&device, &queue, backend, &tree_texture.texture);
+        let texture_bind_group = Self::create_texture_bind_group(&device, &texture_bind_group_layout, &texture_view, &tree_texture.sampler);
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+        let sample_count = 1;
+        let depth_texture = Texture::create_depth_texture(&device, &config, sample_count, depth_format, "depth_texture");
 
-        let mesh = Mesh::new(&device);
+        let meshes = vec![Mesh::cube(&device)];
 
         let camera_bind_group_layout = CameraState::layout(&device);
         let camera_state = CameraState::new(&device, config.width, config.height, &camera_bind_group_layout);
 
-        let rotator_bind_group_layout = Rotation::layout(&device);
-        let rotator = Rotation::new(&device, &rotator_bind_group_layout);
-        let instances = Instances::new(&device);
+        let rotation_bind_group_layout = Rotation::layout(&device);
+        let rotation = Rotation::new(&device, &rotation_bind_group_layout);
+        let mut instances = Instances::new(&device, vertex_storage_supported);
+        instances.set_mesh(&queue, &meshes[0]);
+
+        let light_bind_group_layout = LightState::layout(&device);
+        let max_shadow_map_size = limits.max_texture_dimension_2d;
+        let shadow_map = ShadowMap::new(&device,
+                                        shadow_map::DEFAULT_SIZE.min(max_shadow_map_size),
+                                        &rotation_bind_group_layout,
+                                        &instances.layout,
+                                        instance_vertex_buffer);
+        let ground_plane = GroundPlane::new(&device, &queue, backend, &texture_bind_group_layout, &rotation_bind_group_layout, &instances);
 
         let bind_group_layouts = [
             &texture_bind_group_layout,
             &camera_bind_group_layout,
-            &rotator_bind_group_layout,
-            &instances.layout
+            &rotation_bind_group_layout,
+            &instances.layout,
+            &light_bind_group_layout,
         ];
-        let render_pipeline = Self::create_render_scene_pipeline(&device, &config, &bind_group_layouts);
-        let depth_view = DepthView::new(&device, config.format, &depth_texture);
+        let scene_shader_source = include_str!("shaders/shaders.wgsl").to_string();
+        let scene_shader = Self::create_scene_shader(&device, &scene_shader_source);
+        let depth_settings = DepthSettings::default();
+        let cull_mode = Some(wgpu::Face::Back);
+        let ssao = Ssao::new(&device,
+                             PrepassConfig { sample_count, depth_format, depth_compare: depth_settings.compare, cull_mode, instance_vertex_buffer },
+                             [&camera_bind_group_layout, &rotation_bind_group_layout, &instances.layout],
+                             &depth_texture,
+                             config.width,
+                             config.height);
+        let light = LightState::new(&device, &queue, &light_bind_group_layout, &shadow_map, ssao.view());
+        let shading = ShadingMode::default();
+        let scene_pipeline_config = ScenePipelineConfig { sample_count, depth_format, depth_settings, cull_mode, shading, instance_vertex_buffer };
+        let shader_constants = HashMap::new();
+        // The ground plane is drawn with the triangle pipelines, the others are created when a mesh needs them
+        let scene_pipelines = Self::create_scene_pipeline_cache(&device,
+                                                                scene_pipeline_config,
+                                                                [wgpu::PrimitiveTopology::TriangleList],
+                                                                &shader_constants,
+                                                                &scene_shader,
+                                                                &bind_group_layouts);
+        let mut depth_view = DepthView::new(&device, config.format, &depth_texture, sample_count);
+        depth_view.set_clip_planes(camera_state.model.znear, camera_state.model.zfar);
+        let depth_readback = DepthReadback::new(&device, sample_count);
+        let ground_grid = GroundGrid::new(&device, ToneMapper::HDR_FORMAT, sample_count, depth_format, depth_settings.compare, &camera_bind_group_layout);
+        let bounds_box = BoundsBox::new(&device, ToneMapper::HDR_FORMAT, sample_count, depth_format, depth_settings.compare, &camera_bind_group_layout, instances.bounds());
+        let axis_gizmo = AxisGizmo::new(&device, config.format);
+        let outline = Outline::new(&device,
+                                   ToneMapper::HDR_FORMAT,
+                                   sample_count,
+                                   depth_format,
+                                   instance_vertex_buffer,
+                                   [&camera_bind_group_layout, &rotation_bind_group_layout, &instances.layout],
+                                   wgpu::Color { r: 1.0, g: 0.6, b: 0.0, a: 1.0 });
+        let tone_mapper = ToneMapper::new(&device, config.format, config.width, config.height);
+        let bloom = Bloom::new(&device, tone_mapper.hdr_view(), config.width, config.height);
+        let skybox = Skybox::new(&device, ToneMapper::HDR_FORMAT, sample_count, depth_format, &camera_bind_group_layout);
+        let profiler = GpuProfiler::new(&device, &queue);
+        let instance_animation = InstanceAnimation::new(&device, &adapter, &instances.animation_layout);
+        let particle_renderer = ParticleRenderer::new(&device, ToneMapper::HDR_FORMAT, sample_count, depth_format, depth_settings.compare, &camera_bind_group_layout);
+        #[cfg(feature = "egui")]
+        let egui_overlay = EguiOverlay::new(&device, config.format);
+        let offscreen_texture = match surface {
+            Some(_) => None,
+            None => Some(Self::create_offscreen_texture(&device, &config)),
+        };
 
         Self {
             surface,
             window,
+            offscreen_texture,
+            adapter,
+            features,
+            limits,
             device,
             queue,
             config,
+            surface_caps,
             size,
-            background_color: position_to_color(&PhysicalPosition { x: 0f64, y: 0f64 }),
-            render_pipeline,
-            mesh,
+            max_texture_dimension,
+            scale_factor: window.map_or(1.0, |window| window.scale_factor()),
+            background_color: position_to_color(&LogicalPosition { x: 0f64, y: 0f64 }),
+            background_mode: BackgroundMode::CursorTracking,
+            background_time: Duration::ZERO,
+            scene_shader,
+            scene_shader_source,
+            shader_constants,
+            scene_pipelines,
+            depth_format,
+            depth_settings,
+            cull_mode,
+            shading,
+            wireframe: false,
+            transparent: false,
+            show_backfaces: false,
+            indirect_draw_supported,
+            indirect_draw: indirect_draw_supported,
+            frustum_culling: false,
+            last_draw_count: 0,
+            vertex_storage_supported,
+            instance_vertex_buffer,
+            meshes,
+            selected_mesh: 0,
+            texture_bind_group_layout,
+            camera_bind_group_layout,
+            split_camera: None,
             camera_state,
-            rotator,
+            rotation_bind_group_layout,
+            rotation,
             instances,
+            instance_animation,
+            particles: None,
+            particle_renderer,
+            light_bind_group_layout,
+            light,
+            shadow_map,
+            ground_plane,
+            texture_sampler_config: tree_texture.sampler_config(),
+            texture_sampler: tree_texture.sampler,
+            texture_view,
             texture_bind_group,
+            anisotropy,
             depth_texture,
-            depth_view: Some(depth_view)
+            depth_view: Some(depth_view),
+            depth_readback,
+            ground_grid,
+            ground_grid_visible: false,
+            bounds_box,
+            bounds_visible: false,
+            axis_gizmo,
+            axis_gizmo_enabled: false,
+            axis_gizmo_corner: Corner::BottomRight,
+            overlay_renderer: None,
+            outline,
+            outline_enabled: false,
+            skybox,
+            tone_mapper,
+            bloom,
+            bloom_enabled: false,
+            fxaa: None,
+            ssao,
+            ssao_enabled: false,
+            recorder: None,
+            render_graph: Self::create_render_graph(),
+            msaa_flags,
+            sample_count,
+            msaa_view: None,
+            last_update: Instant::now(),
+            target_fps: None,
+            next_frame: Instant::now(),
+            paused: false,
+            deterministic_frame: None,
+            minimized: false,
+            occluded: false,
+            cursor_position: None,
+            last_click: None,
+            frame_timer: FrameTimer::new(),
+            profiler,
+            #[cfg(feature = "egui")]
+            egui_overlay,
         }
     }
 
-    pub fn create_render_scene_pipeline(
-        device: &Device,
-        config: &SurfaceConfiguration,
-        bind_group_layouts: &[&BindGroupLayout]
-    ) -> wgpu::RenderPipeline {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Just some shaders"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shaders.wgsl").into()),
-        });
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts,
-                push_constant_ranges: &[],
-            });
-
-        return device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                compilation_options: Default::default(),
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        });
+    fn with_settings(mut self, settings: Settings) -> Self {
+        self.set_background_mode(settings.background);
+        self.rotation.set_speed(settings.rotation_speed.0, settings.rotation_speed.1);
+        self.instances.set_grid(&self.device, settings.grid_rows, settings.grid_cols, settings.grid_spacing);
+        self.camera_state.set_viewpoint(settings.camera);
+        self
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    // `view` comes from `scene_texture_view`
+    fn create_texture_bind_group(device: &Device, layout: &BindGroupLayout, view: &TextureView, sampler: &wgpu::Sampler) -> wgpu::BindGroup {
+        device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    }
+                ],
+                label: Some("diffuse_bind_group"),
+            }
+        )
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 && new_size.width <= 8192 && new_size.height <= 8192 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
-            match &mut self.depth_view {
-                Some(depth_view) => {
-                    depth_view.set_depth_texture(&self.device, &self.depth_texture);
-                }
-                _ => {}
-            }
+    // The scene binds its texture as an array. GL picks a texture's target from its layer
+    // count when it's created: single layer textures can't be viewed as arrays there and
+    // square ones with a multiple of 6 layers are cube maps. Those are copied into a texture
+    // with the last layer repeated once more, which the shader never samples past.
+    fn scene_texture_view(device: &Device, queue: &wgpu::Queue, backend: wgpu::Backend, texture: &wgpu::Texture) -> TextureView {
+        let layers = texture.depth_or_array_layers();
+        let cube_compatible = texture.width() == texture.height() && layers.is_multiple_of(6);
+        let array_view = |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            array_layer_count: Some(layers),
+            ..Default::default()
+        });
+        if backend != wgpu::Backend::Gl || (layers > 1 && !cube_compatible) {
+            return array_view(texture);
+        }
+        let copy = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("scene_texture_copy"),
+            size: wgpu::Extent3d { depth_or_array_layers: layers + 1, ..texture.size() },
+            mip_level_count: texture.mip_level_count(),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture.format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Scene Texture Copy Encoder"),
+        });
+        for mip_level in 0..texture.mip_level_count() {
+            let size = texture.size().mip_level_size(mip_level, wgpu::TextureDimension::D2);
+            let layer = |texture, z| wgpu::ImageCopyTexture {
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d { x: 0, y: 0, z },
+                aspect: wgpu::TextureAspect::All,
+            };
+            encoder.copy_texture_to_texture(layer(texture, 0), layer(&copy, 0), size);
+            let last = wgpu::Extent3d { depth_or_array_layers: 1, ..size };
+            encoder.copy_texture_to_texture(layer(texture, layers - 1), layer(&copy, layers), last);
         }
+        queue.submit(Some(encoder.finish()));
+        array_view(&copy)
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::CursorMoved { position, .. } => {
-                self.background_color = position_to_color(position);
-                true
+    // Replaces the cube texture with a PNG or JPEG image, the pipeline is kept
+    // Images that fail to decode are replaced by Texture::missing, other errors are returned
+    pub fn load_texture(&mut self, bytes: &[u8], label: &str) -> Result<(), TextureError> {
+        let mut texture = match image::load_from_memory(bytes) {
+            Ok(img) => Texture::from_image(&self.device, &self.queue, &img, Some(label))?,
+            Err(e) => {
+                log::error!("Failed to decode {}, using the missing texture: {}", label, e);
+                Texture::missing(&self.device, &self.queue)
             }
-            _ => {
-                self.camera_state.controller.process_events(event)
-            },
-        }
+        };
+        texture.set_anisotropy(&self.device, self.anisotropy);
+        self.set_texture(&texture);
+        Ok(())
     }
 
-    pub fn update(&mut self) {
-        self.camera_state.update(&self.queue);
-        self.rotator.update(&self.queue);
+    // Returns the index to select the mesh with
+    pub fn add_mesh(&mut self, mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
     }
 
-    fn run_cubes_pipeline(&self, view: &TextureView, encoder: &mut CommandEncoder) {
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+    pub fn mesh_count(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn selected_mesh(&self) -> usize {
+        self.selected_mesh
+    }
+
+    // Fails and keeps the selection for indices not returned by `add_mesh`
+    pub fn select_mesh(&mut self, index: usize) -> Result<(), MeshIndexError> {
+        if index >= self.meshes.len() {
+            return Err(MeshIndexError { index, count: self.meshes.len() });
+        }
+        self.selected_mesh = index;
+        self.instances.set_mesh(&self.queue, &self.meshes[index]);
+        let topology = self.meshes[index].topology();
+        if !self.scene_pipelines.contains_key(&topology) {
+            let pipelines = Self::create_scene_pipelines(&self.device,
+                                                         self.scene_pipeline_config(),
+                                                         topology,
+                                                         &self.shader_constants,
+                                                         &self.scene_shader,
+                                                         &self.scene_bind_group_layouts());
+            self.scene_pipelines.insert(topology, pipelines);
+        }
+        Ok(())
+    }
+
+    // Selects the next mesh, wrapping around to the first one
+    pub fn cycle_mesh(&mut self) {
+        // The cube is always there, the index is in range
+        self.select_mesh((self.selected_mesh + 1) % self.meshes.len()).unwrap();
+    }
+
+    // e.g. Texture::uv_debug to check the tex_coords of a mesh. Samples it with the sampler
+    // config of the texture.
+    pub fn set_texture(&mut self, texture: &Texture) {
+        self.texture_view = Self::scene_texture_view(&self.device, &self.queue, self.adapter.get_info().backend, &texture.texture);
+        self.set_texture_sampler(texture.sampler_config());
+    }
+
+    fn set_texture_sampler(&mut self, config: SamplerConfig) {
+        self.texture_sampler_config = config;
+        self.texture_sampler = config.create_sampler(&self.device);
+        self.texture_bind_group = Self::create_texture_bind_group(&self.device, &self.texture_bind_group_layout, &self.texture_view, &self.texture_sampler);
+    }
+
+    // Replaces the cube texture with an array of PNG or JPEG images of the same size and
+    // color type, each instance samples the layer of Instances::set_layer
+    pub fn load_texture_array(&mut self, layers: &[&[u8]]) -> Result<(), TextureError> {
+        let mut images = layers.iter()
+            .map(|bytes| image::load_from_memory(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        // GL would create a cube map, which can't be mipmapped or bound as an array, see
+        // `scene_texture_view`. Instances past the last layer sample it anyway.
+        let cube_compatible = images.first().is_some_and(|img| img.width() == img.height()) && images.len().is_multiple_of(6);
+        if cube_compatible && self.adapter.get_info().backend == wgpu::Backend::Gl {
+            images.push(images[images.len() - 1].clone());
+        }
+        let mut texture = Texture::array_from_images(&self.device, &self.queue, &images, Some("texture_array"))?;
+        texture.set_anisotropy(&self.device, self.anisotropy);
+        self.set_texture(&texture);
+        Ok(())
+    }
+
+    pub fn is_anisotropy_supported(&self) -> bool {
+        self.adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING)
+    }
+
+    pub fn anisotropy(&self) -> u16 {
+        self.anisotropy
+    }
+
+    // Anisotropic filtering with up to `anisotropy` samples for the current texture and the
+    // ones loaded later, clamped to 1..=16 where 1 turns it off. No-op where it isn't supported.
+    pub fn set_anisotropy(&mut self, anisotropy: u16) {
+        if anisotropy > 1 && !self.is_anisotropy_supported() {
+            log::warn!("Anisotropic filtering is not supported by the adapter");
+            return;
+        }
+        self.anisotropy = anisotropy.clamp(1, 16);
+        self.set_texture_sampler(SamplerConfig { anisotropy: self.anisotropy, ..self.texture_sampler_config });
+    }
+
+    pub fn sampler_config(&self) -> SamplerConfig {
+        self.texture_sampler_config
+    }
+
+    // Changes how the current texture is sampled without reloading it, e.g. AddressMode::Repeat
+    // with a uv_scale shader constant above 1 to tile it. Its anisotropy is the one of set_anisotropy.
+    pub fn set_sampler_config(&mut self, config: SamplerConfig) {
+        self.set_texture_sampler(SamplerConfig { anisotropy: self.anisotropy, ..config });
+    }
+
+    // Draws the six images in the order +X, -X, +Y, -Y, +Z, -Z behind the scene
+    pub fn load_skybox(&mut self, faces: [&[u8]; 6]) -> Result<(), TextureError> {
+        let cubemap = Cubemap::from_bytes(&self.device, &self.queue, faces, "skybox")?;
+        self.set_skybox(&cubemap);
+        Ok(())
+    }
+
+    pub fn set_skybox(&mut self, cubemap: &Cubemap) {
+        self.skybox.set_cubemap(&self.device, cubemap);
+    }
+
+    // Shows the background of the BackgroundMode again
+    pub fn clear_skybox(&mut self) {
+        self.skybox.clear_cubemap();
+    }
+
+    fn create_scene_shader(device: &Device, source: &str) -> wgpu::ShaderModule {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Just some shaders"),
+            source: wgpu::ShaderSource::Wgsl(light::scene_shader_source(device, source)),
+        })
+    }
+
+    fn create_render_scene_pipeline(
+        device: &Device,
+        config: ScenePipelineConfig,
+        kind: ScenePipelineKind,
+        topology: wgpu::PrimitiveTopology,
+        constants: &HashMap<String, f64>,
+        shader: &wgpu::ShaderModule,
+        bind_group_layouts: &[&BindGroupLayout]
+    ) -> wgpu::RenderPipeline {
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+        let (vertex_entry_point, vertex_buffers) = Instances::vertex_input(config.instance_vertex_buffer);
+        let triangles = topology == wgpu::PrimitiveTopology::TriangleList;
+        let backfaces = kind == ScenePipelineKind::Backfaces;
+        let fragment_entry_point = if backfaces {
+            "fs_backface"
+        } else if triangles {
+            config.shading.fragment_entry_point()
+        } else {
+            // Lines and points are always drawn in their vertex colors
+            "fs_unlit"
+        };
+        // Passes where the fill pipeline already drew a back face with culling off
+        let depth_compare = match config.depth_settings.compare {
+            wgpu::CompareFunction::Less if backfaces => wgpu::CompareFunction::LessEqual,
+            wgpu::CompareFunction::Greater if backfaces => wgpu::CompareFunction::GreaterEqual,
+            compare => compare,
+        };
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: vertex_entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants,
+                    ..Default::default()
+                },
+                buffers: &vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: fragment_entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants,
+                    ..Default::default()
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ToneMapper::HDR_FORMAT,
+                    blend: Some(if kind == ScenePipelineKind::Transparent {
+                        wgpu::BlendState::ALPHA_BLENDING
+                    } else {
+                        wgpu::BlendState::REPLACE
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: if backfaces {
+                    Some(wgpu::Face::Front)
+                } else if triangles {
+                    config.cull_mode
+                } else {
+                    None
+                },
+                // Line requires Features::POLYGON_MODE_LINE
+                polygon_mode: if kind == ScenePipelineKind::Wireframe {
+                    wgpu::PolygonMode::Line
+                } else {
+                    wgpu::PolygonMode::Fill
+                },
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: config.depth_format,
+                // Transparent instances must not hide the ones drawn after them
+                depth_write_enabled: config.depth_settings.write_enabled && kind != ScenePipelineKind::Transparent,
+                depth_compare,
+                stencil: outline::scene_stencil_state(config.depth_format),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: config.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_scene_pipelines(
+        device: &Device,
+        config: ScenePipelineConfig,
+        topology: wgpu::PrimitiveTopology,
+        constants: &HashMap<String, f64>,
+        shader: &wgpu::ShaderModule,
+        bind_group_layouts: &[&BindGroupLayout]
+    ) -> ScenePipelines {
+        let create = |kind| Self::create_render_scene_pipeline(device, config, kind, topology, constants, shader, bind_group_layouts);
+        ScenePipelines {
+            fill: create(ScenePipelineKind::Fill),
+            // Lines and points look the same in wireframe
+            wireframe: (device.features().contains(wgpu::Features::POLYGON_MODE_LINE)
+                && topology == wgpu::PrimitiveTopology::TriangleList)
+                .then(|| create(ScenePipelineKind::Wireframe)),
+            transparent: create(ScenePipelineKind::Transparent),
+            backfaces: (topology == wgpu::PrimitiveTopology::TriangleList).then(|| create(ScenePipelineKind::Backfaces)),
+        }
+    }
+
+    // Pipelines for each topology in `topologies`
+    fn create_scene_pipeline_cache(
+        device: &Device,
+        config: ScenePipelineConfig,
+        topologies: impl IntoIterator<Item = wgpu::PrimitiveTopology>,
+        constants: &HashMap<String, f64>,
+        shader: &wgpu::ShaderModule,
+        bind_group_layouts: &[&BindGroupLayout]
+    ) -> HashMap<wgpu::PrimitiveTopology, ScenePipelines> {
+        topologies.into_iter()
+            .map(|topology| (topology, Self::create_scene_pipelines(device, config, topology, constants, shader, bind_group_layouts)))
+            .collect()
+    }
+
+    fn create_msaa_view(device: &Device, config: &SurfaceConfiguration, sample_count: u32) -> Option<TextureView> {
+        if sample_count == 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("msaa_texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: ToneMapper::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
+    fn scene_bind_group_layouts(&self) -> [&BindGroupLayout; 5] {
+        [
+            &self.texture_bind_group_layout,
+            &self.camera_bind_group_layout,
+            &self.rotation_bind_group_layout,
+            &self.instances.layout,
+            &self.light_bind_group_layout,
+        ]
+    }
+
+    fn scene_pipeline_config(&self) -> ScenePipelineConfig {
+        ScenePipelineConfig {
+            sample_count: self.sample_count,
+            depth_format: self.depth_format,
+            depth_settings: self.depth_settings,
+            cull_mode: self.cull_mode,
+            shading: self.shading,
+            instance_vertex_buffer: self.instance_vertex_buffer,
+        }
+    }
+
+    fn rebuild_render_pipeline(&mut self) {
+        let topologies: Vec<_> = self.scene_pipelines.keys().copied().collect();
+        self.scene_pipelines = Self::create_scene_pipeline_cache(&self.device,
+                                                                 self.scene_pipeline_config(),
+                                                                 topologies,
+                                                                 &self.shader_constants,
+                                                                 &self.scene_shader,
+                                                                 &self.scene_bind_group_layouts());
+        // The prepass has to produce the same depth as the scene pass
+        let prepass_config = PrepassConfig {
+            sample_count: self.sample_count,
+            depth_format: self.depth_format,
+            depth_compare: self.depth_settings.compare,
+            cull_mode: self.cull_mode,
+            instance_vertex_buffer: self.instance_vertex_buffer,
+        };
+        let layouts = [&self.camera_bind_group_layout, &self.rotation_bind_group_layout, &self.instances.layout];
+        self.ssao.rebuild_pipelines(&self.device, prepass_config, layouts);
+    }
+
+    pub fn depth_settings(&self) -> DepthSettings {
+        self.depth_settings
+    }
+
+    pub fn set_depth_settings(&mut self, settings: DepthSettings) {
+        if settings == self.depth_settings {
+            return;
+        }
+        self.depth_settings = settings;
+        self.rebuild_render_pipeline();
+        self.rebuild_overlay_pipelines();
+    }
+
+    // The pipelines drawn into the scene pass besides the scene pipelines
+    fn rebuild_overlay_pipelines(&mut self) {
+        let (format, count, depth_format) = (ToneMapper::HDR_FORMAT, self.sample_count, self.depth_format);
+        let compare = self.depth_settings.compare;
+        self.ground_grid.rebuild_pipeline(&self.device, format, count, depth_format, compare, &self.camera_bind_group_layout);
+        self.bounds_box.rebuild_pipeline(&self.device, format, count, depth_format, compare, &self.camera_bind_group_layout);
+        self.skybox.rebuild_pipeline(&self.device, format, count, depth_format, &self.camera_bind_group_layout);
+        let outline_layouts = [&self.camera_bind_group_layout, &self.rotation_bind_group_layout, &self.instances.layout];
+        self.outline.rebuild_pipeline(&self.device, format, count, depth_format, self.instance_vertex_buffer, outline_layouts);
+        self.particle_renderer.rebuild_pipeline(&self.device, format, count, depth_format, compare, &self.camera_bind_group_layout);
+    }
+
+    pub fn depth_format(&self) -> wgpu::TextureFormat {
+        self.depth_format
+    }
+
+    // E.g. Depth24PlusStencil8 for stencil effects, Texture::DEPTH_FORMAT by default. Falls back to a
+    // lower sample count when the current one isn't supported with the new format.
+    pub fn set_depth_format(&mut self, format: wgpu::TextureFormat) -> Result<(), DepthFormatError> {
+        if format == self.depth_format {
+            return Ok(());
+        }
+        let format_features = Self::format_features(&self.adapter, self.features, format);
+        let required_usages = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        if !format.has_depth_aspect()
+            || !self.features.contains(format.required_features())
+            || !format_features.allowed_usages.contains(required_usages) {
+            return Err(DepthFormatError { format });
+        }
+        if self.outline_enabled && !format.has_stencil_aspect() {
+            log::warn!("{:?} has no stencil, turning the outline off", format);
+            self.outline_enabled = false;
+        }
+        self.depth_format = format;
+        self.msaa_flags = Self::format_features(&self.adapter, self.features, ToneMapper::HDR_FORMAT).flags
+            & format_features.flags;
+        if !self.msaa_flags.sample_count_supported(self.sample_count) {
+            // Rebuilds everything for the new sample count
+            self.set_sample_count(self.sample_count);
+            return Ok(());
+        }
+        self.rebuild_render_pipeline();
+        self.rebuild_overlay_pipelines();
+        self.recreate_render_targets();
+        Ok(())
+    }
+
+    fn format_features(adapter: &wgpu::Adapter, features: wgpu::Features, format: wgpu::TextureFormat) -> wgpu::TextureFormatFeatures {
+        if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(features)
+        }
+    }
+
+    pub fn cull_mode(&self) -> Option<wgpu::Face> {
+        self.cull_mode
+    }
+
+    // None is useful for meshes with inconsistent winding or to look inside them
+    pub fn set_cull_mode(&mut self, mode: Option<wgpu::Face>) {
+        if mode == self.cull_mode {
+            return;
+        }
+        self.cull_mode = mode;
+        self.rebuild_render_pipeline();
+    }
+
+    pub fn shader_constant(&self, name: &str) -> Option<f64> {
+        self.shader_constants.get(name).copied()
+    }
+
+    // Sets an override constant of shaders.wgsl, see SCENE_SHADER_CONSTANTS, and rebuilds the scene pipelines
+    pub fn set_shader_constant(&mut self, name: &str, value: f64) -> Result<(), ShaderConstantError> {
+        if !SCENE_SHADER_CONSTANTS.contains(&name) {
+            return Err(ShaderConstantError { name: name.to_string() });
+        }
+        if self.shader_constants.insert(name.to_string(), value) != Some(value) {
+            // The GL backend caches programs per shader module regardless of the
+            // constants, only a new module gets them applied
+            self.scene_shader = Self::create_scene_shader(&self.device, &self.scene_shader_source);
+            self.rebuild_render_pipeline();
+        }
+        Ok(())
+    }
+
+    pub fn shading(&self) -> ShadingMode {
+        self.shading
+    }
+
+    pub fn set_shading(&mut self, mode: ShadingMode) {
+        if mode == self.shading {
+            return;
+        }
+        self.shading = mode;
+        self.rebuild_render_pipeline();
+    }
+
+    pub fn is_reverse_z(&self) -> bool {
+        self.camera_state.model.reverse_z
+    }
+
+    // Stores depth as 1.0 at the near plane and 0.0 at the far one, which spreads float precision
+    // more evenly over the distance. Flips the depth compare function to match.
+    pub fn set_reverse_z(&mut self, on: bool) {
+        if on == self.camera_state.model.reverse_z {
+            return;
+        }
+        self.camera_state.model.reverse_z = on;
+        if let Some(camera) = &mut self.split_camera {
+            camera.model.reverse_z = on;
+        }
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.set_reverse_z(on);
+        }
+        let compare = match self.depth_settings.compare {
+            wgpu::CompareFunction::Less => wgpu::CompareFunction::Greater,
+            wgpu::CompareFunction::LessEqual => wgpu::CompareFunction::GreaterEqual,
+            wgpu::CompareFunction::Greater => wgpu::CompareFunction::Less,
+            wgpu::CompareFunction::GreaterEqual => wgpu::CompareFunction::LessEqual,
+            other => other,
+        };
+        self.set_depth_settings(DepthSettings { compare, ..self.depth_settings });
+    }
+
+    pub fn is_grid_visible(&self) -> bool {
+        self.ground_grid_visible
+    }
+
+    // Lines on the XZ plane through the origin
+    pub fn set_grid_visible(&mut self, on: bool) {
+        self.ground_grid_visible = on;
+    }
+
+    pub fn is_bounds_visible(&self) -> bool {
+        self.bounds_visible
+    }
+
+    // Wireframe box around all instances, follows changes of the instance grid
+    pub fn set_bounds_visible(&mut self, on: bool) {
+        self.bounds_visible = on;
+        if on {
+            self.bounds_box.set_bounds(&self.queue, self.instances.bounds());
+        }
+    }
+
+    pub fn is_axis_gizmo_enabled(&self) -> bool {
+        self.axis_gizmo_enabled
+    }
+
+    // Small X (red), Y (green) and Z (blue) axes in a corner of the window, turned with the camera
+    pub fn set_axis_gizmo(&mut self, on: bool) {
+        self.axis_gizmo_enabled = on;
+    }
+
+    pub fn axis_gizmo_corner(&self) -> Corner {
+        self.axis_gizmo_corner
+    }
+
+    pub fn set_axis_gizmo_corner(&mut self, corner: Corner) {
+        self.axis_gizmo_corner = corner;
+    }
+
+    // Called every frame with the frame's encoder and output view once the scene is tone
+    // mapped, the debug overlays (depth view, axis gizmo, egui) are drawn on top.
+    // Passes added have to load the view's contents, the view is in the surface format.
+    pub fn set_overlay_renderer(&mut self, f: OverlayRenderer) {
+        self.overlay_renderer = Some(f);
+    }
+
+    pub fn clear_overlay_renderer(&mut self) {
+        self.overlay_renderer = None;
+    }
+
+    // Shows the depth buffer in the rectangle from (x, y) in pixels from the top left
+    // instead of over the scene
+    pub fn set_depth_view_viewport(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.set_viewport(x, y, width, height);
+        }
+    }
+
+    pub fn clear_depth_view_viewport(&mut self) {
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.clear_viewport();
+        }
+    }
+
+    pub fn depth_view_colormap(&self) -> Colormap {
+        self.depth_view.as_ref().map_or_else(Colormap::default, |depth_view| depth_view.colormap())
+    }
+
+    // Turbo makes small depth differences easier to tell apart than grayscale
+    pub fn set_depth_view_colormap(&mut self, map: Colormap) {
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.set_colormap(map);
+        }
+    }
+
+    // Threshold and intensity when the bloom is on
+    pub fn bloom(&self) -> Option<(f32, f32)> {
+        self.bloom_enabled.then(|| (self.bloom.threshold(), self.bloom.intensity()))
+    }
+
+    // Glow around the parts of the scene brighter than `threshold` (luminance before tone
+    // mapping), `intensity` scales the glow added back
+    pub fn set_bloom(&mut self, on: bool, threshold: f32, intensity: f32) {
+        self.bloom_enabled = on;
+        self.bloom.set_params(&self.queue, threshold.max(0.0), intensity.max(0.0));
+    }
+
+    pub fn is_fxaa_enabled(&self) -> bool {
+        self.fxaa.is_some()
+    }
+
+    // Post-process anti-aliasing of the tone mapped frame, works where MSAA isn't available,
+    // e.g. on WebGL. It costs one fullscreen pass at any scene complexity, while MSAA's cost
+    // grows with the sample count, but it only sees the final pixels: it softens textures and
+    // text along with the edges and can't recover sub-pixel detail. Both can be on at once.
+    pub fn set_fxaa(&mut self, on: bool) {
+        if on == self.fxaa.is_some() {
+            return;
+        }
+        self.fxaa = on.then(|| Fxaa::new(&self.device, self.config.format, self.config.width, self.config.height));
+    }
+
+    // Radius and strength when SSAO is on, also while the split view suspends it
+    pub fn ssao(&self) -> Option<(f32, f32)> {
+        self.ssao_enabled.then(|| (self.ssao.radius(), self.ssao.strength()))
+    }
+
+    // SSAO is on and not suspended by the split view
+    fn is_ssao_active(&self) -> bool {
+        self.ssao_enabled && self.split_camera.is_none()
+    }
+
+    // Screen-space ambient occlusion darkening the ambient light where geometry is close
+    // together. `radius` is the view space distance searched for occluders and `strength`
+    // how much a fully occluded point loses, 1 removes its ambient light. Costs a depth
+    // prepass of the scene and two fullscreen passes.
+    pub fn set_ssao(&mut self, on: bool, radius: f32, strength: f32) {
+        self.ssao_enabled = on;
+        self.ssao.set_params(radius.max(0.01), strength.max(0.0));
+        self.light.set_ambient_occlusion(&self.queue, self.is_ssao_active());
+    }
+
+    // The color when the outline is on
+    pub fn outline(&self) -> Option<wgpu::Color> {
+        self.outline_enabled.then(|| self.outline.color())
+    }
+
+    // Outlines the silhouette of the instances. Switches the depth buffer to Depth24PlusStencil8
+    // unless its format already has stencil.
+    pub fn set_outline(&mut self, on: bool, color: wgpu::Color) {
+        self.outline.set_color(&self.queue, color);
+        if on && !self.depth_format.has_stencil_aspect() {
+            if let Err(e) = self.set_depth_format(wgpu::TextureFormat::Depth24PlusStencil8) {
+                log::warn!("Can't draw the outline: {}", e);
+                return;
+            }
+        }
+        self.outline_enabled = on;
+    }
+
+    pub fn is_wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    // No-op when the device doesn't support line polygon mode
+    pub fn set_wireframe(&mut self, on: bool) {
+        if on && self.scene_pipelines[&wgpu::PrimitiveTopology::TriangleList].wireframe.is_none() {
+            log::warn!("Wireframe rendering is not supported by the device");
+            return;
+        }
+        self.wireframe = on;
+    }
+
+    pub fn is_showing_backfaces(&self) -> bool {
+        self.show_backfaces
+    }
+
+    // Draws the visible back faces of the instances in red, e.g. to find the triangles
+    // of a loaded mesh that are wound the wrong way. Only for triangle meshes.
+    pub fn set_show_backfaces(&mut self, on: bool) {
+        self.show_backfaces = on;
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.transparent
+    }
+
+    // Blends the instances using the alpha of their colors. Instances are
+    // sorted back to front on the CPU every update while this is on.
+    pub fn set_transparent(&mut self, on: bool) {
+        self.transparent = on;
+        if !on {
+            self.instances.reset_order();
+        }
+    }
+
+    pub fn is_frustum_culling(&self) -> bool {
+        self.frustum_culling
+    }
+
+    // Leaves the instances out of view of the cameras out of the draws, tested on the CPU
+    // every update against their bounding spheres. Off by default, compare fps and
+    // `last_draw_count` with it on and off to see whether it pays off.
+    pub fn set_frustum_culling(&mut self, on: bool) {
+        self.frustum_culling = on;
+        if !on {
+            self.instances.uncull(&self.queue);
+        }
+    }
+
+    // Instances drawn by the scene pass of the last frame, counted once per view in the
+    // split view. 0 when the scene pass is disabled.
+    pub fn last_draw_count(&self) -> u32 {
+        self.last_draw_count
+    }
+
+    pub fn is_gpu_animation(&self) -> bool {
+        self.instances.is_gpu_animated()
+    }
+
+    // Advances the instance spins in a compute pass instead of on the CPU,
+    // no-op on devices without compute shaders, e.g. WebGL
+    pub fn set_gpu_animation(&mut self, on: bool) {
+        if on && self.instance_animation.is_none() {
+            log::warn!("GPU animation is not supported by the device");
+            return;
+        }
+        self.instances.set_gpu_animated(&self.device, on);
+    }
+
+    pub fn is_particles_enabled(&self) -> bool {
+        self.particles.is_some()
+    }
+
+    // Fountain of DEFAULT_PARTICLE_COUNT particles animated in a compute pass,
+    // no-op on devices without compute shaders, e.g. WebGL
+    pub fn set_particles_enabled(&mut self, on: bool) {
+        if on == self.particles.is_some() {
+            return;
+        }
+        if on {
+            self.set_particle_count(DEFAULT_PARTICLE_COUNT);
+        } else {
+            self.particles = None;
+        }
+    }
+
+    // 0 while the particles are off
+    pub fn particle_count(&self) -> u32 {
+        self.particles.as_ref().map_or(0, |particles| particles.count())
+    }
+
+    // Restarts the particles with `count` of them, turns them on. At least 1 since an
+    // empty storage buffer can't be bound.
+    pub fn set_particle_count(&mut self, count: u32) {
+        if !self.adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            log::warn!("Particles are not supported by the device");
+            return;
+        }
+        self.particles = Some(Particles::new(&self.device, count.max(1)));
+    }
+
+    pub fn is_indirect_draw(&self) -> bool {
+        self.indirect_draw
+    }
+
+    // Enabled by default when supported, no-op on devices without indirect execution, e.g. WebGL
+    pub fn set_indirect_draw(&mut self, on: bool) {
+        if on && !self.indirect_draw_supported {
+            log::warn!("Indirect drawing is not supported by the device");
+            return;
+        }
+        self.indirect_draw = on;
+    }
+
+    pub fn is_instance_vertex_buffer(&self) -> bool {
+        self.instance_vertex_buffer
+    }
+
+    // Reads the instances as vertex attributes instead of from a storage buffer. Always on
+    // when the vertex stage can't read storage buffers, e.g. with WebGL's limits.
+    pub fn set_instance_vertex_buffer(&mut self, on: bool) {
+        if !on && !self.vertex_storage_supported {
+            log::warn!("Storage buffers in the vertex stage are not supported by the device");
+            return;
+        }
+        if on == self.instance_vertex_buffer {
+            return;
+        }
+        self.instance_vertex_buffer = on;
+        self.rebuild_render_pipeline();
+        self.rebuild_overlay_pipelines();
+        self.shadow_map.rebuild_pipeline(&self.device, &self.rotation_bind_group_layout, &self.instances.layout, on);
+    }
+
+    // Rereads the shaders from the source tree and rebuilds the pipelines,
+    // keeps the previous pipeline if the new shader doesn't compile
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn reload_shaders(&mut self) {
+        let shaders_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shaders");
+        let read_shader = |name: &str| std::fs::read_to_string(shaders_dir.join(name))
+            .map_err(|e| log::error!("Failed to read {}: {}", name, e))
+            .ok();
+
+        if let Some(source) = read_shader("shaders.wgsl") {
+            let result = catch_validation_error(&self.device, || {
+                let shader = Self::create_scene_shader(&self.device, &source);
+                let pipelines = Self::create_scene_pipeline_cache(&self.device,
+                                                             self.scene_pipeline_config(),
+                                                             self.scene_pipelines.keys().copied(),
+                                                             &self.shader_constants,
+                                                             &shader,
+                                                             &self.scene_bind_group_layouts());
+                (shader, pipelines)
+            });
+            match result {
+                Ok((shader, pipelines)) => {
+                    self.scene_shader = shader;
+                    self.scene_shader_source = source;
+                    self.scene_pipelines = pipelines;
+                    log::info!("Reloaded shaders.wgsl");
+                }
+                Err(e) => log::error!("Failed to reload shaders.wgsl: {}", e),
+            }
+        }
+
+        if let Some(depth_view) = &mut self.depth_view {
+            if let Some(source) = read_shader("depth_render.wgsl") {
+                match catch_validation_error(&self.device, || depth_view.create_pipeline(&self.device, &source)) {
+                    Ok(pipeline) => {
+                        depth_view.set_pipeline(pipeline, source);
+                        log::info!("Reloaded depth_render.wgsl");
+                    }
+                    Err(e) => log::error!("Failed to reload depth_render.wgsl: {}", e),
+                }
+            }
+        }
+    }
+
+    // Recreates everything that depends on the surface size or the sample count
+    fn recreate_render_targets(&mut self) {
+        self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, self.sample_count, self.depth_format, "depth_texture");
+        self.msaa_view = Self::create_msaa_view(&self.device, &self.config, self.sample_count);
+        self.tone_mapper.resize(&self.device, self.config.width, self.config.height);
+        self.bloom.resize(&self.device, self.tone_mapper.hdr_view(), self.config.width, self.config.height);
+        self.ssao.resize(&self.device, &self.depth_texture, self.config.width, self.config.height);
+        self.light.recreate_bind_group(&self.device, &self.light_bind_group_layout, &self.shadow_map, self.ssao.view());
+        if let Some(fxaa) = &mut self.fxaa {
+            fxaa.resize(&self.device, self.config.width, self.config.height);
+        }
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.set_depth_texture(&self.device, &self.depth_texture);
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Supports 1, 4 and 8x MSAA, falls back to the highest supported count below the requested one
+    pub fn set_sample_count(&mut self, count: u32) {
+        let supported = [8, 4, 1].into_iter()
+            .filter(|c| *c <= count)
+            .find(|c| self.msaa_flags.sample_count_supported(*c))
+            .unwrap_or(1);
+        if supported != count {
+            log::warn!("{}x MSAA is not supported, using {}x instead", count, supported);
+        }
+        let count = supported;
+        if count == self.sample_count {
+            return;
+        }
+        self.sample_count = count;
+        self.rebuild_render_pipeline();
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.set_sample_count(&self.device, count);
+        }
+        self.depth_readback.set_sample_count(&self.device, count);
+        self.rebuild_overlay_pipelines();
+        self.recreate_render_targets();
+    }
+
+    fn create_offscreen_texture(device: &Device, config: &SurfaceConfiguration) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &[],
+        })
+    }
+
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    // Name, backend and type of the GPU in use
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    pub fn features(&self) -> wgpu::Features {
+        self.features
+    }
+
+    pub fn limits(&self) -> wgpu::Limits {
+        self.limits.clone()
+    }
+
+    // Whether the device has all of `features`
+    pub fn supports(&self, features: wgpu::Features) -> bool {
+        self.features.contains(features)
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        self.window
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        // Happens when the window is minimized, the surface keeps its last size
+        if new_size.width == 0 || new_size.height == 0 {
+            self.minimized = true;
+            return;
+        }
+        self.minimized = false;
+        let max = self.max_texture_dimension;
+        let clamped = winit::dpi::PhysicalSize::new(new_size.width.min(max), new_size.height.min(max));
+        if clamped != new_size {
+            log::warn!("{}x{} exceeds the device limit of {}, rendering at {}x{}",
+                       new_size.width, new_size.height, max, clamped.width, clamped.height);
+        }
+        self.size = clamped;
+        self.config.width = clamped.width;
+        self.config.height = clamped.height;
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.config),
+            None => self.offscreen_texture = Some(Self::create_offscreen_texture(&self.device, &self.config)),
+        }
+        self.recreate_render_targets();
+        self.resize_cameras();
+    }
+
+    // Each camera gets the aspect ratio of its half in the split view
+    fn resize_cameras(&mut self) {
+        let [left, right] = self.split_viewports();
+        match &mut self.split_camera {
+            Some(camera) => {
+                self.camera_state.resize(left.1, self.config.height, &self.queue);
+                camera.resize(right.1, self.config.height, &self.queue);
+            }
+            None => self.camera_state.resize(self.config.width, self.config.height, &self.queue),
+        }
+    }
+
+    // Left and right half of the frame as (x, width) in pixels
+    fn split_viewports(&self) -> [(u32, u32); 2] {
+        let left = self.config.width / 2;
+        [(0, left), (left, self.config.width - left)]
+    }
+
+    pub fn is_split_view(&self) -> bool {
+        self.split_camera.is_some()
+    }
+
+    // Draws the scene twice side by side, the left half with `camera_state` and the right one
+    // with `split_camera`, which starts at the same viewpoint and isn't moved by input.
+    // SSAO is suspended meanwhile, it rebuilds positions with a single camera's projection.
+    pub fn set_split_view(&mut self, on: bool) {
+        if on == self.split_camera.is_some() {
+            return;
+        }
+        self.split_camera = on.then(|| {
+            let model = &self.camera_state.model;
+            let mut camera = CameraState::new(&self.device, self.config.width, self.config.height, &self.camera_bind_group_layout);
+            camera.set_viewpoint(self.camera_state.viewpoint());
+            camera.set_projection(model.projection);
+            camera.model.znear = model.znear;
+            camera.model.zfar = model.zfar;
+            camera.model.reverse_z = model.reverse_z;
+            camera
+        });
+        self.resize_cameras();
+        self.light.set_ambient_occlusion(&self.queue, self.is_ssao_active());
+    }
+
+    // The right half's camera, None unless the split view is on
+    pub fn split_camera(&self) -> Option<&CameraState> {
+        self.split_camera.as_ref()
+    }
+
+    pub fn split_camera_mut(&mut self) -> Option<&mut CameraState> {
+        self.split_camera.as_mut()
+    }
+
+    // The camera drawing pixel column x, with the left edge and the width of its part of the frame
+    fn camera_at(&self, x: u32) -> (&CameraState, u32, u32) {
+        let [left, right] = self.split_viewports();
+        match &self.split_camera {
+            Some(camera) if x >= right.0 => (camera, right.0, right.1),
+            Some(_) => (&self.camera_state, left.0, left.1),
+            None => (&self.camera_state, 0, self.config.width),
+        }
+    }
+
+    // Runs `draw` once per camera, each with its viewport in the split view
+    fn draw_views<'p>(&'p self, render_pass: &mut wgpu::RenderPass<'p>, draw: impl Fn(&mut wgpu::RenderPass<'p>, &'p CameraState)) {
+        let Some(split_camera) = &self.split_camera else {
+            draw(render_pass, &self.camera_state);
+            return;
+        };
+        let height = self.config.height as f32;
+        for (camera, (x, width)) in [&self.camera_state, split_camera].into_iter().zip(self.split_viewports()) {
+            render_pass.set_viewport(x as f32, 0.0, width as f32, height, 0.0, 1.0);
+            draw(render_pass, camera);
+        }
+    }
+
+    // Neither minimized nor occluded
+    pub fn is_visible(&self) -> bool {
+        !self.minimized && !self.occluded
+    }
+
+    // `render` skips frames while the window is hidden, e.g. on WindowEvent::Occluded.
+    // A resize to zero, as on minimizing, hides it too and the next proper size shows it
+    // again, unless it is still occluded.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.occluded = !visible;
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    // Called when the window moves to a monitor with a different DPI. The window's inner size
+    // is still the old one then, winit sends Resized with the new one after the event.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
+    pub fn background_color(&self) -> wgpu::Color {
+        self.background_color
+    }
+
+    // Stays until the cursor moves or the hue cycles, unless the mode is Solid
+    pub fn set_background_color(&mut self, color: wgpu::Color) {
+        self.background_color = color;
+        if let BackgroundMode::Solid(_) = self.background_mode {
+            self.background_mode = BackgroundMode::Solid(color);
+        }
+    }
+
+    // Disabling keeps the current color
+    pub fn set_cursor_color_enabled(&mut self, enabled: bool) {
+        self.set_background_mode(if enabled {
+            BackgroundMode::CursorTracking
+        } else {
+            BackgroundMode::Solid(self.background_color)
+        });
+    }
+
+    pub fn background_mode(&self) -> BackgroundMode {
+        self.background_mode
+    }
+
+    pub fn set_background_mode(&mut self, mode: BackgroundMode) {
+        self.background_mode = mode;
+        match mode {
+            BackgroundMode::Solid(color) => self.background_color = color,
+            // Starts from the current position on the next CursorMoved
+            BackgroundMode::CursorTracking => {}
+            BackgroundMode::HueCycle { .. } => {
+                self.background_time = Duration::ZERO;
+                self.update_background();
+            }
+        }
+    }
+
+    fn update_background(&mut self) {
+        if let BackgroundMode::HueCycle { period } = self.background_mode {
+            let hue = if period.is_zero() {
+                0.0
+            } else {
+                self.background_time.as_secs_f64() / period.as_secs_f64() % 1.0
+            };
+            self.background_color = hue_to_color(hue);
+        }
+    }
+
+    // May not be sRGB where the surface doesn't support it, the tone mapping pass encodes
+    // the output itself then. Textures drawn into the frame have to be of this format.
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    // A headless State reports its target's format and the configured modes
+    pub fn surface_capabilities(&self) -> &wgpu::SurfaceCapabilities {
+        &self.surface_caps
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    pub fn present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.surface_caps.present_modes
+    }
+
+    // Fifo is vsync, Immediate and Mailbox don't wait for it where supported
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> Result<(), PresentModeError> {
+        if !self.surface_caps.present_modes.contains(&mode) {
+            return Err(PresentModeError {
+                requested: mode,
+                supported: self.surface_caps.present_modes.clone(),
+            });
+        }
+        self.config.present_mode = mode;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+        Ok(())
+    }
+
+    pub fn alpha_mode(&self) -> wgpu::CompositeAlphaMode {
+        self.config.alpha_mode
+    }
+
+    pub fn alpha_modes(&self) -> &[wgpu::CompositeAlphaMode] {
+        &self.surface_caps.alpha_modes
+    }
+
+    // How the frame's alpha is composited with what's behind the window, e.g. the desktop
+    // or the page around the canvas. The background color's alpha is what shows through.
+    // With PreMultiplied the compositor expects colors already multiplied by the alpha, so
+    // the background is cleared premultiplied and alpha blended geometry stays so. With
+    // PostMultiplied the compositor multiplies them, blended geometry over a transparent
+    // background comes out darker then. Opaque ignores the alpha.
+    pub fn set_alpha_mode(&mut self, mode: wgpu::CompositeAlphaMode) -> Result<(), AlphaModeError> {
+        if !self.surface_caps.alpha_modes.contains(&mode) {
+            return Err(AlphaModeError {
+                requested: mode,
+                supported: self.surface_caps.alpha_modes.clone(),
+            });
+        }
+        self.config.alpha_mode = mode;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+        Ok(())
+    }
+
+    // The background color as the scene pass clears to it, see set_alpha_mode
+    fn clear_color(&self) -> wgpu::Color {
+        let color = self.background_color;
+        if self.config.alpha_mode == wgpu::CompositeAlphaMode::PreMultiplied {
+            wgpu::Color { r: color.r * color.a, g: color.g * color.a, b: color.b * color.a, a: color.a }
+        } else {
+            color
+        }
+    }
+
+    pub fn frame_latency(&self) -> u32 {
+        self.config.desired_maximum_frame_latency
+    }
+
+    // Frames the GPU may queue up before `render` blocks, clamped to 1..=MAX_FRAME_LATENCY.
+    // 1 shows input soonest but leaves the GPU idle while the CPU prepares the next frame,
+    // 2 or 3 keep it busy for more throughput at the cost of a frame or two of input lag.
+    pub fn set_frame_latency(&mut self, latency: u32) {
+        self.config.desired_maximum_frame_latency = latency.clamp(1, MAX_FRAME_LATENCY);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    pub fn tonemap(&self) -> ToneMapOp {
+        self.tone_mapper.op()
+    }
+
+    // Applied to the whole scene before it's written to the output
+    pub fn set_tonemap(&mut self, op: ToneMapOp) {
+        self.tone_mapper.set_op(&self.queue, op);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.tone_mapper.exposure()
+    }
+
+    // Scales the HDR scene before tone mapping, 1.0 by default
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.tone_mapper.set_exposure(&self.queue, exposure);
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.tone_mapper.gamma()
+    }
+
+    // The output is raised to 1/gamma after tone mapping. Defaults to 2.2 for surfaces
+    // without sRGB formats and to 1.0 for sRGB ones, which the hardware already encodes.
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.tone_mapper.set_gamma(&self.queue, gamma);
+    }
+
+    // Vertical field of view, None with an orthographic projection
+    pub fn fov(&self) -> Option<Deg<f32>> {
+        match self.camera_state.model.projection {
+            Projection::Perspective { fovy } => Some(fovy),
+            Projection::Orthographic { .. } => None,
+        }
+    }
+
+    // Clamped to MIN_FOVY..=MAX_FOVY, no-op with an orthographic projection.
+    // Takes effect on the next update.
+    pub fn set_fov(&mut self, fovy: Deg<f32>) {
+        if let Projection::Orthographic { .. } = self.camera_state.model.projection {
+            log::warn!("The field of view doesn't apply to an orthographic projection");
+            return;
+        }
+        let fovy = Deg(fovy.0.clamp(MIN_FOVY.0, MAX_FOVY.0));
+        self.camera_state.set_projection(Projection::Perspective { fovy });
+    }
+
+    pub fn set_light_direction(&mut self, dir: Vector3<f32>) {
+        self.light.set_direction(&self.queue, dir);
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.light.lights
+    }
+
+    // Lights added to the main directional light of `set_light_direction`, which alone casts
+    // shadows. Without storage buffers, e.g. on WebGL, only the first MAX_UNIFORM_LIGHTS are used.
+    pub fn set_lights(&mut self, lights: &[Light]) {
+        self.light.set_lights(&self.device, &self.queue, &self.light_bind_group_layout, &self.shadow_map, self.ssao.view(), lights);
+    }
+
+    // Shininess and specular strength of the surfaces
+    pub fn material(&self) -> (f32, f32) {
+        (self.light.shininess, self.light.specular_strength)
+    }
+
+    // Blinn-Phong highlights of every light, `shininess` is the exponent (clamped to at least 1)
+    // and `specular_strength` scales them, 0 turns them off
+    pub fn set_material(&mut self, shininess: f32, specular_strength: f32) {
+        self.light.set_material(&self.queue, shininess.max(1.0), specular_strength.max(0.0));
+    }
+
+    pub fn shadows_enabled(&self) -> bool {
+        self.light.shadows
+    }
+
+    // Shadows of the directional light on the instances and on a ground plane below them
+    pub fn set_shadows(&mut self, on: bool) {
+        self.light.set_shadows(&self.queue, on);
+    }
+
+    pub fn shadow_map_size(&self) -> u32 {
+        self.shadow_map.size()
+    }
+
+    // Width and height of the shadow map in texels, clamped to the device limit
+    pub fn set_shadow_map_size(&mut self, size: u32) {
+        let size = size.clamp(1, self.limits.max_texture_dimension_2d);
+        self.shadow_map.resize(&self.device, size);
+        self.light.recreate_bind_group(&self.device, &self.light_bind_group_layout, &self.shadow_map, self.ssao.view());
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        #[cfg(feature = "egui")]
+        if self.egui_overlay.on_event(event, self.scale_factor) {
+            return true;
+        }
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.set_scale_factor(*scale_factor);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if self.background_mode == BackgroundMode::CursorTracking {
+                    // Logical, so the colors don't depend on the monitor's DPI
+                    self.background_color = position_to_color(&position.to_logical(self.scale_factor));
+                }
+                self.cursor_position = Some(*position);
+                // The camera controller also tracks the cursor for orbiting
+                self.camera_state.controller.process_events(event);
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if let Some(position) = self.cursor_position {
+                    let now = Instant::now();
+                    let double_click = self.last_click.is_some_and(|(time, last)| {
+                        now - time <= DOUBLE_CLICK_TIME
+                            && (position.x - last.x).hypot(position.y - last.y) <= DOUBLE_CLICK_DISTANCE
+                    });
+                    if double_click {
+                        // The depth readback would block the browser's event loop, which
+                        // has to run for the buffer to be mapped
+                        #[cfg(not(target_arch = "wasm32"))]
+                        self.focus_at(position.x as u32, position.y as u32);
+                        // A third click starts over
+                        self.last_click = None;
+                    } else {
+                        self.last_click = Some((now, position));
+                    }
+                }
+                // Still starts dragging
+                self.camera_state.controller.process_events(event)
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(keycode @ (KeyCode::BracketLeft | KeyCode::BracketRight)),
+                    ..
+                },
+                ..
+            } => {
+                self.resize_grid(if *keycode == KeyCode::BracketRight { 1 } else { -1 });
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::Space),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.set_paused(!self.paused);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(keycode @ (KeyCode::Equal | KeyCode::NumpadAdd | KeyCode::Minus | KeyCode::NumpadSubtract)),
+                    ..
+                },
+                ..
+            } if self.fov().is_some() => {
+                // + narrows the field of view, which zooms in
+                let step = if matches!(keycode, KeyCode::Equal | KeyCode::NumpadAdd) { -FOVY_STEP } else { FOVY_STEP };
+                self.set_fov(self.fov().unwrap() + step);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::Period),
+                    ..
+                },
+                ..
+            } => {
+                self.step();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.set_particles_enabled(!self.is_particles_enabled());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyM),
+                    ..
+                },
+                ..
+            } => {
+                self.cycle_mesh();
+                true
+            }
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                    ..
+                },
+                ..
+            } => {
+                self.reload_shaders();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                let mode = if self.camera_mode() == CameraMode::Fly { CameraMode::Orbit } else { CameraMode::Fly };
+                self.set_camera_mode(mode);
+                true
+            }
+            // Releases the cursor before Escape can quit
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::Escape),
+                    ..
+                },
+                ..
+            } if self.camera_mode() == CameraMode::Fly => {
+                self.set_camera_mode(CameraMode::Orbit);
+                true
+            }
+            _ => {
+                self.camera_state.controller.process_events(event)
+            },
+        }
+    }
+
+    // Forward DeviceEvent::MouseMotion, flying looks around with it
+    pub fn process_mouse_motion(&mut self, (dx, dy): (f64, f64)) -> bool {
+        self.camera_state.controller.process_mouse_motion(dx, dy)
+    }
+
+    pub fn camera_mode(&self) -> CameraMode {
+        self.camera_state.mode()
+    }
+
+    // Flying hides the cursor and locks it to the window, where the platform allows it
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_state.set_mode(mode);
+        let Some(window) = self.window else {
+            return;
+        };
+        let grab = if mode == CameraMode::Fly {
+            // Not every platform can lock the cursor, keeping it inside the window is the next best thing
+            window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = grab {
+            log::warn!("Failed to grab the cursor: {}", e);
+        }
+        window.set_cursor_visible(mode != CameraMode::Fly);
+    }
+
+    pub fn instances(&self) -> &Instances {
+        &self.instances
+    }
+
+    // Changes made through `Instances::transform_mut` are committed by the next `update`
+    pub fn instances_mut(&mut self) -> &mut Instances {
+        &mut self.instances
+    }
+
+    // Replaces the instances, e.g. with ones from Instances::from_transforms
+    pub fn set_instances(&mut self, mut instances: Instances) {
+        instances.set_vertex_storage(&self.device, self.vertex_storage_supported);
+        instances.set_mesh(&self.queue, &self.meshes[self.selected_mesh]);
+        if self.is_gpu_animation() {
+            instances.set_gpu_animated(&self.device, true);
+        }
+        self.instances = instances;
+        // The pipelines were created with the old instances layout
+        self.rebuild_render_pipeline();
+        self.rebuild_overlay_pipelines();
+        self.shadow_map.rebuild_pipeline(&self.device, &self.rotation_bind_group_layout, &self.instances.layout, self.instance_vertex_buffer);
+        if self.bounds_visible {
+            self.bounds_box.set_bounds(&self.queue, self.instances.bounds());
+        }
+    }
+
+    // Adds (or removes for a negative delta) rows and columns of instances
+    pub fn resize_grid(&mut self, delta: i32) {
+        let rows = self.instances.rows().saturating_add_signed(delta);
+        let cols = self.instances.cols().saturating_add_signed(delta);
+        self.instances.set_grid(&self.device, rows, cols, self.instances.spacing());
+    }
+
+    // Advances by the time since the last call, see `update_with_dt`
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let dt = now - self.last_update;
+        self.last_update = now;
+        self.update_with_dt(dt);
+    }
+
+    // For hosts keeping their own clock, e.g. recording at a fixed frame rate
+    pub fn update_with_dt(&mut self, dt: Duration) {
+        // The camera can still be moved while paused
+        self.camera_state.update(&self.queue, dt);
+        if let Some(camera) = &mut self.split_camera {
+            camera.update(&self.queue, dt);
+        }
+        match self.deterministic_frame {
+            Some(frame) => self.seek(frame),
+            None => self.advance(if self.paused { Duration::ZERO } else { dt }),
+        }
+        if self.bounds_visible {
+            self.bounds_box.set_bounds(&self.queue, self.instances.bounds());
+        }
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.update(&self.queue);
+        }
+        if self.is_ssao_active() {
+            self.ssao.update(&self.queue, &self.camera_state.model);
+        }
+        if self.axis_gizmo_enabled {
+            self.axis_gizmo.update(&self.queue, &self.camera_state.model);
+        }
+        #[cfg(feature = "egui")]
+        {
+            self.egui_overlay.begin_pass(self.size, self.scale_factor);
+            self.debug_ui();
+        }
+    }
+
+    // Advances the instance animations by `dt`
+    fn advance(&mut self, dt: Duration) {
+        self.background_time += dt;
+        self.update_background();
+        self.rotation.update(&self.queue, dt);
+        self.order_instances();
+        self.instances.update(&self.queue, dt);
+        if let (Some(animation), Some(bind_group)) = (&self.instance_animation, self.instances.animation_bind_group()) {
+            if !dt.is_zero() {
+                animation.run(&self.device, &self.queue, bind_group, self.instances.count(), dt);
+            }
+        }
+        if let Some(particles) = &mut self.particles {
+            if !dt.is_zero() {
+                particles.run(&self.device, &self.queue, dt);
+            }
+        }
+    }
+
+    // Sorts and culls the instances for the cameras before they are uploaded
+    fn order_instances(&mut self) {
+        if self.transparent {
+            self.instances.sort_back_to_front(self.camera_state.position());
+        }
+        if self.frustum_culling {
+            let view_projections: Vec<_> = std::iter::once(&self.camera_state)
+                .chain(&self.split_camera)
+                .map(|camera| camera.model.build_view_projection_matrix())
+                .collect();
+            self.instances.cull(&self.queue, &view_projections);
+        }
+    }
+
+    // Sets the animations to where they are `frame` STEPs after the start. Replaying the
+    // particles from the start when going back can take a while.
+    fn seek(&mut self, frame: u64) {
+        // Saturates after hundreds of years
+        let time = Duration::from_nanos((STEP.as_nanos() as u64).saturating_mul(frame));
+        self.background_time = time;
+        self.update_background();
+        self.rotation.set_time(&self.queue, time);
+        self.order_instances();
+        // Also with GPU animation, its compute pass isn't run
+        self.instances.set_time(&self.queue, time);
+        if let Some(particles) = &mut self.particles {
+            particles.run_until(&self.device, &self.queue, frame, STEP);
+        }
+    }
+
+    // Idle when the animations are paused or have nothing to animate, and the cameras
+    // neither animate nor move with held keys
+    pub fn render_mode(&self) -> RenderMode {
+        let camera_moving = std::iter::once(&self.camera_state)
+            .chain(&self.split_camera)
+            .any(|camera| camera.is_animating() || camera.controller.is_moving());
+        let animating = !self.paused && self.deterministic_frame.is_none() && (
+            matches!(self.background_mode, BackgroundMode::HueCycle { period } if !period.is_zero())
+                || self.rotation.is_turning()
+                || self.instances.is_spinning()
+                || self.particles.is_some());
+        #[cfg(feature = "egui")]
+        let animating = animating || self.egui_overlay.context().has_requested_repaint();
+        if camera_moving || animating {
+            RenderMode::Continuous
+        } else {
+            RenderMode::Idle
+        }
+    }
+
+    pub fn deterministic_frame(&self) -> Option<u64> {
+        self.deterministic_frame
+    }
+
+    // Computes the rotation, the instance spins, the background's hue and the particles
+    // for `frame` 60 Hz ticks after the start instead of advancing them by the elapsed time,
+    // until `clear_deterministic`. The same frame always renders the same, e.g. for golden
+    // images or recordings. Spins start from angle 0. `set_paused` is ignored, a rotation
+    // paused on its own stays where it is. Particles are simulated one compute submission
+    // per frame up to `frame`, from the start when it's earlier than the last one, so far
+    // away frames take a while with particles on.
+    pub fn set_deterministic(&mut self, frame: u64) {
+        self.deterministic_frame = Some(frame);
+        self.seek(frame);
+    }
+
+    // Animations continue from the deterministic frame by the elapsed time
+    pub fn clear_deterministic(&mut self) {
+        self.deterministic_frame = None;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Freezes the animations, `update` keeps uploading camera moves
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    // Advances the animations by a single 60 Hz tick, meant for stepping while paused,
+    // or to the next deterministic frame
+    pub fn step(&mut self) {
+        match self.deterministic_frame {
+            Some(frame) => self.set_deterministic(frame + 1),
+            None => self.advance(STEP),
+        }
+    }
+
+    // Panels added to it after `update` are drawn by the next `render`
+    #[cfg(feature = "egui")]
+    pub fn egui_context(&self) -> &egui::Context {
+        self.egui_overlay.context()
+    }
+
+    #[cfg(feature = "egui")]
+    fn debug_ui(&mut self) {
+        // The context is reference counted, cloning it frees self for the closure
+        let context = self.egui_overlay.context().clone();
+        egui::Window::new("Debug").show(&context, |ui| {
+            ui.label(format!("{:.1} fps, {:.2} ms", self.fps(), self.frame_time_ms()));
+            let mut culling = self.frustum_culling;
+            if ui.checkbox(&mut culling, format!("frustum culling, {} drawn", self.last_draw_count)).changed() {
+                self.set_frustum_culling(culling);
+            }
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.paused, "paused");
+                if ui.button("step").clicked() {
+                    self.step();
+                }
+            });
+
+            let (mut x, mut y) = self.rotation.speed();
+            let x_changed = ui.add(egui::Slider::new(&mut x, -300.0..=300.0).text("rotation x (deg/s)")).changed();
+            let y_changed = ui.add(egui::Slider::new(&mut y, -300.0..=300.0).text("rotation y (deg/s)")).changed();
+            if x_changed || y_changed {
+                self.rotation.set_speed(x, y);
+            }
+
+            let mut direction = self.light.direction;
+            let mut changed = false;
+            ui.horizontal(|ui| {
+                ui.label("light direction");
+                for component in [&mut direction.x, &mut direction.y, &mut direction.z] {
+                    changed |= ui.add(egui::DragValue::new(component).speed(0.01).range(-1.0..=1.0)).changed();
+                }
+            });
+            // Normalizing the zero vector would give NaNs
+            if changed && direction != Vector3::new(0.0, 0.0, 0.0) {
+                self.set_light_direction(direction);
+            }
+
+            let (mut shininess, mut specular_strength) = self.material();
+            let shininess_changed = ui.add(egui::Slider::new(&mut shininess, 1.0..=256.0).logarithmic(true).text("shininess")).changed();
+            let specular_changed = ui.add(egui::Slider::new(&mut specular_strength, 0.0..=2.0).text("specular")).changed();
+            if shininess_changed || specular_changed {
+                self.set_material(shininess, specular_strength);
+            }
+
+            let mut fxaa = self.is_fxaa_enabled();
+            if ui.checkbox(&mut fxaa, "FXAA").changed() {
+                self.set_fxaa(fxaa);
+            }
+
+            let mut ssao = self.ssao_enabled;
+            if ui.checkbox(&mut ssao, "SSAO").changed() {
+                self.set_ssao(ssao, self.ssao.radius(), self.ssao.strength());
+            }
+
+            let mut bloom = self.bloom_enabled;
+            if ui.checkbox(&mut bloom, "bloom").changed() {
+                self.set_bloom(bloom, self.bloom.threshold(), self.bloom.intensity());
+            }
+
+            let mut exposure = self.exposure();
+            if ui.add(egui::Slider::new(&mut exposure, 0.0..=8.0).text("exposure")).changed() {
+                self.set_exposure(exposure);
+            }
+            let mut gamma = self.gamma();
+            if ui.add(egui::Slider::new(&mut gamma, 0.1..=4.0).text("gamma")).changed() {
+                self.set_gamma(gamma);
+            }
+
+            let (mut rows, mut cols) = (self.instances.rows(), self.instances.cols());
+            let rows_changed = ui.add(egui::Slider::new(&mut rows, 1..=50).text("rows")).changed();
+            let cols_changed = ui.add(egui::Slider::new(&mut cols, 1..=50).text("columns")).changed();
+            if rows_changed || cols_changed {
+                self.instances.set_grid(&self.device, rows, cols, self.instances.spacing());
+            }
+
+            let mut present_mode = self.present_mode();
+            egui::ComboBox::from_label("present mode")
+                .selected_text(format!("{:?}", present_mode))
+                .show_ui(ui, |ui| {
+                    for mode in &self.surface_caps.present_modes {
+                        ui.selectable_value(&mut present_mode, *mode, format!("{:?}", mode));
+                    }
+                });
+            if present_mode != self.present_mode() {
+                // Only supported modes are listed
+                self.set_present_mode(present_mode).unwrap();
+            }
+
+            let mut alpha_mode = self.alpha_mode();
+            egui::ComboBox::from_label("alpha mode")
+                .selected_text(format!("{:?}", alpha_mode))
+                .show_ui(ui, |ui| {
+                    for mode in &self.surface_caps.alpha_modes {
+                        ui.selectable_value(&mut alpha_mode, *mode, format!("{:?}", mode));
+                    }
+                });
+            if alpha_mode != self.alpha_mode() {
+                // Only supported modes are listed
+                self.set_alpha_mode(alpha_mode).unwrap();
+            }
+
+            let mut latency = self.frame_latency();
+            if ui.add(egui::Slider::new(&mut latency, 1..=MAX_FRAME_LATENCY).text("frame latency")).changed() {
+                self.set_frame_latency(latency);
+            }
+
+            let mut capped = self.target_fps.is_some();
+            let mut fps = self.target_fps.unwrap_or(60);
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut capped, "cap fps");
+                ui.add_enabled(capped, egui::Slider::new(&mut fps, 1..=240));
+            });
+            let target_fps = capped.then_some(fps);
+            if target_fps != self.target_fps {
+                self.set_target_fps(target_fps);
+            }
+        });
+    }
+
+    fn run_cubes_pipeline(&self,
+                          view: &TextureView,
+                          encoder: &mut CommandEncoder,
+                          timestamp_writes: Option<wgpu::RenderPassTimestampWrites>) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: view,
-                resolve_target: None,
+                view: self.msaa_view.as_ref().unwrap_or(view),
+                resolve_target: self.msaa_view.as_ref().map(|_| view),
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(self.background_color),
+                    load: wgpu::LoadOp::Clear(self.clear_color()),
                     store: StoreOp::Store,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &self.depth_texture.view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    // The far plane
+                    load: wgpu::LoadOp::Clear(if self.camera_state.model.reverse_z { 0.0 } else { 1.0 }),
+                    store: StoreOp::Store,
+                }),
+                // Must be None for formats without stencil
+                stencil_ops: self.depth_format.has_stencil_aspect().then_some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
                     store: StoreOp::Store,
                 }),
-                stencil_ops: None,
             }),
-            timestamp_writes: None,
+            timestamp_writes,
             occlusion_query_set: None,
         });
-        render_pass.set_pipeline(&self.render_pipeline);
+        self.draw_views(&mut render_pass, |render_pass, camera| self.draw_scene(render_pass, camera));
+    }
+
+    fn draw_scene<'p>(&'p self, render_pass: &mut wgpu::RenderPass<'p>, camera: &'p CameraState) {
+        self.skybox.render(render_pass, &camera.bind_group);
+        render_pass.set_pipeline(self.scene_pipelines[&wgpu::PrimitiveTopology::TriangleList].select(self.wireframe, self.transparent));
+        render_pass.set_bind_group(1, &camera.bind_group, &[]);
+        render_pass.set_bind_group(4, &self.light.bind_group, &[]);
+        if self.light.shadows {
+            let ground = &self.ground_plane;
+            render_pass.set_bind_group(0, &ground.texture_bind_group, &[]);
+            render_pass.set_bind_group(2, &ground.rotation.bind_group, &[]);
+            render_pass.set_bind_group(3, &ground.instance_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, ground.mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, ground.instance_buffer.slice(..));
+            render_pass.set_index_buffer(ground.mesh.index_buffer.slice(..), ground.mesh.index_format());
+            render_pass.draw_indexed(0..ground.mesh.num_indices(), 0, 0..1);
+        }
+        // Only the instances are outlined, the ground plane above wrote the default reference 0
+        render_pass.set_stencil_reference(outline::INSTANCE_STENCIL_REFERENCE);
         render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.camera_state.bind_group, &[]);
-        render_pass.set_bind_group(2, &self.rotator.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.rotation.bind_group, &[]);
         render_pass.set_bind_group(3, &self.instances.bind_group, &[]);
-        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.draw_indexed(0..self.mesh.num_indices, 0, 0..self.instances.count());
+        let mesh = &self.meshes[self.selected_mesh];
+        render_pass.set_pipeline(self.scene_pipelines[&mesh.topology()].select(self.wireframe, self.transparent));
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        // Only read by the pipelines taking the instances as vertex attributes
+        render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format());
+        if self.indirect_draw {
+            render_pass.draw_indexed_indirect(self.instances.indirect_buffer(), 0);
+        } else {
+            render_pass.draw_indexed(0..mesh.num_indices(), 0, 0..self.instances.visible_count());
+        }
+        if let Some(backfaces) = self.scene_pipelines[&mesh.topology()].backfaces.as_ref().filter(|_| self.show_backfaces) {
+            render_pass.set_pipeline(backfaces);
+            render_pass.draw_indexed(0..mesh.num_indices(), 0, 0..self.instances.visible_count());
+        }
+        // The outline and the shadows are only drawn for triangles
+        if self.outline_enabled && mesh.topology() == wgpu::PrimitiveTopology::TriangleList {
+            self.outline.render(render_pass, &camera.bind_group, &self.rotation.bind_group, &self.instances, mesh);
+        }
+        if self.ground_grid_visible {
+            self.ground_grid.render(render_pass, &camera.bind_group);
+        }
+        if self.bounds_visible {
+            self.bounds_box.render(render_pass, &camera.bind_group);
+        }
+        if let Some(particles) = &self.particles {
+            self.particle_renderer.render(render_pass, &camera.bind_group, particles);
+        }
+    }
+
+    // Fills the depth texture for the SSAO pass, the scene pass clears it again
+    fn run_depth_prepass(&self, encoder: &mut CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    // The far plane
+                    load: wgpu::LoadOp::Clear(if self.camera_state.model.reverse_z { 0.0 } else { 1.0 }),
+                    store: StoreOp::Store,
+                }),
+                // Must be None for formats without stencil
+                stencil_ops: self.depth_format.has_stencil_aspect().then_some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: StoreOp::Store,
+                }),
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(self.ssao.prepass_pipeline());
+        // Not run in the split view, see set_split_view
+        self.draw_prepass(&mut render_pass, &self.camera_state);
+    }
 
+    fn draw_prepass<'p>(&'p self, render_pass: &mut wgpu::RenderPass<'p>, camera: &'p CameraState) {
+        render_pass.set_bind_group(0, &camera.bind_group, &[]);
+        if self.light.shadows {
+            let ground = &self.ground_plane;
+            render_pass.set_bind_group(1, &ground.rotation.bind_group, &[]);
+            render_pass.set_bind_group(2, &ground.instance_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, ground.mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, ground.instance_buffer.slice(..));
+            render_pass.set_index_buffer(ground.mesh.index_buffer.slice(..), ground.mesh.index_format());
+            render_pass.draw_indexed(0..ground.mesh.num_indices(), 0, 0..1);
+        }
+        // Lines and points don't occlude
+        let mesh = &self.meshes[self.selected_mesh];
+        if mesh.topology() != wgpu::PrimitiveTopology::TriangleList {
+            return;
+        }
+        render_pass.set_bind_group(1, &self.rotation.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.instances.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format());
+        render_pass.draw_indexed(0..mesh.num_indices(), 0, 0..self.instances.visible_count());
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    // World space point the last frame shows at pixel (x, y) from the top left, None over the
+    // background and outside the surface. Waits for the GPU to read the depth buffer back,
+    // so it's native only: on wasm the mapping only completes once control returns to the
+    // browser, it hangs there.
+    pub fn world_position_at(&self, x: u32, y: u32) -> Option<Point3<f32>> {
+        use cgmath::SquareMatrix;
+        if x >= self.config.width || y >= self.config.height {
+            return None;
+        }
+        let depth = self.depth_readback.read(&self.device, &self.queue, &self.depth_texture, x, y);
+        let (camera, left, width) = self.camera_at(x);
+        let model = &camera.model;
+        // The depth buffer is cleared to the far plane
+        if depth == if model.reverse_z { 0.0 } else { 1.0 } {
+            return None;
+        }
+        let ndc = cgmath::Vector4::new(
+            ((x - left) as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / self.config.height as f32 * 2.0,
+            depth,
+            1.0,
+        );
+        let world = model.build_view_projection_matrix().invert()? * ndc;
+        Some(Point3::new(world.x / world.w, world.y / world.w, world.z / world.w))
+    }
+
+    // Moves the camera to orbit around the point under pixel (x, y), as on a double click.
+    // Returns false and leaves the camera alone where no geometry is. In the split view
+    // the camera of the half clicked into moves. Native only like `world_position_at`,
+    // double clicks don't focus on wasm.
+    pub fn focus_at(&mut self, x: u32, y: u32) -> bool {
+        let right = self.split_viewports()[1].0;
+        match self.world_position_at(x, y) {
+            Some(point) => {
+                match &mut self.split_camera {
+                    Some(camera) if x >= right => camera.focus_on(point, FOCUS_DURATION),
+                    _ => self.camera_state.focus_on(point, FOCUS_DURATION),
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Averaged over the last 60 frames
+    pub fn fps(&self) -> f32 {
+        self.frame_timer.fps()
+    }
+
+    pub fn frame_time_ms(&self) -> f32 {
+        self.frame_timer.frame_time_ms()
+    }
+
+    // GPU time of the last measured frame, None when timestamp queries aren't supported
+    pub fn last_pass_timings(&self) -> Option<PassTimings> {
+        self.profiler.as_ref().and_then(|p| p.last_timings())
+    }
+
+    fn create_render_graph() -> RenderGraph {
+        use Resource::*;
+        let mut graph = RenderGraph::new();
+        let passes: &[(PassId, &[Resource], &[Resource])] = &[
+            (PassId::Shadows, &[], &[ShadowMap]),
+            (PassId::DepthPrepass, &[], &[PrepassDepth]),
+            (PassId::Ssao, &[PrepassDepth], &[AmbientOcclusion]),
+            (PassId::Scene, &[ShadowMap, AmbientOcclusion], &[Hdr, Depth]),
+            (PassId::Bloom, &[Hdr], &[Hdr]),
+            (PassId::ToneMap, &[Hdr], &[Frame]),
+            (PassId::Recorder, &[Hdr], &[]),
+            (PassId::Overlay, &[], &[Frame]),
+            (PassId::DepthView, &[Depth], &[Frame]),
+            (PassId::AxisGizmo, &[], &[Frame]),
+            #[cfg(feature = "egui")]
+            (PassId::Egui, &[], &[Frame]),
+        ];
+        for &(id, inputs, outputs) in passes {
+            graph.add_pass(id, inputs, outputs).expect("the built-in passes form a valid graph");
+        }
+        graph
+    }
+
+    pub fn render_graph(&self) -> &RenderGraph {
+        &self.render_graph
+    }
+
+    // To disable or reorder the passes of a frame
+    pub fn render_graph_mut(&mut self) -> &mut RenderGraph {
+        &mut self.render_graph
+    }
+
+    // Passes whose feature is off do nothing
+    fn run_pass(&mut self, pass: PassId, encoder: &mut CommandEncoder, view: &TextureView) {
+        match pass {
+            PassId::Shadows => {
+                if self.light.shadows && self.meshes[self.selected_mesh].topology() == wgpu::PrimitiveTopology::TriangleList {
+                    self.shadow_map.render(encoder,
+                                           &self.light.shadow_bind_group,
+                                           &self.rotation.bind_group,
+                                           &self.instances,
+                                           &self.meshes[self.selected_mesh]);
+                }
+            }
+            PassId::DepthPrepass => {
+                if self.is_ssao_active() {
+                    self.run_depth_prepass(encoder);
+                }
+            }
+            PassId::Ssao => {
+                if self.is_ssao_active() {
+                    self.ssao.render(encoder);
+                }
+            }
+            PassId::Scene => {
+                self.run_cubes_pipeline(self.tone_mapper.hdr_view(), encoder, self.profiler.as_ref().and_then(|p| p.scene_pass_writes()));
+                let views = if self.split_camera.is_some() { 2 } else { 1 };
+                self.last_draw_count = self.instances.visible_count() * views;
+            }
+            PassId::Bloom => {
+                if self.bloom_enabled {
+                    self.bloom.render(encoder, self.tone_mapper.hdr_view());
+                }
+            }
+            PassId::ToneMap => {
+                if let Some(fxaa) = &self.fxaa {
+                    self.tone_mapper.render(encoder, fxaa.view());
+                    fxaa.render(encoder, view);
+                } else {
+                    self.tone_mapper.render(encoder, view);
+                }
+            }
+            PassId::Recorder => {
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record(encoder, &self.tone_mapper);
+                }
+            }
+            PassId::Overlay => {
+                if let Some(overlay_renderer) = &self.overlay_renderer {
+                    overlay_renderer(encoder, view);
+                }
+            }
+            PassId::DepthView => {
+                if let Some(depth_view) = &self.depth_view {
+                    depth_view.render(view, encoder, self.size, self.profiler.as_mut().and_then(|p| p.depth_view_pass_writes()));
+                }
+            }
+            PassId::AxisGizmo => {
+                if self.axis_gizmo_enabled {
+                    self.axis_gizmo.render(view, encoder, self.size, self.scale_factor, self.axis_gizmo_corner);
+                }
+            }
+            #[cfg(feature = "egui")]
+            PassId::Egui => {
+                self.egui_overlay.render(&self.device, &self.queue, encoder, view, self.size);
+            }
+        }
+    }
+
+    fn render_to_view(&mut self, view: &TextureView) {
+        self.frame_timer.record_frame();
+        self.last_draw_count = 0;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin_frame(&self.device);
+        }
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        self.run_cubes_pipeline(&view, &mut encoder);
-        if let Some(depth_view) = &self.depth_view {
-            depth_view.render(&view, &mut encoder);
+        for pass in self.render_graph.execution_order() {
+            self.run_pass(pass, &mut encoder, view);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.resolve(&mut encoder);
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(recorder) = &mut self.recorder {
+            recorder.submitted();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame();
+        }
+    }
+
+    // Lost and Outdated surfaces are reconfigured here, only OutOfMemory and Timeout are returned
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // No surface texture can be acquired while minimized
+        if self.surface.is_some() && (!self.is_visible() || !self.wait_for_next_frame()) {
+            return Ok(());
+        }
+        let Some(surface) = &self.surface else {
+            // Unless the surface is gone after `shutdown`
+            if self.offscreen_texture.is_some() {
+                self.render_headless();
+            }
+            return Ok(());
+        };
+        let output = match surface.get_current_texture() {
+            Ok(output) => output,
+            // Happens after a resize or when the window was minimized, the configuration
+            // still has the last valid size
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                surface.configure(&self.device, &self.config);
+                match surface.get_current_texture() {
+                    Ok(output) => output,
+                    Err(e @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                        log::warn!("Skipping frame, the surface is still unusable after reconfiguring: {:?}", e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.render_to_view(&view);
         output.present();
 
         Ok(())
     }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    // Caps how often `render` presents, e.g. to save power with PresentMode::Immediate.
+    // Animations keep their speed as `update` advances them by the wall clock time.
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.target_fps = fps.map(|fps| fps.max(1));
+        self.next_frame = Instant::now();
+    }
+
+    // Blocks until the next frame is due, false when the frame has to be skipped instead
+    fn wait_for_next_frame(&mut self) -> bool {
+        let Some(fps) = self.target_fps else {
+            return true;
+        };
+        let now = Instant::now();
+        if now < self.next_frame {
+            // The browser's event loop can't be blocked, the next redraw checks again. It has
+            // to be requested here, the event loop doesn't redraw an idle scene by itself.
+            if cfg!(target_arch = "wasm32") {
+                if let Some(window) = self.window {
+                    window.request_redraw();
+                }
+                return false;
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            std::thread::sleep(self.next_frame - now);
+        }
+        // Late frames move the schedule instead of being caught up on
+        self.next_frame = self.next_frame.max(now) + Duration::from_secs(1) / fps;
+        true
+    }
+
+    // Waits for the GPU to finish, unmaps the recording and profiling buffers and releases
+    // the surface before the window it was created for. Call it before dropping the State
+    // to avoid validation errors about work in flight on exit. With a window `render` does
+    // nothing after it.
+    pub fn shutdown(&mut self) {
+        self.device.poll(wgpu::Maintain::Wait);
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.release();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.release();
+        }
+        self.surface = None;
+    }
+
+    // Every rendered frame is also captured at the given size, without the overlays.
+    // Restarting with another size drops the frames not taken yet.
+    pub fn start_recording(&mut self, width: u32, height: u32) {
+        let width = width.clamp(1, self.max_texture_dimension);
+        let height = height.clamp(1, self.max_texture_dimension);
+        self.recorder = Some(Recorder::new(&self.device, &self.tone_mapper, self.config.format, width, height));
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Tightly packed RGBA rows of the newest recorded frame the GPU finished copying,
+    // None when there is none yet. Doesn't block, frames are dropped when not taken
+    // often enough.
+    pub fn take_frame(&mut self) -> Option<Vec<u8>> {
+        self.recorder.as_mut()?.take_frame(&self.device)
+    }
+
+    // Renders a frame into the offscreen texture and returns it, only available when headless
+    pub fn render_headless(&mut self) -> &wgpu::Texture {
+        let texture = self.offscreen_texture.take().expect("render_headless requires a headless State");
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.render_to_view(&view);
+        self.offscreen_texture.insert(texture)
+    }
+
+    // Renders a frame like `render_headless` and reads it back as tightly packed RGBA rows
+    pub fn render_headless_rgba(&mut self) -> Vec<u8> {
+        self.render_headless();
+        let texture = self.offscreen_texture.as_ref().unwrap();
+        read_texture_rgba(&self.device, &self.queue, texture)
+    }
+}
+
+// Copies a 4 bytes per pixel texture into a buffer and blocks until it is mapped
+fn read_texture_rgba(device: &Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Vec<u8> {
+    let width = texture.width();
+    let height = texture.height();
+    let unpadded_bytes_per_row = 4 * width;
+    // Buffer copies require rows aligned to 256 bytes
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        sender.send(result).unwrap();
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().unwrap().expect("Failed to map readback buffer");
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    for row in slice.get_mapped_range().chunks(padded_bytes_per_row as usize) {
+        pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+    }
+    buffer.unmap();
+    pixels
+}
+
+// Runs `create` with validation errors captured instead of reported to the uncaptured error handler
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+fn catch_validation_error<T>(device: &Device, create: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error),
+        None => Ok(value),
+    }
+}
+
+// Fully saturated color of `hue` in [0, 1), a little darkened
+fn hue_to_color(hue: f64) -> wgpu::Color {
+    const VALUE: f64 = 0.8;
+    let h = hue * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    wgpu::Color { r: r * VALUE, g: g * VALUE, b: b * VALUE, a: 1.0 }
 }
 
-fn position_to_color(p: &PhysicalPosition<f64>) -> wgpu::Color {
+fn position_to_color(p: &LogicalPosition<f64>) -> wgpu::Color {
     wgpu::Color {
         r: ((p.x * PI / 128.0).cos() + 1.0) / 2.0,
         g: ((p.y * PI / 128.0).sin() + 1.0) / 2.0,
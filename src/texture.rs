@@ -1,10 +1,129 @@
+use std::fmt;
+
 use image::GenericImageView;
-use anyhow::*;
+use wgpu::util::DeviceExt;
+use anyhow::Result;
+
+#[derive(Debug)]
+pub enum TextureError {
+    Decode(image::ImageError),
+    // The image is larger than the device's max_texture_dimension_2d
+    TooLarge { width: u32, height: u32, max: u32 },
+    // Cubemap faces have to be square and all of the size of the first one
+    CubemapFace { face: usize, width: u32, height: u32, size: u32 },
+    // Texture arrays need 1 to max_texture_array_layers layers
+    LayerCount { count: usize, max: u32 },
+    // Array layers have to be of the size of the first one
+    LayerSize { layer: usize, width: u32, height: u32, expected: (u32, u32) },
+    // Array layers have to decode to the color type of the first one
+    LayerFormat { layer: usize, format: image::ColorType, expected: image::ColorType },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Decode(e) => write!(f, "failed to decode image: {}", e),
+            TextureError::TooLarge { width, height, max } =>
+                write!(f, "image is {}x{}, at most {}x{} is supported", width, height, max, max),
+            TextureError::CubemapFace { face, width, height, size } =>
+                write!(f, "cubemap face {} is {}x{}, expected {}x{}", face, width, height, size, size),
+            TextureError::LayerCount { count, max } =>
+                write!(f, "texture array has {} layers, 1 to {} are supported", count, max),
+            TextureError::LayerSize { layer, width, height, expected } =>
+                write!(f, "texture array layer {} is {}x{}, expected {}x{}", layer, width, height, expected.0, expected.1),
+            TextureError::LayerFormat { layer, format, expected } =>
+                write!(f, "texture array layer {} is {:?}, expected {:?}", layer, format, expected),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TextureError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for TextureError {
+    fn from(value: image::ImageError) -> Self {
+        TextureError::Decode(value)
+    }
+}
+
+// How a Texture's sampler filters and what it does with tex coords outside 0..1
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SamplerConfig {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    // 1 to 16, values above 1 need all filters to be linear and override them
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerConfig {
+    // Clamped, nearest when minified, what textures without mipmaps get
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl SamplerConfig {
+    // Linear between and within the mip levels, what textures with mipmaps get
+    pub fn mipmapped() -> Self {
+        Self {
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        }
+    }
+
+    // Same address mode along every axis, e.g. Repeat for tiling
+    pub fn with_address_mode(self, mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode_u: mode,
+            address_mode_v: mode,
+            address_mode_w: mode,
+            ..self
+        }
+    }
+
+    pub(crate) fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        let anisotropy = self.anisotropy.clamp(1, 16);
+        let filter = |filter| if anisotropy > 1 { wgpu::FilterMode::Linear } else { filter };
+        device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: self.address_mode_u,
+                address_mode_v: self.address_mode_v,
+                address_mode_w: self.address_mode_w,
+                mag_filter: filter(self.mag_filter),
+                min_filter: filter(self.min_filter),
+                mipmap_filter: filter(self.mipmap_filter),
+                anisotropy_clamp: anisotropy,
+                ..Default::default()
+            }
+        )
+    }
+}
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    // Of the sampler, depth textures' samplers also have a compare function
+    pub(crate) sampler_config: SamplerConfig,
 }
 
 impl Texture {
@@ -15,7 +134,7 @@ impl Texture {
         label: &str
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Ok(Self::from_image(device, queue, &img, Some(label))?)
     }
 
     pub fn from_image(
@@ -23,63 +142,310 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>
+    ) -> Result<Self, TextureError> {
+        Self::create_from_image(device, queue, img, label, false, SamplerConfig::default())
+    }
+
+    // size x size texture with red increasing along U, green along V and an 8x8 checker on top,
+    // for spotting wrong tex_coords
+    pub fn uv_debug(device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> Result<Self, TextureError> {
+        const CELLS: u32 = 8;
+        let size = size.max(1);
+        let img = image::RgbaImage::from_fn(size, size, |x, y| {
+            let u = (x as f32 + 0.5) / size as f32;
+            let v = (y as f32 + 0.5) / size as f32;
+            let cell = (x * CELLS / size + y * CELLS / size) % 2;
+            let shade = if cell == 0 { 1.0 } else { 0.6 };
+            image::Rgba([(u * shade * 255.0) as u8, (v * shade * 255.0) as u8, (shade * 64.0) as u8, 255])
+        });
+        Self::from_image(device, queue, &image::DynamicImage::ImageRgba8(img), Some("uv_debug"))
+    }
+
+    // Magenta and black 8x8 checker standing in for textures that failed to load
+    pub fn missing(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        const SIZE: u32 = 64;
+        const CELL: u32 = SIZE / 8;
+        let img = image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            if (x / CELL + y / CELL).is_multiple_of(2) {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        Self::from_image(device, queue, &image::DynamicImage::ImageRgba8(img), Some("missing"))
+            .expect("the missing texture is smaller than any device's limit")
+    }
+
+    // Same as from_bytes, but with the full mip chain for less aliasing on distant surfaces
+    pub fn from_bytes_with_mipmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let img = image::load_from_memory(bytes)?;
+        Ok(Self::from_image_with_mipmaps(device, queue, &img, Some(label))?)
+    }
+
+    pub fn from_image_with_mipmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>
+    ) -> Result<Self, TextureError> {
+        Self::create_from_image(device, queue, img, label, true, SamplerConfig::mipmapped())
+    }
+
+    // Like from_image or from_image_with_mipmaps, with another sampler than theirs
+    pub fn from_image_with_sampler(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        mipmaps: bool,
+        sampler_config: SamplerConfig
+    ) -> Result<Self, TextureError> {
+        Self::create_from_image(device, queue, img, label, mipmaps, sampler_config)
+    }
+
+    // Mipmapped layers of a D2Array texture, e.g. for instances picking their layer with
+    // Instances::set_layer. All layers need the size and the color type of the first one.
+    pub fn array_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[image::DynamicImage],
+        label: Option<&str>
+    ) -> Result<Self, TextureError> {
+        let max = device.limits().max_texture_array_layers;
+        let Some(first) = layers.first().filter(|_| layers.len() <= max as usize) else {
+            return Err(TextureError::LayerCount { count: layers.len(), max });
+        };
+        for (layer, img) in layers.iter().enumerate() {
+            let (width, height) = img.dimensions();
+            if (width, height) != first.dimensions() {
+                return Err(TextureError::LayerSize { layer, width, height, expected: first.dimensions() });
+            }
+            if img.color() != first.color() {
+                return Err(TextureError::LayerFormat { layer, format: img.color(), expected: first.color() });
+            }
+        }
+        Self::create_from_layers(device, queue, layers, label, true, SamplerConfig::mipmapped())
+    }
+
+    fn create_from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        mipmaps: bool,
+        sampler_config: SamplerConfig
+    ) -> Result<Self, TextureError> {
+        Self::create_from_layers(device, queue, std::slice::from_ref(img), label, mipmaps, sampler_config)
+    }
+
+    // `layers` are all of the same size
+    fn create_from_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &[image::DynamicImage],
+        label: Option<&str>,
+        mipmaps: bool,
+        sampler_config: SamplerConfig
+    ) -> Result<Self, TextureError> {
+        let dimensions = layers[0].dimensions();
+        let max = device.limits().max_texture_dimension_2d;
+        if dimensions.0 > max || dimensions.1 > max {
+            return Err(TextureError::TooLarge { width: dimensions.0, height: dimensions.1, max });
+        }
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: layers.len() as u32,
         };
+        let mip_level_count = if mipmaps { size.max_mips(wgpu::TextureDimension::D2) } else { 1 };
+        // Copied from where the scene can't bind it as is, see State::scene_texture_view
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC;
+        if mipmaps {
+            // The smaller levels are rendered from the previous ones
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                usage,
                 view_formats: &[],
             }
         );
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        for (layer, img) in layers.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                },
+                &img.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * dimensions.0),
+                    rows_per_image: Some(dimensions.1),
+                },
+                wgpu::Extent3d { depth_or_array_layers: 1, ..size },
+            );
+        }
+        if mipmaps {
+            Self::generate_mipmaps(device, queue, &texture);
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
+        let sampler = sampler_config.create_sampler(device);
 
-        Ok(Self { texture, view, sampler })
+        Ok(Self { texture, view, sampler, sampler_config })
+    }
+
+    pub fn sampler_config(&self) -> SamplerConfig {
+        self.sampler_config
+    }
+
+    // Recreates only the sampler, the texture data is kept.
+    // Bind groups using the previous sampler have to be recreated.
+    pub fn set_sampler_config(&mut self, device: &wgpu::Device, config: SamplerConfig) {
+        self.sampler_config = config;
+        self.sampler = config.create_sampler(device);
+    }
+
+    pub fn anisotropy(&self) -> u16 {
+        self.sampler_config.anisotropy
+    }
+
+    // Recreates the sampler with up to `anisotropy` (1 to 16) samples along the direction a
+    // surface is viewed at, sharpening it at grazing angles. Values above 1 switch
+    // to linear filtering. The device ignores it without DownlevelFlags::ANISOTROPIC_FILTERING,
+    // e.g. on WebGL, see State::is_anisotropy_supported.
+    // Bind groups using the previous sampler have to be recreated.
+    pub fn set_anisotropy(&mut self, device: &wgpu::Device, anisotropy: u16) {
+        let config = SamplerConfig { anisotropy: anisotropy.clamp(1, 16), ..self.sampler_config };
+        self.set_sampler_config(device, config);
     }
 
+    // Fills every mip level after the first by rendering the previous level into it
+    // with a linear sampler, layer by layer. Odd sizes round down, the sampler averages
+    // across the seam.
+    fn generate_mipmaps(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/blit.wgsl").into()),
+        });
+        // GL binds views of a single layer as the whole texture, so arrays are sampled by layer index
+        let layered = texture.depth_or_array_layers() > 1;
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mipmap Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: if layered { "fs_layer" } else { "fs_main" },
+                compilation_options: Default::default(),
+                targets: &[Some(texture.format().into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for layer in 0..texture.depth_or_array_layers() {
+            let layer_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Mipmap Layer Buffer"),
+                contents: bytemuck::cast_slice(&[layer, 0, 0, 0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            for level in 1..texture.mip_level_count() {
+                let source = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mip View"),
+                    base_mip_level: level - 1,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                });
+                let target = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mip View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+                let source_entry = wgpu::BindGroupEntry {
+                    binding: if layered { 2 } else { 0 },
+                    resource: wgpu::BindingResource::TextureView(&source),
+                };
+                let sampler_entry = wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                };
+                let layer_entry = wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: layer_buffer.as_entire_binding(),
+                };
+                let entries = if layered { vec![source_entry, sampler_entry, layer_entry] } else { vec![source_entry, sampler_entry] };
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("mipmap_bind_group"),
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &entries,
+                });
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mipmap Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                render_pass.set_pipeline(&pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+
+    // Default format of the scene's depth buffer, the shadow map always uses it
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device,
+                                config: &wgpu::SurfaceConfiguration,
+                                sample_count: u32,
+                                format: wgpu::TextureFormat,
+                                label: &str) -> Self {
         let size = wgpu::Extent3d {
             width: config.width,
             height: config.height,
@@ -89,9 +455,9 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
                 | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
@@ -114,6 +480,89 @@ impl Texture {
             }
         );
 
-        Self { texture, view, sampler }
+        Self { texture, view, sampler, sampler_config: SamplerConfig { min_filter: wgpu::FilterMode::Linear, ..Default::default() } }
+    }
+}
+
+// Six square faces in the order +X, -X, +Y, -Y, +Z, -Z
+pub struct Cubemap {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl Cubemap {
+    pub fn from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [&[u8]; 6],
+        label: &str
+    ) -> Result<Self, TextureError> {
+        let images = faces.iter()
+            .map(|bytes| image::load_from_memory(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let size = images[0].width();
+        for (face, img) in images.iter().enumerate() {
+            let (width, height) = img.dimensions();
+            if width != size || height != size {
+                return Err(TextureError::CubemapFace { face, width, height, size });
+            }
+        }
+        let max = device.limits().max_texture_dimension_2d;
+        if size > max {
+            return Err(TextureError::TooLarge { width: size, height: size, max });
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (face, img) in images.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: face as u32 },
+                },
+                &img.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * size),
+                    rows_per_image: Some(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self { texture, view, sampler })
     }
 }
@@ -0,0 +1,258 @@
+use wgpu::util::DeviceExt;
+use wgpu::{Device, Queue, TextureFormat};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ToneMapOp {
+    // Colors above 1.0 are clipped
+    #[default]
+    None,
+    Reinhard,
+    // Filmic curve with more contrast than Reinhard
+    Aces,
+}
+
+// Lower bound of `set_gamma`
+const MIN_GAMMA: f32 = 0.1;
+// Of outputs without the hardware's sRGB encoding
+const DEFAULT_GAMMA: f32 = 2.2;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    op: u32,
+    exposure: f32,
+    gamma: f32,
+    // Uniform buffers are at least 16 bytes on WebGL
+    _padding: u32,
+}
+
+// The scene is rendered into a linear HDR texture, this pass scales it by the exposure,
+// tone maps it and gamma corrects it into the output. Non sRGB outputs default to a gamma
+// of 2.2, sRGB ones to 1.0 as the hardware encodes them.
+pub struct ToneMapper {
+    hdr_view: wgpu::TextureView,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    op: ToneMapOp,
+    exposure: f32,
+    gamma: f32,
+}
+
+impl ToneMapper {
+    pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+    pub fn new(device: &Device, output_format: TextureFormat, width: u32, height: u32) -> Self {
+        let op = ToneMapOp::default();
+        let exposure = 1.0;
+        let gamma = if output_format.is_srgb() { 1.0 } else { DEFAULT_GAMMA };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tone Map Params Buffer"),
+            contents: bytemuck::cast_slice(&[Self::params(op, exposure, gamma)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tonemap_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        // Read with textureLoad, so no filtering is needed
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let hdr_view = Self::create_hdr_view(device, width, height);
+        let bind_group = Self::create_bind_group(device, &layout, &hdr_view, &params_buffer);
+        let pipeline = Self::create_pipeline(device, output_format, &layout);
+        Self {
+            hdr_view,
+            layout,
+            bind_group,
+            params_buffer,
+            pipeline,
+            op,
+            exposure,
+            gamma,
+        }
+    }
+
+    fn params(op: ToneMapOp, exposure: f32, gamma: f32) -> ParamsUniform {
+        ParamsUniform {
+            op: match op {
+                ToneMapOp::None => 0,
+                ToneMapOp::Reinhard => 1,
+                ToneMapOp::Aces => 2,
+            },
+            exposure,
+            gamma,
+            _padding: 0,
+        }
+    }
+
+    fn write_params(&self, queue: &Queue) {
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[Self::params(self.op, self.exposure, self.gamma)]));
+    }
+
+    fn create_hdr_view(device: &Device, width: u32, height: u32) -> wgpu::TextureView {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("hdr_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_bind_group(device: &Device,
+                         layout: &wgpu::BindGroupLayout,
+                         hdr_view: &wgpu::TextureView,
+                         params_buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        })
+    }
+
+    fn create_pipeline(device: &Device, output_format: TextureFormat, layout: &wgpu::BindGroupLayout) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tone Map Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/tonemap.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tone Map Pipeline Layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tone Map Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Where the scene is rendered to
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.hdr_view
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.hdr_view = Self::create_hdr_view(device, width, height);
+        self.bind_group = Self::create_bind_group(device, &self.layout, &self.hdr_view, &self.params_buffer);
+    }
+
+    pub fn op(&self) -> ToneMapOp {
+        self.op
+    }
+
+    pub fn set_op(&mut self, queue: &Queue, op: ToneMapOp) {
+        self.op = op;
+        self.write_params(queue);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    // Negative exposures are clamped to 0
+    pub fn set_exposure(&mut self, queue: &Queue, exposure: f32) {
+        self.exposure = exposure.max(0.0);
+        self.write_params(queue);
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    // Clamped to at least MIN_GAMMA
+    pub fn set_gamma(&mut self, queue: &Queue, gamma: f32) {
+        self.gamma = gamma.max(MIN_GAMMA);
+        self.write_params(queue);
+    }
+
+    // For tone mapping into another output format, see `render_with`
+    pub fn create_pipeline_for(&self, device: &Device, output_format: TextureFormat) -> wgpu::RenderPipeline {
+        Self::create_pipeline(device, output_format, &self.layout)
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        self.render_with(encoder, output, &self.pipeline);
+    }
+
+    // `pipeline` comes from `create_pipeline_for` with the format of `output`, which
+    // has to match the output format's sRGB-ness for the gamma to fit
+    pub fn render_with(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView, pipeline: &wgpu::RenderPipeline) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone Map Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,93 @@
+// Helpers for the tests rendering with a headless State
+
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cgmath::{Matrix4, Point3, SquareMatrix};
+use webgpu_playground::{BackgroundMode, Instances, PassId, Settings, State, StateConfig, Viewpoint};
+
+// Fails the test where no adapter is available, a software one like llvmpipe will do
+pub fn headless_state(width: u32, height: u32, settings: Settings) -> State<'static> {
+    let mut state = pollster::block_on(State::headless_with_config(width, height, StateConfig { settings, ..Default::default() }))
+        .unwrap_or_else(|e| panic!("can't create a headless State, the render tests need an adapter: {}", e));
+    // Only the camera moves while paused, and only on input
+    state.set_paused(true);
+    state
+}
+
+// A black background and the camera looking at the origin from 4 units along +z
+pub fn single_cube_settings() -> Settings {
+    Settings {
+        background: BackgroundMode::Solid(wgpu::Color::BLACK),
+        camera: Viewpoint { eye: Point3::new(0.0, 0.0, 4.0), target: Point3::new(0.0, 0.0, 0.0) },
+        ..Default::default()
+    }
+}
+
+// One white cube at the origin, lit but without the depth view overlay
+pub fn lit_single_cube_state(width: u32, height: u32, settings: Settings) -> State<'static> {
+    let mut state = headless_state(width, height, settings);
+    state.render_graph_mut().set_enabled(PassId::DepthView, false);
+    state.load_texture(&solid_png([255; 4]), "white").unwrap();
+    state.set_instances(Instances::from_transforms(state.device(), &[Matrix4::identity()]));
+    state
+}
+
+// A 1x1 PNG, white ones show the instance colors and the lighting unchanged
+pub fn solid_png(rgba: [u8; 4]) -> Vec<u8> {
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)))
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    png
+}
+
+// Tightly packed RGBA rows of a frame rendered without advancing time
+pub fn render_to_rgba(state: &mut State) -> Vec<u8> {
+    state.update_with_dt(Duration::ZERO);
+    state.render_headless_rgba()
+}
+
+// The RGBA pixel at (x, y) from the top left
+pub fn pixel(rgba: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+    let i = ((y * width + x) * 4) as usize;
+    rgba[i..i + 4].try_into().unwrap()
+}
+
+// Pixels with a channel differing by more than `tolerance`
+pub fn count_mismatches(expected: &[u8], actual: &[u8], tolerance: u8) -> usize {
+    assert_eq!(expected.len(), actual.len(), "the images differ in size");
+    expected.chunks_exact(4)
+        .zip(actual.chunks_exact(4))
+        .filter(|(e, a)| e.iter().zip(a.iter()).any(|(e, a)| e.abs_diff(*a) > tolerance))
+        .count()
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.png", name))
+}
+
+// Compares with tests/golden/<name>.png, which is written instead when UPDATE_GOLDEN is
+// set. GPUs rasterize edges slightly differently, so up to
+// `max_mismatches` pixels may differ by more than `tolerance`. On failure the frame is
+// saved to the target directory for comparison.
+pub fn assert_golden(name: &str, width: u32, height: u32, rgba: &[u8], tolerance: u8, max_mismatches: usize) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::save_buffer(&path, rgba, width, height, image::ColorType::Rgba8).unwrap();
+        eprintln!("wrote {}", path.display());
+        return;
+    }
+    assert!(path.exists(), "{} is missing, rerun with UPDATE_GOLDEN=1 to write it", path.display());
+    let golden = image::open(&path).unwrap().to_rgba8();
+    assert_eq!(golden.dimensions(), (width, height), "{} has another size, rerun with UPDATE_GOLDEN=1", path.display());
+    let mismatches = count_mismatches(golden.as_raw(), rgba, tolerance);
+    if mismatches > max_mismatches {
+        let actual = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+        image::save_buffer(&actual, rgba, width, height, image::ColorType::Rgba8).unwrap();
+        panic!("{} pixels differ from {} by more than {}, the frame is at {}",
+               mismatches, path.display(), tolerance, actual.display());
+    }
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use cgmath::{Matrix4, Point3, Vector3};
+use webgpu_playground::{Instances, RenderMode, Settings, Viewpoint};
+
+use common::{assert_golden, headless_state, lit_single_cube_state, pixel, render_to_rgba, single_cube_settings, solid_png};
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 128;
+
+#[test]
+fn default_scene_matches_golden() {
+    let mut state = headless_state(WIDTH, HEIGHT, Settings::default());
+    let rgba = render_to_rgba(&mut state);
+    assert_golden("default_scene", WIDTH, HEIGHT, &rgba, 8, (WIDTH * HEIGHT / 100) as usize);
+}
+
+#[test]
+fn red_cube_fills_the_center() {
+    let mut state = lit_single_cube_state(WIDTH, HEIGHT, single_cube_settings());
+    state.instances_mut().set_color(0, [1.0, 0.0, 0.0, 1.0]);
+
+    let rgba = render_to_rgba(&mut state);
+    // The cube's front face covers about 40 pixels around the center
+    for y in HEIGHT / 2 - 12..HEIGHT / 2 + 12 {
+        for x in WIDTH / 2 - 12..WIDTH / 2 + 12 {
+            let [r, g, b, _] = pixel(&rgba, WIDTH, x, y);
+            // Lit red, with a little specular white
+            assert!(r > 128 && r as u16 > 4 * g.max(b) as u16, "({}, {}) is {:?}, not red", x, y, [r, g, b]);
+        }
+    }
+    for (x, y) in [(0, 0), (WIDTH - 1, 0), (0, HEIGHT - 1), (WIDTH - 1, HEIGHT - 1)] {
+        assert_eq!(pixel(&rgba, WIDTH, x, y), [0, 0, 0, 255], "({}, {}) isn't the background", x, y);
+    }
+}
+
+#[test]
+fn deterministic_frames_repeat() {
+    let mut state = headless_state(WIDTH, HEIGHT, Settings::default());
+    state.instances_mut().randomize_spins(7);
+    state.set_particles_enabled(true);
+    state.set_deterministic(90);
+    let frame = render_to_rgba(&mut state);
+    state.set_deterministic(30);
+    let earlier = render_to_rgba(&mut state);
+    assert_ne!(earlier, frame);
+    // The particles are replayed from the start
+    state.set_deterministic(90);
+    assert!(render_to_rgba(&mut state) == frame, "frame 90 differs after going back");
+}
+
+#[test]
+fn paused_scene_is_idle() {
+    let mut state = headless_state(WIDTH, HEIGHT, Settings::default());
+    assert_eq!(state.render_mode(), RenderMode::Idle);
+    state.set_paused(false);
+    assert_eq!(state.render_mode(), RenderMode::Continuous);
+}
+
+#[test]
+fn undecodable_texture_shows_the_checker() {
+    let mut state = lit_single_cube_state(WIDTH, HEIGHT, single_cube_settings());
+    state.load_texture(b"not an image", "corrupt").unwrap();
+
+    let rgba = render_to_rgba(&mut state);
+    let magenta = (HEIGHT / 2 - 12..HEIGHT / 2 + 12)
+        .flat_map(|y| (WIDTH / 2 - 12..WIDTH / 2 + 12).map(move |x| (x, y)))
+        .filter(|&(x, y)| {
+            let [r, g, b, _] = pixel(&rgba, WIDTH, x, y);
+            r > 128 && b > 128 && g < 64
+        })
+        .count();
+    assert!(magenta > 0, "no magenta on the cube");
+}
+
+#[test]
+fn frustum_culling_keeps_the_frame() {
+    let mut state = headless_state(WIDTH, HEIGHT, Settings::default());
+    let unculled = render_to_rgba(&mut state);
+    let all = state.last_draw_count();
+    assert_eq!(all, state.instances().count());
+
+    state.set_frustum_culling(true);
+    assert!(render_to_rgba(&mut state) == unculled, "culling changed the frame");
+    assert!(state.last_draw_count() < all, "nothing was culled");
+
+    state.set_frustum_culling(false);
+    render_to_rgba(&mut state);
+    assert_eq!(state.last_draw_count(), all);
+}
+
+#[test]
+fn frustum_culling_skips_instances_behind_the_camera() {
+    let settings = Settings {
+        camera: Viewpoint { eye: Point3::new(0.0, 1.0, 2.0), target: Point3::new(0.0, 1.0, 10.0) },
+        ..Default::default()
+    };
+    let mut state = headless_state(WIDTH, HEIGHT, settings);
+    state.set_frustum_culling(true);
+    render_to_rgba(&mut state);
+    assert_eq!(state.last_draw_count(), 0);
+}
+
+#[test]
+fn empty_instances_draw_nothing() {
+    let mut state = lit_single_cube_state(WIDTH, HEIGHT, single_cube_settings());
+    state.set_instances(Instances::from_transforms(state.device(), &[]));
+    assert_eq!(state.instances().count(), 0);
+
+    let rgba = render_to_rgba(&mut state);
+    assert_eq!(state.last_draw_count(), 0);
+    assert!(rgba.chunks_exact(4).all(|p| p == [0, 0, 0, 255]), "something was drawn");
+}
+
+#[test]
+fn shadows_darken_the_receiver() {
+    let settings = Settings {
+        camera: Viewpoint { eye: Point3::new(0.0, 6.0, 6.0), target: Point3::new(0.0, 0.0, 0.0) },
+        ..single_cube_settings()
+    };
+    let mut state = lit_single_cube_state(WIDTH, HEIGHT, settings);
+    // A wide slab with its top at y = 0 and a cube above it, the light falls
+    // sideways so the shadow lands next to the cube
+    let receiver = Matrix4::from_translation(Vector3::new(0.0, -0.5, 0.0)) * Matrix4::from_nonuniform_scale(12.0, 1.0, 12.0);
+    let caster = Matrix4::from_translation(Vector3::new(0.0, 1.5, 0.0));
+    state.set_light_direction(Vector3::new(1.0, -1.0, 0.0));
+    let darker = |lit: &[u8], shadowed: &[u8]| {
+        let luma = |p: &[u8]| p[0] as u32 + p[1] as u32 + p[2] as u32;
+        lit.chunks_exact(4).zip(shadowed.chunks_exact(4)).filter(|(l, s)| luma(s) + 60 < luma(l)).count()
+    };
+
+    // Without a caster the receiver doesn't shadow itself
+    state.set_instances(Instances::from_transforms(state.device(), &[receiver]));
+    let alone = render_to_rgba(&mut state);
+    state.set_shadows(true);
+    assert_eq!(darker(&alone, &render_to_rgba(&mut state)), 0, "the receiver shadows itself");
+
+    state.set_shadows(false);
+    state.set_instances(Instances::from_transforms(state.device(), &[receiver, caster]));
+    let unshadowed = render_to_rgba(&mut state);
+    state.set_shadows(true);
+    let shadowed = render_to_rgba(&mut state);
+    assert!(darker(&unshadowed, &shadowed) > 100, "the caster's shadow is missing");
+
+    state.set_shadows(false);
+    assert!(render_to_rgba(&mut state) == unshadowed, "the shadow stays after turning shadows off");
+}
+
+#[test]
+fn skybox_replaces_the_background() {
+    let mut state = lit_single_cube_state(WIDTH, HEIGHT, single_cube_settings());
+    let background = render_to_rgba(&mut state);
+
+    let blue = solid_png([0, 0, 255, 255]);
+    state.load_skybox([blue.as_slice(); 6]).unwrap();
+    let rgba = render_to_rgba(&mut state);
+    for (x, y) in [(0, 0), (WIDTH - 1, 0), (0, HEIGHT - 1), (WIDTH - 1, HEIGHT - 1)] {
+        let [r, g, b, _] = pixel(&rgba, WIDTH, x, y);
+        assert!(b > 128 && r < 32 && g < 32, "({}, {}) is {:?}, not the skybox", x, y, [r, g, b]);
+    }
+    // The cube stays in front of the sky
+    let center = (WIDTH / 2, HEIGHT / 2);
+    assert_eq!(pixel(&rgba, WIDTH, center.0, center.1), pixel(&background, WIDTH, center.0, center.1));
+
+    state.clear_skybox();
+    assert!(render_to_rgba(&mut state) == background, "the skybox stays after clearing it");
+}
+
+#[test]
+fn headless_zero_size_is_clamped() {
+    let mut state = headless_state(0, HEIGHT, Settings::default());
+    assert_eq!((state.size().width, state.size().height), (1, HEIGHT));
+    assert_eq!(render_to_rgba(&mut state).len(), 4 * HEIGHT as usize);
+}